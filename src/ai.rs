@@ -1,141 +1,1244 @@
-use crate::board::Board;
+use crate::board::{splitmix, Board};
+use crate::choss::WinCondition;
 use crate::piece::{Action, Color, Piece};
 use crate::pos::Pos;
-const MAX_DEPTH: i32 = -6;
+use bevy::utils::{Duration, Instant};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+// how many captures deep the search keeps following exchanges once out of depth, by default
+pub const QUIESCENCE_DEPTH: u32 = 6;
+// how much shallower the search goes after passing the turn
+const NULL_MOVE_REDUCTION: i32 = 2;
+// quiet moves after that many are searched shallower first, if there's enough depth left
+const LMR_MOVES: usize = 3;
+const LMR_DEPTH: i32 = 3;
+// the AI picks among the moves up to 3 points below the best one, they need an exact score
+const ASPIRATION_BELOW: f32 = 4.;
+const ASPIRATION_ABOVE: f32 = 1.;
+// forcing moves get searched one ply deeper, at most that many times per line
+const MAX_EXTENSIONS: i32 = 2;
+const CHECK_EXTENSIONS: bool = true;
+const RECAPTURE_EXTENSIONS: bool = true;
+// a timed search stops there even if it has time left, the scores don't change much that deep
+const MAX_TIMED_DEPTH: u32 = 32;
+// a timed search looks at the time once every that many nodes
+const CLOCK_NODES: u32 = 1024;
+// a table kept between searches starts over past that many positions
+const MAX_TABLE_ENTRIES: usize = 1 << 18;
 
-pub fn piece_value(piece: Piece) -> f32 {
-    match piece {
-        Piece::Pawn {
-            orientation: _,
-            status: _,
-        } => 1.,
-        Piece::Knight => 3.,
-        Piece::Bishop => 3.5,
-        Piece::Rook => 5.,
-        Piece::Queen => 9.,
-        Piece::King => 1000.,
+// a sequence of moves, starting with the side to play
+pub type Line = Vec<(Pos, Vec<Action>)>;
+// the root moves with their score and the line expected after them, best first
+pub type ScoredLines = Vec<(f32, Pos, Vec<Action>, Line)>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum NodeType {
+    Exact,
+    // the search was cut off, the score is at least that
+    LowerBound,
+    // no move reached alpha, the score is at most that
+    UpperBound,
+}
+
+struct TableEntry {
+    depth: i32,
+    score: f32,
+    node_type: NodeType,
+    pv: Line,
+}
+
+// what the stored scores depend on besides the position: the side contempt is for, and the search settings
+type TableRules = (Color, WinCondition, f32, PieceValues, u32);
+
+// the positions already searched, so a position reached by another move order isn't searched again,
+// it can be kept for the next search as long as the rules don't change
+#[derive(Default)]
+pub struct TranspositionTable {
+    entries: HashMap<u64, TableEntry>,
+    rules: Option<TableRules>,
+}
+
+impl TranspositionTable {
+    fn follow(&mut self, rules: TableRules) {
+        if self.rules != Some(rules) || self.entries.len() > MAX_TABLE_ENTRIES {
+            self.entries.clear();
+            self.rules = Some(rules);
+        }
+    }
+
+    fn key(board: &Board, color: Color, checks: [u32; 2]) -> u64 {
+        // the checks given count in some variants
+        board.position_hash(color) ^ splitmix(((checks[0] as u64) << 32) | checks[1] as u64)
+    }
+
+    fn probe(&self, key: u64, depth: i32, alpha: f32, beta: f32) -> Option<(f32, &Line)> {
+        // a shallower search isn't enough, and a bound only helps if it's outside the window
+        let entry = self
+            .entries
+            .get(&key)
+            .filter(|entry| entry.depth >= depth)?;
+        match entry.node_type {
+            NodeType::Exact => Some((entry.score, &entry.pv)),
+            NodeType::LowerBound if entry.score >= beta => Some((entry.score, &entry.pv)),
+            NodeType::UpperBound if entry.score <= alpha => Some((entry.score, &entry.pv)),
+            _ => None,
+        }
+    }
+
+    fn store(&mut self, key: u64, depth: i32, score: f32, (alpha, beta): (f32, f32), pv: &Line) {
+        if matches!(self.entries.get(&key), Some(entry) if entry.depth > depth) {
+            return;
+        }
+        let node_type = if score <= alpha {
+            NodeType::UpperBound
+        } else if score >= beta {
+            NodeType::LowerBound
+        } else {
+            NodeType::Exact
+        };
+        self.entries.insert(
+            key,
+            TableEntry {
+                depth,
+                score,
+                node_type,
+                pv: pv.clone(),
+            },
+        );
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Extensions {
+    // how many more plies this line can be extended by
+    left: i32,
+    // the square the previous move took on, if it was a capture
+    last_capture: Option<Pos>,
+}
+
+impl Extensions {
+    fn new(last_capture: Option<Pos>) -> Self {
+        Extensions {
+            left: MAX_EXTENSIONS,
+            last_capture,
+        }
+    }
+}
+
+// tells a search when to stop, without reading the time or the stop flag at every node
+#[derive(Default)]
+struct Clock {
+    deadline: Option<Instant>,
+    // set from another thread to call the search off
+    stop: Option<Arc<AtomicBool>>,
+    nodes: u32,
+    // once the time is up the search unwinds, and the scores it returns mean nothing
+    expired: bool,
+}
+
+impl Clock {
+    fn tick(&mut self) -> bool {
+        if self.deadline.is_some() || self.stop.is_some() {
+            self.nodes += 1;
+            if self.nodes >= CLOCK_NODES {
+                self.nodes = 0;
+                self.expired = self.is_up();
+            }
+        }
+        self.expired
+    }
+
+    fn is_up(&self) -> bool {
+        matches!(self.deadline, Some(deadline) if Instant::now() >= deadline)
+            || matches!(&self.stop, Some(stop) if stop.load(Ordering::Relaxed))
+    }
+}
+
+fn capture_square(board: &Board, color: Color, actions: &[Action]) -> Option<Pos> {
+    actions.iter().find_map(|action| match *action {
+        Action::Go(pos) if board.is_capture(color, &[Action::Go(pos)]) => Some(pos),
+        Action::Take(pos) => Some(pos),
+        _ => None,
+    })
+}
+
+// taking the king ends the game, it's worth more than everything else together
+pub const KING_VALUE: f32 = 1000.;
+
+// what each piece is worth in the eyes of the AI, in pawns
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PieceValues {
+    pub pawn: f32,
+    pub knight: f32,
+    pub bishop: f32,
+    pub rook: f32,
+    pub queen: f32,
+}
+
+impl PieceValues {
+    pub fn classical() -> Self {
+        PieceValues {
+            pawn: 1.,
+            knight: 3.,
+            bishop: 3.5,
+            rook: 5.,
+            queen: 9.,
+        }
+    }
+
+    pub fn kaufman() -> Self {
+        // Larry Kaufman's values, from his study of master games
+        PieceValues {
+            pawn: 1.,
+            knight: 3.25,
+            bishop: 3.25,
+            rook: 5.,
+            queen: 9.75,
+        }
+    }
+
+    pub fn value(&self, piece: Piece) -> f32 {
+        match piece {
+            Piece::Pawn {
+                orientation: _,
+                status: _,
+            } => self.pawn,
+            Piece::Knight => self.knight,
+            Piece::Bishop => self.bishop,
+            Piece::Rook => self.rook,
+            Piece::Queen => self.queen,
+            Piece::King => KING_VALUE,
+        }
+    }
+}
+
+impl Default for PieceValues {
+    fn default() -> Self {
+        PieceValues::classical()
     }
 }
 
-fn move_value(board: &Board, pos: Pos, actions: &Vec<Action>) -> f32 {
-    // compute the material value of a move, *assuming that if the moves win any material the piece is lost at 90%*
-    let (color, piece) = board.get(pos).unwrap().unwrap();
+fn material_gain(board: &Board, color: Color, actions: &[Action], values: &PieceValues) -> f32 {
+    // the material a move wins right away, without looking at what follows
     let mut value = 0.;
     for action in actions {
         match *action {
             Action::Go(go_pos) => {
                 if let Some(Some((o_color, o_piece))) = board.get(go_pos) {
-                    value += piece_value(*o_piece) * if *o_color == color { -1. } else { 1. };
+                    value += values.value(*o_piece) * if *o_color == color { -1. } else { 1. };
                 }
             }
             Action::Take(take_pos) => {
                 if let Some(Some((o_color, o_piece))) = board.get(take_pos) {
-                    value += piece_value(*o_piece) * if *o_color == color { -1. } else { 1. };
+                    value += values.value(*o_piece) * if *o_color == color { -1. } else { 1. };
                 }
             }
             Action::Promotion(n_piece) => {
-                value += piece_value(n_piece);
+                value += values.value(n_piece);
             }
+            Action::Castle(_, _) => {}
         }
     }
-    if value > 0. {
-        value -= piece_value(piece) * 0.9;
-    }
     value
 }
 
-fn mat_score(board: &Board) -> f32 {
-    board
-        .squares
+fn see(board: &Board, pos: Pos, actions: &[Action], values: &PieceValues) -> f32 {
+    // static exchange evaluation: what a capture wins once both sides are done
+    // recapturing on the square, each with their least valuable piece first
+    let (color, _) = board.get(pos).unwrap().unwrap();
+    let target = actions
         .iter()
-        .map(|square| {
-            if let Some((color, piece)) = square {
-                piece_value(*piece) * if *color == Color::White { 1. } else { -1. }
-            } else {
-                0.
-            }
+        .rev()
+        .find_map(|action| match action {
+            Action::Go(go_pos) => Some(*go_pos),
+            _ => None,
         })
+        .unwrap_or(pos);
+    let next_board = board.play(color, pos, actions);
+    material_gain(board, color, actions, values)
+        - recapture(&next_board, color.next(), target, values)
+}
+
+fn recapture(board: &Board, color: Color, target: Pos, values: &PieceValues) -> f32 {
+    // the best this side can get by recapturing on target, it can always choose not to
+    board
+        .takes(color, false)
+        .into_iter()
+        .filter(|(_, actions)| actions.contains(&Action::Go(target)))
+        .map(|(pos, actions)| {
+            let (_, piece) = board.get(pos).unwrap().unwrap();
+            (values.value(piece), pos, actions)
+        })
+        .min_by(|(value1, _, _), (value2, _, _)| value1.partial_cmp(value2).unwrap())
+        .map_or(0., |(_, pos, actions)| {
+            see(board, pos, &actions, values).max(0.)
+        })
+}
+
+fn move_value(board: &Board, pos: Pos, actions: &[Action], values: &PieceValues) -> f32 {
+    // compute the material value of a move, captures account for the exchanges that follow
+    let (color, _) = board.get(pos).unwrap().unwrap();
+    let value = if board.is_capture(color, actions) {
+        see(board, pos, actions, values)
+    } else {
+        material_gain(board, color, actions, values)
+    };
+    value + check_bonus(&board.play(color, pos, actions), color)
+}
+
+fn check_bonus(board: &Board, color: Color) -> f32 {
+    // checks are forcing, a double check even more so, look at them early
+    // (this is only for move ordering, the eval doesn't care)
+    let king_pos = match board.king_pos(color.next()) {
+        Some(king_pos) => king_pos,
+        None => return 0.,
+    };
+    let checkers = board
+        .pieces_of(color)
+        .filter(|(pos, piece)| piece.attacks(board, *pos).contains(&king_pos))
+        .count();
+    match checkers {
+        0 => 0.,
+        1 => 0.5,
+        _ => 1.5,
+    }
+}
+
+fn valued_moves(
+    board: &Board,
+    moves: Vec<(Pos, Vec<Action>)>,
+    values: &PieceValues,
+) -> Vec<(f32, Pos, Vec<Action>)> {
+    // sort the moves with move_value heuristic, computed once per move
+    let mut valued: Vec<_> = moves
+        .into_iter()
+        .map(|(pos, actions)| (move_value(board, pos, &actions, values), pos, actions))
+        .collect();
+    valued.sort_by(|(value1, _, _), (value2, _, _)| value2.partial_cmp(value1).unwrap());
+    valued
+}
+
+fn order_moves(
+    board: &Board,
+    moves: Vec<(Pos, Vec<Action>)>,
+    values: &PieceValues,
+) -> Vec<(Pos, Vec<Action>)> {
+    valued_moves(board, moves, values)
+        .into_iter()
+        .map(|(_, pos, actions)| (pos, actions))
+        .collect()
+}
+
+fn officers_value(board: &Board, color: Color, values: &PieceValues) -> f32 {
+    // material of a side without its pawns and king
+    board
+        .pieces_of(color)
+        .filter(|(_, piece)| !matches!(piece, Piece::Pawn { .. } | Piece::King))
+        .map(|(_, piece)| values.value(piece))
+        .sum()
+}
+
+fn mat_score(board: &Board, values: &PieceValues) -> f32 {
+    board
+        .pieces()
+        .map(|(_, color, piece)| values.value(piece) * if color == Color::White { 1. } else { -1. })
         .fold(0., |a, b| a + b)
 }
 
-fn _negamax(board: &Board, depth: i32, mut alpha: f32, beta: f32, color: Color) -> f32 {
-    let mut moves;
-    if depth <= MAX_DEPTH {
-        return mat_score(board) * if color == Color::White { 1. } else { -1. };
+// what the nodes of a search share: the rules and style it plays by, and what it found so far
+pub struct Search<'a> {
+    // the side to play at the root, the contempt is its own
+    color: Color,
+    win_condition: WinCondition,
+    contempt: f32,
+    values: PieceValues,
+    quiescence: u32,
+    table: &'a mut TranspositionTable,
+    clock: Clock,
+    // the positions since the last capture or pawn move, in the game then in the search
+    history: Vec<u64>,
+}
+
+impl<'a> Search<'a> {
+    pub fn new(
+        color: Color,
+        win_condition: WinCondition,
+        contempt: f32,
+        values: &PieceValues,
+        quiescence: u32,
+        history: &[u64],
+        table: &'a mut TranspositionTable,
+    ) -> Self {
+        // what the table holds from another search is only worth something under the same rules
+        table.follow((color, win_condition, contempt, *values, quiescence));
+        Search {
+            color,
+            win_condition,
+            contempt,
+            values: *values,
+            quiescence,
+            table,
+            clock: Clock::default(),
+            history: history.to_vec(),
+        }
+    }
+
+    pub fn stop_on(mut self, stop: Arc<AtomicBool>) -> Self {
+        // the search gives up soon after stop is set, and returns nothing worth using
+        self.clock.stop = Some(stop);
+        self
+    }
+
+    fn contempt(&self, color: Color) -> f32 {
+        // how much this side dislikes a draw
+        if color == self.color {
+            self.contempt
+        } else {
+            -self.contempt
+        }
+    }
+}
+
+fn _negamax(
+    search: &mut Search,
+    board: &Board,
+    mut depth: i32,
+    (mut alpha, beta): (f32, f32),
+    color: Color,
+    checks: [u32; 2],
+    mut extensions: Extensions,
+) -> (f32, Line) {
+    // returns the score for the side to play and the line the search expects from here
+    let moves;
+    let win_condition = search.win_condition;
+    let values = search.values;
+    let contempt = search.contempt(color);
+    if search.clock.tick() {
+        return (0., Vec::new());
+    }
+    if let Some(winner) = win_condition.winner(board, checks) {
+        // a variant win is as good as taking the king
+        return (
+            KING_VALUE * if winner == color { 1. } else { -1. },
+            Vec::new(),
+        );
+    }
+    if board.king_pos(color).is_none() && board.king_pos(color.next()).is_some() {
+        // the king was just taken, the game is over and taking the other king back doesn't count
+        return (-KING_VALUE, Vec::new());
+    }
+    if win_condition != WinCondition::KingOfTheHill && board.has_insufficient_material() {
+        // nobody can win from here, no need to look further
+        return (-contempt, Vec::new());
+    }
+    let position = board.position_hash(color);
+    if search.history.contains(&position) {
+        // going back to a position is a draw, the other side can just repeat it
+        return (-contempt, Vec::new());
+    }
+    // the captures searched out of depth aren't worth storing
+    let (key, window, stored_depth) = (
+        TranspositionTable::key(board, color, checks),
+        (alpha, beta),
+        depth,
+    );
+    if depth > 0 {
+        if let Some((score, line)) = search.table.probe(key, depth, alpha, beta) {
+            return (score, line.clone());
+        }
+    }
+    if CHECK_EXTENSIONS && depth > 0 && extensions.left > 0 && board.is_checked(color) {
+        // a check is forcing, search the replies one ply deeper to see where it leads
+        depth += 1;
+        extensions.left -= 1;
+    }
+    if depth <= -(search.quiescence as i32) {
+        return (
+            mat_score(board, &values) * if color == Color::White { 1. } else { -1. },
+            Vec::new(),
+        );
     } else if depth <= 0 {
-        // if we're out of depth, only explore taking moves
-        moves = board.takes(color, false);
+        // if we're out of depth, only explore taking moves, and not the ones that lose material
+        moves = valued_moves(board, board.takes(color, false), &values)
+            .into_iter()
+            .filter(|(value, _, _)| *value >= 0.)
+            .map(|(_, pos, actions)| (pos, actions))
+            .collect();
     } else {
-        moves = board.moves(color, false);
-    }
-    // sort the moves with move_value heuristic
-    moves.sort_by(|(pos1, actions1), (pos2, actions2)| {
-        move_value(board, *pos2, actions2)
-            .partial_cmp(&move_value(board, *pos1, actions1))
-            .unwrap()
-    });
+        if depth >= NULL_MOVE_REDUCTION
+            && beta.is_finite()
+            && officers_value(board, color, &values) >= values.rook
+            && !board.is_checked(color)
+        {
+            // null move: if passing the turn is still too good for the opponent to allow, so is any real move.
+            // With only pawns and a few pieces left passing might be the best move (zugzwang) so we don't try it
+            // passing isn't a move, what follows can't repeat the game
+            let history = std::mem::take(&mut search.history);
+            let (score, _) = _negamax(
+                search,
+                board,
+                depth - 1 - NULL_MOVE_REDUCTION,
+                (-beta, -beta + 0.01),
+                color.next(),
+                checks,
+                Extensions {
+                    last_capture: None,
+                    ..extensions
+                },
+            );
+            search.history = history;
+            if search.clock.expired {
+                return (0., Vec::new());
+            }
+            if -score >= beta {
+                return (beta, Vec::new());
+            }
+        }
+        moves = order_moves(board, board.moves(color, false), &values);
+    }
     let mut best_score = f32::NEG_INFINITY;
+    let mut pv = Vec::new();
 
-    for (pos, actions) in moves {
-        best_score = f32::max(
-            best_score,
-            -_negamax(
-                &board.play(color, pos, &actions),
-                depth - 1,
-                -beta,
-                -alpha,
+    for (i, (pos, actions)) in moves.into_iter().enumerate() {
+        let next_board = board.play(color, pos, &actions);
+        let mut next_checks = checks;
+        win_condition.record_check(&next_board, color, &mut next_checks);
+        let capture = capture_square(board, color, &actions);
+        let mut next_depth = depth - 1;
+        let mut next_extensions = Extensions {
+            last_capture: capture,
+            ..extensions
+        };
+        if RECAPTURE_EXTENSIONS
+            && depth > 0
+            && extensions.left > 0
+            && capture.is_some()
+            && capture == extensions.last_capture
+        {
+            // taking back is forced too, see how the exchange ends
+            next_depth += 1;
+            next_extensions.left -= 1;
+        }
+        let reduce = depth >= LMR_DEPTH
+            && i >= LMR_MOVES
+            && alpha.is_finite()
+            && capture.is_none()
+            && !actions
+                .iter()
+                .any(|action| matches!(action, Action::Promotion(_)))
+            && !next_board.is_checked(color.next());
+        let (mut score, mut next_pv) = (f32::NEG_INFINITY, Vec::new());
+        search.history.push(position);
+        if reduce {
+            // the move ordering puts the good moves first, so a late quiet move only needs
+            // a shallow search to show it doesn't beat alpha
+            let (reduced_score, reduced_pv) = _negamax(
+                search,
+                &next_board,
+                next_depth - 1,
+                (-alpha - 0.01, -alpha),
                 color.next(),
-            ),
-        );
+                next_checks,
+                next_extensions,
+            );
+            score = -reduced_score;
+            next_pv = reduced_pv;
+        }
+        if !reduce || score > alpha {
+            let (full_score, full_pv) = _negamax(
+                search,
+                &next_board,
+                next_depth,
+                (-beta, -alpha),
+                color.next(),
+                next_checks,
+                next_extensions,
+            );
+            score = -full_score;
+            next_pv = full_pv;
+        }
+        search.history.pop();
+        if search.clock.expired {
+            // the score is made up, it must not reach the table
+            return (0., Vec::new());
+        }
+        if score > best_score {
+            // remember the line that got us this score
+            best_score = score;
+            pv.clear();
+            pv.push((pos, actions));
+            pv.append(&mut next_pv);
+        }
         alpha = f32::max(alpha, best_score);
         if alpha >= beta {
-            return alpha;
+            if stored_depth > 0 {
+                search.table.store(key, stored_depth, alpha, window, &pv);
+            }
+            return (alpha, pv);
         }
     }
     if depth <= 0 {
         // if we're out of depth, consider that the score can't be worse than current board eval
-        best_score.max(mat_score(board) * if color == Color::White { 1. } else { -1. })
+        return (
+            best_score
+                .max(mat_score(board, &values) * if color == Color::White { 1. } else { -1. }),
+            pv,
+        );
+    }
+    let score = if best_score <= -KING_VALUE / 2.
+        && !board.is_checked(color)
+        && board.moves(color, true).is_empty()
+    {
+        // every move loses the king but we're not in check: stalemate
+        -contempt
     } else {
         best_score
-    }
+    };
+    search.table.store(key, stored_depth, score, window, &pv);
+    (score, pv)
+}
+
+#[cfg(test)]
+pub fn negamax(
+    board: &Board,
+    color: Color,
+    depth: u32,
+    win_condition: WinCondition,
+    checks: [u32; 2],
+    contempt: f32,
+    values: &PieceValues,
+    quiescence: u32,
+) -> Vec<(f32, Pos, Vec<Action>)> {
+    let mut table = TranspositionTable::default();
+    negamax_pv(
+        Search::new(
+            color,
+            win_condition,
+            contempt,
+            values,
+            quiescence,
+            &[],
+            &mut table,
+        ),
+        board,
+        depth,
+        checks,
+    )
+    .into_iter()
+    .map(|(score, pos, actions, _)| (score, pos, actions))
+    .collect()
+}
+
+pub fn negamax_pv(search: Search, board: &Board, depth: u32, checks: [u32; 2]) -> ScoredLines {
+    // same as negamax, but every root move also comes with the line the search expects after it
+    // a positive contempt makes the engine score draws below 0 and play on in equal positions,
+    // going back to one of the positions in the search history included
+    // with a single legal move there's nothing to choose, a shallow search is enough to score it
+    let only_move = board.moves(search.color, true).len() == 1;
+    deepen(
+        search,
+        board,
+        if only_move { 1 } else { depth },
+        checks,
+        true,
+        None,
+    )
+    .0
+}
+
+pub fn negamax_timed_pv(
+    search: Search,
+    board: &Board,
+    max_millis: u64,
+    checks: [u32; 2],
+) -> (ScoredLines, u32) {
+    // same as negamax_pv, but goes one depth deeper at a time until the time is up,
+    // and also returns the last depth it finished
+    let only_move = board.moves(search.color, true).len() == 1;
+    deepen(
+        search,
+        board,
+        if only_move { 1 } else { MAX_TIMED_DEPTH },
+        checks,
+        true,
+        Some(Instant::now() + Duration::from_millis(max_millis)),
+    )
 }
 
-pub fn negamax(board: &Board, color: Color, depth: u32) -> Vec<(f32, Pos, Vec<Action>)> {
-    println!("{}", board);
-    let mut moves = board.moves(color, true);
-    // sort the moves with move_value heuristic
-    moves.sort_by(|(pos1, actions1), (pos2, actions2)| {
-        move_value(board, *pos2, actions2)
-            .partial_cmp(&move_value(board, *pos1, actions1))
-            .unwrap()
-    });
-    let mut res = Vec::new();
-    for (pos, actions) in moves {
-        let curr_board = board.play(color, pos, &actions);
-        let mut score = -_negamax(
+fn deepen(
+    mut search: Search,
+    board: &Board,
+    depth: u32,
+    checks: [u32; 2],
+    aspiration: bool,
+    deadline: Option<Instant>,
+) -> (ScoredLines, u32) {
+    // the search is shared by the root moves and the depths, the positions repeat a lot between them
+    let (color, win_condition, contempt, values) = (
+        search.color,
+        search.win_condition,
+        search.contempt,
+        search.values,
+    );
+    // the root position counts even if the game doesn't have it yet
+    search.history.push(board.position_hash(color));
+    // returns the score the search gives to a root move in the (alpha, beta) window,
+    // and the score once adjusted for mobility and game endings
+    // None if the time ran out during the search
+    let mut search_move = |depth: u32, pos: Pos, actions: &[Action], window: (f32, f32)| {
+        // the first depth always finishes unless the search is called off,
+        // a later one is dropped as soon as the deadline passes
+        search.clock.deadline = deadline.filter(|_| depth > 1);
+        if search.clock.is_up() {
+            return None;
+        }
+        let curr_board = board.play(color, pos, actions);
+        let mut curr_checks = checks;
+        win_condition.record_check(&curr_board, color, &mut curr_checks);
+        let own_moves = curr_board.moves(color, false).len() as f32;
+        let op_moves = curr_board.moves(color.next(), true).len() as f32;
+        // a forced reply costs nothing to search, so the line is followed one ply further
+        let depth = if op_moves == 1. { depth + 1 } else { depth };
+        let (score, pv) = _negamax(
+            &mut search,
             &curr_board,
             depth as i32 - 1,
-            f32::NEG_INFINITY,
-            f32::INFINITY,
+            (-window.1, -window.0),
             color.next(),
+            curr_checks,
+            Extensions::new(capture_square(board, color, actions)),
         );
+        let score = -score;
+        if search.clock.expired {
+            return None;
+        }
         // compute an auxiliary score based on how many safe moves are available for both player in the next position
-        let own_moves = curr_board.moves(color, false).len() as f32;
-        let op_moves = curr_board.moves(color.next(), true).len() as f32;
-        if op_moves == 0. {
+        let adjusted = if win_condition.winner(&curr_board, curr_checks) == Some(color) {
+            f32::INFINITY
+        } else if op_moves == 0. {
             // if the opponent has no legal move it is either a draw or a win
             if curr_board.is_checked(color.next()) {
-                score = f32::INFINITY;
+                f32::INFINITY
             } else {
-                score = 0.;
+                -contempt
             }
         } else {
             // else, the score is raised if the position has more moves for the player and less for the opponent
             // cannot exceed the value of a pawn
-            score += (own_moves / 100. - op_moves / 100.).min(1.);
+            score + (own_moves / 100. - op_moves / 100.).min(1.)
+        };
+        Some((score, adjusted, pv))
+    };
+    let mut moves = order_moves(board, board.moves(color, true), &values);
+    let mut res: Vec<(f32, Pos, Vec<Action>, Line)> = Vec::new();
+    let mut reached = 0;
+    // iterative deepening: each depth goes through the moves in the order the previous one ranked them,
+    // and only expects scores close to its best one (aspiration window)
+    'deepening: for curr_depth in 1..=depth.max(1) {
+        let window = match res.first() {
+            Some((best, _, _, _)) if aspiration && best.is_finite() => {
+                (best - ASPIRATION_BELOW, best + ASPIRATION_ABOVE)
+            }
+            _ => (f32::NEG_INFINITY, f32::INFINITY),
+        };
+        let mut scored = Vec::new();
+        for (pos, actions) in moves.iter() {
+            let (mut score, mut adjusted, mut pv) =
+                match search_move(curr_depth, *pos, actions, window) {
+                    Some(searched) => searched,
+                    None => break 'deepening,
+                };
+            if score >= window.1 {
+                // fail high, the move might be even better than that
+                match search_move(curr_depth, *pos, actions, (window.0, f32::INFINITY)) {
+                    Some((new_score, new_adjusted, new_pv)) => {
+                        score = new_score;
+                        adjusted = new_adjusted;
+                        pv = new_pv;
+                    }
+                    None => break 'deepening,
+                }
+            }
+            scored.push((score, adjusted, *pos, actions.clone(), pv));
+        }
+        if scored.iter().all(|(score, _, _, _, _)| *score <= window.0) {
+            // fail low everywhere, all we know is that every move is worse than expected
+            scored.clear();
+            for (pos, actions) in moves.iter() {
+                match search_move(curr_depth, *pos, actions, (f32::NEG_INFINITY, window.1)) {
+                    Some((score, adjusted, pv)) => {
+                        scored.push((score, adjusted, *pos, actions.clone(), pv))
+                    }
+                    None => break 'deepening,
+                }
+            }
+        }
+        res = scored
+            .into_iter()
+            .map(|(_, adjusted, pos, actions, pv)| (adjusted, pos, actions, pv))
+            .collect();
+        res.sort_by(|(score1, _, _, _), (score2, _, _, _)| score2.partial_cmp(score1).unwrap());
+        moves = res
+            .iter()
+            .map(|(_, pos, actions, _)| (*pos, actions.clone()))
+            .collect();
+        reached = curr_depth;
+    }
+    (res, reached)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contempt_avoids_stalemate() {
+        // a pawn down, b5-b6 stalemates black
+        let board = Board::from_fen("k1K5/7p/7p/1P5p/7P/8/8/8 w").unwrap();
+        let stalemates = |contempt| {
+            let (_, pos, actions) = negamax(
+                &board,
+                Color::White,
+                2,
+                WinCondition::Checkmate,
+                [0; 2],
+                contempt,
+                &PieceValues::classical(),
+                QUIESCENCE_DEPTH,
+            )
+            .remove(0);
+            pos == Pos(1, 3) && actions == vec![Action::Go(Pos(1, 2))]
+        };
+        assert!(stalemates(0.));
+        assert!(!stalemates(3.));
+    }
+
+    #[test]
+    fn contempt_avoids_repetition() {
+        // two pawns down, and the game already went through the position after Kb2
+        let board = Board::from_fen("k7/7p/7p/7p/7P/8/8/K7 w").unwrap();
+        let king_up = [Action::Go(Pos(1, 6))];
+        let history = [
+            board
+                .play(Color::White, Pos(0, 7), &king_up)
+                .position_hash(Color::Black),
+            board.position_hash(Color::White),
+        ];
+        let repeats = |contempt| {
+            let (_, pos, actions, _) = negamax_pv(
+                Search::new(
+                    Color::White,
+                    WinCondition::Checkmate,
+                    contempt,
+                    &PieceValues::classical(),
+                    QUIESCENCE_DEPTH,
+                    &history,
+                    &mut TranspositionTable::default(),
+                ),
+                &board,
+                2,
+                [0; 2],
+            )
+            .remove(0);
+            pos == Pos(0, 7) && actions == king_up
+        };
+        assert!(repeats(0.));
+        assert!(!repeats(5.));
+    }
+
+    #[test]
+    fn bare_kings_are_a_draw() {
+        let board = Board::from_fen("k4/5/5/5/5/5/5/4K w").unwrap();
+        for depth in [-(QUIESCENCE_DEPTH as i32), 0, 1, 4] {
+            let (score, _) = _negamax(
+                &mut Search::new(
+                    Color::White,
+                    WinCondition::Checkmate,
+                    0.5,
+                    &PieceValues::classical(),
+                    QUIESCENCE_DEPTH,
+                    &[],
+                    &mut TranspositionTable::default(),
+                ),
+                &board,
+                depth,
+                (f32::NEG_INFINITY, f32::INFINITY),
+                Color::White,
+                [0; 2],
+                Extensions::new(None),
+            );
+            assert_eq!(score, -0.5);
+        }
+    }
+
+    #[test]
+    fn checks_are_searched_first() {
+        // moving the knight away uncovers the rook
+        let board = Board::from_fen("k4/5/5/5/N4/5/5/R3K w").unwrap();
+        let gives_check = |(pos, actions): &(Pos, Vec<Action>)| {
+            board
+                .play(Color::White, *pos, actions)
+                .is_checked(Color::Black)
+        };
+        let moves = order_moves(
+            &board,
+            board.moves(Color::White, true),
+            &PieceValues::classical(),
+        );
+        assert_eq!(moves[0].0, Pos(0, 4));
+        let checks = moves.iter().filter(|m| gives_check(m)).count();
+        assert_eq!(checks, 4);
+        assert!(moves[..checks].iter().all(gives_check));
+    }
+
+    #[test]
+    fn exchange_signs() {
+        // the pawn is defended, the knight isn't
+        let board = Board::from_fen("k4/1p3/2p2/5/2Q1n/5/5/4K w").unwrap();
+        assert_eq!(
+            see(
+                &board,
+                Pos(2, 4),
+                &[Action::Go(Pos(2, 2))],
+                &PieceValues::classical()
+            ),
+            1. - 9.
+        );
+        assert_eq!(
+            see(
+                &board,
+                Pos(2, 4),
+                &[Action::Go(Pos(4, 4))],
+                &PieceValues::classical()
+            ),
+            3.
+        );
+        // so taking the knight comes first
+        let moves = order_moves(
+            &board,
+            board.takes(Color::White, false),
+            &PieceValues::classical(),
+        );
+        assert_eq!(moves[0], (Pos(2, 4), vec![Action::Go(Pos(4, 4))]));
+    }
+
+    #[test]
+    fn values_change_the_order() {
+        // Bxb6 gives the bishop for the knight the pawn defends
+        let board = Board::from_fen("4k/p4/1n3/5/3B1/5/5/K4 w").unwrap();
+        let trade = (Pos(3, 4), vec![Action::Go(Pos(1, 2))]);
+        let retreat = (Pos(3, 4), vec![Action::Go(Pos(4, 5))]);
+        let rank = |values: &PieceValues, wanted: &(Pos, Vec<Action>)| {
+            order_moves(&board, board.moves(Color::White, true), values)
+                .iter()
+                .position(|m| m == wanted)
+                .unwrap()
+        };
+        let classical = PieceValues::classical();
+        assert!(rank(&classical, &trade) > rank(&classical, &retreat));
+        // once knights are worth more than bishops the trade is a win
+        let knights = PieceValues {
+            knight: 3.5,
+            bishop: 3.,
+            ..classical
+        };
+        assert!(rank(&knights, &trade) < rank(&knights, &retreat));
+        assert_eq!(knights.value(Piece::King), KING_VALUE);
+    }
+
+    #[test]
+    fn null_move_keeps_the_mate() {
+        // passing is tried all over this search, it must not hide the back rank mate
+        let board = Board::from_fen("6k1/5ppp/8/8/8/8/1Q3PPP/6K1 w").unwrap();
+        assert_eq!(
+            officers_value(&board, Color::White, &PieceValues::classical()),
+            9.
+        );
+        let best = &negamax(
+            &board,
+            Color::White,
+            4,
+            WinCondition::Checkmate,
+            [0; 2],
+            0.,
+            &PieceValues::classical(),
+            QUIESCENCE_DEPTH,
+        )[0];
+        assert_eq!((best.1, &best.2), (Pos(1, 6), &vec![Action::Go(Pos(1, 0))]));
+        assert_eq!(best.0, f32::INFINITY);
+    }
+
+    #[test]
+    fn reductions_keep_the_fork() {
+        // Nf6+ forks the king and queen, the reductions must not hide it
+        let board = Board::from_fen("6k1/3q4/8/8/4N3/8/8/K7 w").unwrap();
+        let best = &negamax(
+            &board,
+            Color::White,
+            4,
+            WinCondition::Checkmate,
+            [0; 2],
+            0.,
+            &PieceValues::classical(),
+            QUIESCENCE_DEPTH,
+        )[0];
+        assert_eq!((best.1, &best.2), (Pos(4, 4), &vec![Action::Go(Pos(5, 2))]));
+    }
+
+    #[test]
+    fn aspiration_changes_nothing() {
+        for fen in [
+            "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w",
+            "rnbqk/ppppp/5/5/5/5/PPPPP/RNBQK w",
+            "k4/1p3/2p2/5/2Q1n/5/5/4K w",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            let search = |aspiration| {
+                let (score, pos, actions, _) = deepen(
+                    Search::new(
+                        Color::White,
+                        WinCondition::Checkmate,
+                        0.,
+                        &PieceValues::classical(),
+                        QUIESCENCE_DEPTH,
+                        &[],
+                        &mut TranspositionTable::default(),
+                    ),
+                    &board,
+                    3,
+                    [0; 2],
+                    aspiration,
+                    None,
+                )
+                .0
+                .remove(0);
+                (score, pos, actions)
+            };
+            assert_eq!(search(true), search(false));
+        }
+    }
+
+    #[test]
+    fn timed_search_keeps_finished_depths() {
+        let timed = |board: &Board, max_millis| {
+            let (lines, depth) = negamax_timed_pv(
+                Search::new(
+                    Color::White,
+                    WinCondition::Checkmate,
+                    0.,
+                    &PieceValues::classical(),
+                    QUIESCENCE_DEPTH,
+                    &[],
+                    &mut TranspositionTable::default(),
+                ),
+                board,
+                max_millis,
+                [0; 2],
+            );
+            let moves: Vec<_> = lines
+                .into_iter()
+                .map(|(score, pos, actions, _)| (score, pos, actions))
+                .collect();
+            (moves, depth)
+        };
+        let board = Board::from_fen("k4/1p3/2p2/5/2Q1n/5/5/4K w").unwrap();
+        // out of time right away, only the first depth is searched
+        let (moves, depth) = timed(&board, 0);
+        assert_eq!(depth, 1);
+        assert_eq!(
+            moves,
+            negamax(
+                &board,
+                Color::White,
+                1,
+                WinCondition::Checkmate,
+                [0; 2],
+                0.,
+                &PieceValues::classical(),
+                QUIESCENCE_DEPTH,
+            )
+        );
+        // bare kings are scored right away, so every depth fits in the budget
+        let board = Board::from_fen("k4/5/5/5/5/5/5/4K w").unwrap();
+        assert_eq!(timed(&board, 1000).1, MAX_TIMED_DEPTH);
+        // a depth can't finish in time from the full board, it's cut off in the middle of a move
+        let board = Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w").unwrap();
+        let (moves, depth) = timed(&board, 200);
+        assert!(depth < MAX_TIMED_DEPTH);
+        assert_eq!(moves.len(), 20);
+    }
+
+    #[test]
+    fn transpositions_share_an_entry() {
+        let board = Board::from_fen("rnbqk/ppppp/5/5/5/5/PPPPP/RNBQK w").unwrap();
+        let knight_first = board
+            .play(Color::White, Pos(1, 7), &[Action::Go(Pos(2, 5))])
+            .play(Color::Black, Pos(4, 1), &[Action::Go(Pos(4, 2))])
+            .play(Color::White, Pos(0, 6), &[Action::Go(Pos(0, 5))]);
+        let pawn_first = board
+            .play(Color::White, Pos(0, 6), &[Action::Go(Pos(0, 5))])
+            .play(Color::Black, Pos(4, 1), &[Action::Go(Pos(4, 2))])
+            .play(Color::White, Pos(1, 7), &[Action::Go(Pos(2, 5))]);
+        assert_eq!(knight_first.zobrist(), pawn_first.zobrist());
+        let key = |board| TranspositionTable::key(board, Color::Black, [0; 2]);
+        assert_ne!(key(&knight_first), key(&board));
+        assert_ne!(
+            key(&knight_first),
+            TranspositionTable::key(&knight_first, Color::White, [0; 2])
+        );
+        let mut table = TranspositionTable::default();
+        for board in [&knight_first, &pawn_first] {
+            table.store(key(board), 2, 1., (0., 2.), &Vec::new());
         }
-        res.push((score, pos, actions));
+        assert_eq!(table.entries.len(), 1);
+        assert_eq!(
+            table
+                .probe(key(&pawn_first), 2, 0., 2.)
+                .map(|(score, _)| score),
+            Some(1.)
+        );
+        // a deeper search, or a bound that doesn't settle the window, needs a search
+        assert!(table.probe(key(&pawn_first), 3, 0., 2.).is_none());
+        table.store(key(&board), 2, 3., (0., 2.), &Vec::new());
+        assert!(table.probe(key(&board), 2, 0., 4.).is_none());
+        assert!(table.probe(key(&board), 2, 0., 2.).is_some());
+    }
+
+    #[test]
+    fn checks_are_extended() {
+        // Qd8+ Rxd8 Rxd8# is too deep for a 2 ply search, unless the check and recapture are followed
+        let board = Board::from_fen("2r3k1/5ppp/8/8/8/3Q4/5PPP/3R2K1 w").unwrap();
+        let search = |extensions| {
+            let (score, mut pv) = _negamax(
+                &mut Search::new(
+                    Color::White,
+                    WinCondition::Checkmate,
+                    0.,
+                    &PieceValues::classical(),
+                    QUIESCENCE_DEPTH,
+                    &[],
+                    &mut TranspositionTable::default(),
+                ),
+                &board,
+                2,
+                (f32::NEG_INFINITY, f32::INFINITY),
+                Color::White,
+                [0; 2],
+                extensions,
+            );
+            (score, pv.remove(0))
+        };
+        let (score, _) = search(Extensions {
+            left: 0,
+            last_capture: None,
+        });
+        assert!(score < KING_VALUE / 2.);
+        let (score, first) = search(Extensions::new(None));
+        assert!(score >= KING_VALUE / 2.);
+        assert_eq!(first, (Pos(3, 5), vec![Action::Go(Pos(3, 0))]));
+    }
+
+    #[test]
+    fn quiescence_depth_follows_the_exchange() {
+        // Qxd8+ looks like it wins the queen until the rook takes back
+        let board = Board::from_fen("r2q3k/8/8/8/8/8/8/3Q3K w").unwrap();
+        let quiesce = |quiescence| {
+            _negamax(
+                &mut Search::new(
+                    Color::White,
+                    WinCondition::Checkmate,
+                    0.,
+                    &PieceValues::classical(),
+                    quiescence,
+                    &[],
+                    &mut TranspositionTable::default(),
+                ),
+                &board,
+                0,
+                (f32::NEG_INFINITY, f32::INFINITY),
+                Color::White,
+                [0; 2],
+                Extensions::new(None),
+            )
+            .0
+        };
+        assert_eq!(quiesce(0), -5.);
+        assert_eq!(quiesce(1), 4.);
+        assert_eq!(quiesce(QUIESCENCE_DEPTH), -5.);
+    }
+
+    #[test]
+    fn only_moves_are_not_searched() {
+        // the rook checks along the first rank, Kb2 is the only way out
+        let board = Board::from_fen("k7/8/8/8/8/8/P7/K6r w").unwrap();
+        assert_eq!(board.moves(Color::White, true).len(), 1);
+        let lines = negamax_pv(
+            Search::new(
+                Color::White,
+                WinCondition::Checkmate,
+                0.,
+                &PieceValues::classical(),
+                QUIESCENCE_DEPTH,
+                &[],
+                &mut TranspositionTable::default(),
+            ),
+            &board,
+            4,
+            [0; 2],
+        );
+        assert_eq!(lines.len(), 1);
+        let (_, pos, actions, pv) = &lines[0];
+        assert_eq!((*pos, actions), (Pos(0, 7), &vec![Action::Go(Pos(1, 6))]));
+        // a 4 ply search would expect a whole line after it
+        assert!(pv.is_empty());
+    }
+
+    #[test]
+    fn table_is_kept_between_searches() {
+        let board = Board::from_fen("k4/1p3/2p2/5/2Q1n/5/5/4K w").unwrap();
+        let search = |table: &mut TranspositionTable, contempt| {
+            let search = Search::new(
+                Color::White,
+                WinCondition::Checkmate,
+                contempt,
+                &PieceValues::classical(),
+                QUIESCENCE_DEPTH,
+                &[],
+                table,
+            );
+            negamax_pv(search, &board, 3, [0; 2]);
+            table.entries.len()
+        };
+        let mut table = TranspositionTable::default();
+        let stored = search(&mut table, 0.);
+        assert!(stored > 0);
+        // the same search again finds its positions in the table
+        assert_eq!(search(&mut table, 0.), stored);
+        // another contempt scores the draws differently, the table starts over
+        assert_eq!(
+            search(&mut table, 1.),
+            search(&mut TranspositionTable::default(), 1.)
+        );
+    }
+
+    #[test]
+    fn stopped_search_gives_up() {
+        let board = Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w").unwrap();
+        let mut table = TranspositionTable::default();
+        let search = Search::new(
+            Color::White,
+            WinCondition::Checkmate,
+            0.,
+            &PieceValues::classical(),
+            QUIESCENCE_DEPTH,
+            &[],
+            &mut table,
+        )
+        .stop_on(Arc::new(AtomicBool::new(true)));
+        // with all the time in the world, the search is still called off right away
+        let (lines, depth) = negamax_timed_pv(search, &board, 60_000, [0; 2]);
+        assert_eq!(depth, 0);
+        assert!(lines.is_empty());
     }
-    res.sort_by(|(score1, _, _), (score2, _, _)| score2.partial_cmp(score1).unwrap());
-    res
 }
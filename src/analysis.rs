@@ -0,0 +1,238 @@
+use crate::{
+    ai::Line,
+    board::Square,
+    choss::{ChossGame, HSIZE},
+    keybindings::{Control, Controls},
+    pgn::move2san,
+    piece::Action,
+    pos::Pos,
+    settings::Settings,
+};
+use bevy::prelude::*;
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+#[cfg(not(target_arch = "wasm32"))]
+use futures_lite::future;
+
+const DEPTH: u32 = 2;
+const LINES: usize = 3;
+// in seconds, a new analysis doesn't start sooner than that after the previous one
+const THROTTLE: f64 = 0.5;
+
+type Lines = Vec<(f32, Pos, Vec<Action>, Line)>;
+
+#[derive(Component)]
+struct AnalysisText;
+
+// the position being analyzed in the background, with the search running on a copy of it
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Component)]
+struct AnalysisTask(ChossGame, Task<Lines>);
+
+#[derive(Default)]
+pub struct Analysis {
+    enabled: bool,
+    // the half move and squares the shown lines are about
+    analyzed: Option<(u32, Vec<Square>)>,
+    last_start: f64,
+    content: String,
+}
+
+fn setup(mut commands: Commands, server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: server.load("fonts/RobotoMono-Regular.ttf"),
+        font_size: 14.0,
+        color: Color::rgb(0.8, 0.8, 0.8),
+    };
+    let text_alignment = TextAlignment {
+        vertical: VerticalAlign::Top,
+        horizontal: HorizontalAlign::Left,
+    };
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::with_section("", text_style, text_alignment),
+            ..Default::default()
+        })
+        .insert(AnalysisText);
+}
+
+fn toggle_analysis(controls: Controls, mut analysis: ResMut<Analysis>) {
+    if controls.just_pressed(Control::Analysis) {
+        analysis.enabled = !analysis.enabled;
+    }
+}
+
+fn lines_text(choss: &ChossGame, lines: Lines) -> String {
+    // ranked list of the best moves for the side to play, from their point of view
+    let mut content = String::new();
+    for (i, (score, pos, actions, pv)) in lines.into_iter().enumerate() {
+        let color = choss.turn_color();
+        content += &format!(
+            "{}. {:+.1} {}",
+            i + 1,
+            score,
+            move2san(&choss.board, pos, &actions)
+        );
+        let mut board = choss.board.play(color, pos, &actions);
+        let mut pv_color = color.next();
+        for (pv_pos, pv_actions) in pv {
+            content += &format!(" {}", move2san(&board, pv_pos, &pv_actions));
+            board = board.play(pv_color, pv_pos, &pv_actions);
+            pv_color = pv_color.next();
+        }
+        content += "\n";
+    }
+    content
+}
+
+fn needs_analysis(analysis: &Analysis, choss: &ChossGame, now: f64) -> bool {
+    // only once the position changed, and not more often than the throttle allows
+    analysis.enabled
+        && now - analysis.last_start >= THROTTLE
+        && !matches!(&analysis.analyzed, Some((halfmove, squares)) if *halfmove == choss.halfmove() && *squares == choss.board.squares)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn start_analysis(
+    mut commands: Commands,
+    mut analysis: ResMut<Analysis>,
+    choss: Res<ChossGame>,
+    settings: Res<Settings>,
+    time: Res<Time>,
+    pool: Res<AsyncComputeTaskPool>,
+    query_task: Query<(), With<AnalysisTask>>,
+) {
+    if settings.is_changed() {
+        // the piece values changed, the lines shown are outdated
+        analysis.analyzed = None;
+    }
+    let now = time.seconds_since_startup();
+    if !query_task.is_empty() || !needs_analysis(&analysis, &choss, now) {
+        return;
+    }
+    analysis.last_start = now;
+    let analyzed = (*choss).clone();
+    let (values, quiescence) = (settings.piece_values, settings.quiescence_depth);
+    let task = pool.spawn(async move { analyzed.analyze(DEPTH, LINES, &values, quiescence) });
+    commands
+        .spawn()
+        .insert(AnalysisTask((*choss).clone(), task));
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn finish_analysis(
+    mut commands: Commands,
+    mut analysis: ResMut<Analysis>,
+    mut query_task: Query<(Entity, &mut AnalysisTask)>,
+) {
+    for (entity, mut task) in query_task.iter_mut() {
+        if let Some(lines) = future::block_on(future::poll_once(&mut task.1)) {
+            commands.entity(entity).despawn();
+            // the lines are shown even if the game moved on, until the next ones are ready
+            analysis.content = lines_text(&task.0, lines);
+            analysis.analyzed = Some((task.0.halfmove(), task.0.board.squares.clone()));
+        }
+    }
+}
+
+// there are no threads to search on in the browser, the throttle keeps the game responsive
+#[cfg(target_arch = "wasm32")]
+fn start_analysis(
+    mut analysis: ResMut<Analysis>,
+    choss: Res<ChossGame>,
+    settings: Res<Settings>,
+    time: Res<Time>,
+) {
+    if settings.is_changed() {
+        analysis.analyzed = None;
+    }
+    let now = time.seconds_since_startup();
+    if !needs_analysis(&analysis, &choss, now) {
+        return;
+    }
+    analysis.last_start = now;
+    let lines = choss.analyze(
+        DEPTH,
+        LINES,
+        &settings.piece_values,
+        settings.quiescence_depth,
+    );
+    analysis.content = lines_text(&choss, lines);
+    analysis.analyzed = Some((choss.halfmove(), choss.board.squares.clone()));
+}
+
+fn analysis(
+    mut query_text: Query<(&mut Text, &mut Transform), With<AnalysisText>>,
+    analysis: Res<Analysis>,
+    choss: Res<ChossGame>,
+) {
+    if !(analysis.is_changed() || choss.is_changed()) {
+        return;
+    }
+    if let Ok((mut text, mut transform)) = query_text.get_single_mut() {
+        let content = if analysis.enabled {
+            &analysis.content
+        } else {
+            ""
+        };
+        if text.sections[0].value != content {
+            text.sections[0].value = content.to_string();
+        }
+        // the board can change size between games
+        transform.translation = Vec3::new(
+            HSIZE * choss.board.width as f32 + 16.,
+            HSIZE * choss.board.height as f32,
+            0.,
+        );
+    }
+}
+
+pub struct AnalysisPlugin;
+
+impl Plugin for AnalysisPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Analysis::default())
+            .add_startup_system(setup)
+            .add_system(toggle_analysis)
+            .add_system(start_analysis)
+            .add_system(analysis);
+        #[cfg(not(target_arch = "wasm32"))]
+        app.add_system(finish_analysis);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::Color as PieceColor;
+
+    #[test]
+    fn analysis_is_throttled() {
+        let mut choss = ChossGame::new(PieceColor::White);
+        let mut analysis = Analysis::default();
+        // off by default
+        assert!(!needs_analysis(&analysis, &choss, 10.));
+        analysis.enabled = true;
+        assert!(needs_analysis(&analysis, &choss, 10.));
+        analysis.last_start = 10.;
+        analysis.analyzed = Some((choss.halfmove(), choss.board.squares.clone()));
+        // nothing new to look at
+        assert!(!needs_analysis(&analysis, &choss, 20.));
+        let (pos, actions) = choss.board.moves(PieceColor::White, true).remove(0);
+        choss.play(pos, &actions);
+        assert!(!needs_analysis(&analysis, &choss, 10. + THROTTLE / 2.));
+        assert!(needs_analysis(&analysis, &choss, 10. + THROTTLE));
+    }
+
+    #[test]
+    fn lines_in_san() {
+        let choss = ChossGame::new(PieceColor::White);
+        let lines = vec![(
+            0.5,
+            Pos(1, 6),
+            vec![Action::Go(Pos(1, 5))],
+            vec![(Pos(1, 1), vec![Action::Go(Pos(1, 2))])],
+        )];
+        assert_eq!(lines_text(&choss, lines), "1. +0.5 b3 b6\n");
+    }
+}
@@ -0,0 +1,78 @@
+use crate::character::Character;
+use crate::game::OPPONENTS;
+use crate::piece_set::{set_textures, DEFAULT_SET};
+use bevy::asset::FileAssetIo;
+use bevy::prelude::*;
+use std::path::Path;
+
+// everything loaded by name somewhere in the game, the sound variant folders are optional
+const STATIC_ASSETS: [&str; 12] = [
+    "fonts/RobotoMono-Regular.ttf",
+    "empty.png",
+    "circle.png",
+    "title.png",
+    "sounds/move.ogg",
+    "sounds/take.ogg",
+    "sounds/check.ogg",
+    "sounds/promote.wav",
+    "sounds/illegal.wav",
+    "sounds/win.wav",
+    "sounds/loss.wav",
+    "sounds/draw.wav",
+];
+
+fn required_assets() -> Vec<String> {
+    let mut res: Vec<String> = STATIC_ASSETS.iter().map(|path| path.to_string()).collect();
+    res.extend(set_textures(DEFAULT_SET));
+    for (name, faces) in OPPONENTS {
+        res.extend(faces.iter().map(|face| Character::face_path(name, face)));
+        res.push(Character::voice_path(name));
+    }
+    res
+}
+
+fn missing_assets(folder: &Path) -> Vec<String> {
+    required_assets()
+        .into_iter()
+        .filter(|path| !folder.join(path).is_file())
+        .collect()
+}
+
+fn check_assets() {
+    // the asset server only warns about missing files and leaves the screen blank,
+    // better to stop right away and say what's wrong
+    let folder = FileAssetIo::get_root_path().join("assets");
+    let missing = missing_assets(&folder);
+    if !missing.is_empty() {
+        panic!(
+            "missing assets in {}:\n{}",
+            folder.display(),
+            missing.join("\n")
+        );
+    }
+}
+
+pub struct AssetsPlugin;
+
+impl Plugin for AssetsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(check_assets);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn required_assets_are_there() {
+        assert_eq!(missing_assets(Path::new("assets")), Vec::<String>::new());
+        assert!(required_assets().contains(&"choss_pieces/pawn_b.png".to_string()));
+        assert!(required_assets().contains(&"carl blok/smug.png".to_string()));
+        assert!(required_assets().contains(&"sounds/carl_blok.ogg".to_string()));
+        assert_eq!(
+            missing_assets(Path::new("no_such_folder")).len(),
+            required_assets().len()
+        );
+    }
+}
@@ -1,14 +1,54 @@
-use crate::piece::{Action, Color, Piece};
-use crate::pos::Pos;
+use crate::piece::{Action, Color, PawnStatus, Piece};
+use crate::pos::{Pos, DIAGS, LINES};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
 
-type Square = Option<(Color, Piece)>;
+pub type Square = Option<(Color, Piece)>;
 
-#[derive(Clone)]
+pub fn splitmix(x: u64) -> u64 {
+    // a cheap and well mixed hash, the zobrist keys are computed instead of drawn in a table
+    // so boards of any size get them
+    let mut x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+fn piece_code(color: Color, piece: &Piece) -> u64 {
+    let code = match piece {
+        Piece::Knight => 0,
+        Piece::Bishop => 1,
+        Piece::Rook => 2,
+        Piece::Queen => 3,
+        Piece::King => 4,
+        // a pawn's direction and whether it can leap are part of the position
+        Piece::Pawn {
+            orientation,
+            status,
+        } => {
+            let status = match status {
+                PawnStatus::CanLeap => 0,
+                PawnStatus::JustLeaped => 1,
+                PawnStatus::CannotLeap => 2,
+            };
+            5 + status * 9 + ((orientation.0 + 1) * 3 + orientation.1 + 1) as u64
+        }
+    };
+    code * 2 + if color == Color::White { 0 } else { 1 }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Board {
     pub width: usize,
     pub height: usize,
     pub squares: Vec<Square>,
+    // the left and right edges are glued together
+    pub cylinder: bool,
+    // the kings can castle with the rooks that haven't moved
+    pub castling: bool,
+    // how many times the piece on each square has moved, pieces keep their count as they move
+    pub move_counts: Vec<u32>,
 }
 
 impl Board {
@@ -17,13 +57,29 @@ impl Board {
             width,
             height,
             squares: vec![None; width * height],
+            cylinder: false,
+            castling: false,
+            move_counts: vec![0; width * height],
         }
     }
 
-    pub fn in_bound(&self, pos: Pos) -> bool {
+    pub fn on_board(&self, pos: Pos) -> bool {
+        // strictly inside the drawn board, regardless of wrapping
         0 <= pos.0 && pos.0 < self.width as i32 && 0 <= pos.1 && pos.1 < self.height as i32
     }
 
+    pub fn in_bound(&self, pos: Pos) -> bool {
+        self.on_board(self.wrap(pos))
+    }
+
+    pub fn wrap(&self, pos: Pos) -> Pos {
+        if self.cylinder {
+            Pos(pos.0.rem_euclid(self.width as i32), pos.1)
+        } else {
+            pos
+        }
+    }
+
     pub fn get(&self, pos: Pos) -> Option<&Square> {
         if !self.in_bound(pos) {
             return None;
@@ -32,8 +88,14 @@ impl Board {
     }
 
     pub fn set(&mut self, pos: Pos, square: Square) {
+        // a piece set on the board hasn't moved yet
         let i = self.i(pos);
         self.squares[i] = square;
+        self.move_counts[i] = 0;
+    }
+
+    pub fn move_count(&self, pos: Pos) -> u32 {
+        self.move_counts[self.i(pos)]
     }
 
     pub fn pos(&self, i: usize) -> Pos {
@@ -41,39 +103,150 @@ impl Board {
     }
 
     pub fn i(&self, pos: Pos) -> usize {
+        let pos = self.wrap(pos);
         (pos.0 + pos.1 * self.width as i32) as usize
     }
 
-    fn king_pos(&self, color: Color) -> Option<Pos> {
-        for (i, square) in self.squares.iter().enumerate() {
-            if let Some((piece_color, piece)) = square {
-                if *piece_color == color && *piece == Piece::King {
-                    return Some(self.pos(i));
-                }
+    pub fn pieces(&self) -> impl Iterator<Item = (Pos, Color, Piece)> + '_ {
+        // every occupied square, in board order
+        self.squares
+            .iter()
+            .enumerate()
+            .filter_map(move |(i, square)| square.map(|(color, piece)| (self.pos(i), color, piece)))
+    }
+
+    pub fn pieces_of(&self, color: Color) -> impl Iterator<Item = (Pos, Piece)> + '_ {
+        self.pieces()
+            .filter(move |(_, piece_color, _)| *piece_color == color)
+            .map(|(pos, _, piece)| (pos, piece))
+    }
+
+    pub fn diff(&self, other: &Board) -> Vec<(Pos, Square, Square)> {
+        // the squares that changed from self to other, with their content before and after,
+        // both boards are expected to have the same size
+        self.squares
+            .iter()
+            .zip(other.squares.iter())
+            .enumerate()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(i, (before, after))| (self.pos(i), *before, *after))
+            .collect()
+    }
+
+    pub fn king_pos(&self, color: Color) -> Option<Pos> {
+        self.pieces_of(color)
+            .find(|(_, piece)| *piece == Piece::King)
+            .map(|(pos, _)| pos)
+    }
+
+    pub fn has_insufficient_material(&self) -> bool {
+        // a lone minor piece can't mate, anything more might
+        let mut minors = 0;
+        for (_, _, piece) in self.pieces() {
+            match piece {
+                Piece::King => {}
+                Piece::Knight | Piece::Bishop => minors += 1,
+                _ => return false,
             }
         }
-        None
+        minors <= 1
     }
 
-    pub fn is_checked(&self, color: Color) -> bool {
-        // if this panic then there's no king of this color on the board lol
-        let king_pos = self.king_pos(color).unwrap();
-        // check if the opponent can capture the king
-        let o_color = color.next();
-        for (_, actions) in self.takes(o_color, false) {
-            for action in actions {
-                if let Action::Go(go_pos) = action {
-                    if go_pos == king_pos {
-                        return true;
+    pub fn zobrist(&self) -> u64 {
+        // like the equality, the move counts are left out,
+        // but a king or rook that can still castle makes another position
+        self.squares
+            .iter()
+            .enumerate()
+            .filter_map(|(i, square)| square.as_ref().map(|(color, piece)| (i, color, piece)))
+            .fold(0, |hash, (i, color, piece)| {
+                let can_castle = self.castling
+                    && matches!(piece, Piece::King | Piece::Rook)
+                    && self.move_counts[i] == 0;
+                hash ^ splitmix(((i as u64) << 8) | piece_code(*color, piece))
+                    ^ if can_castle { splitmix(!(i as u64)) } else { 0 }
+            })
+    }
+
+    pub fn position_hash(&self, color: Color) -> u64 {
+        // the same pieces with the other side to play are another position
+        self.zobrist()
+            ^ if color == Color::White {
+                0
+            } else {
+                splitmix(u64::MAX)
+            }
+    }
+
+    pub fn is_capture(&self, color: Color, actions: &[Action]) -> bool {
+        actions.iter().any(|action| match action {
+            Action::Go(pos) => {
+                matches!(self.get(*pos), Some(Some((o_color, _))) if *o_color != color)
+            }
+            Action::Take(_) => true,
+            Action::Promotion(_) | Action::Castle(_, _) => false,
+        })
+    }
+
+    pub fn attacked_by(&self, color: Color) -> HashSet<Pos> {
+        // every square a piece of this color could take on, empty or not
+        let mut res = HashSet::new();
+        for (pos, piece) in self.pieces_of(color) {
+            res.extend(piece.attacks(self, pos));
+        }
+        res
+    }
+
+    pub fn pinned_pieces(&self, color: Color) -> HashMap<Pos, Pos> {
+        // pieces of this color that can't leave the line between their king and an opponent slider,
+        // with the direction of that line from the king
+        let mut res = HashMap::new();
+        let king_pos = match self.king_pos(color) {
+            Some(king_pos) => king_pos,
+            None => return res,
+        };
+        for (dirs, pinner) in [(LINES, Piece::Rook), (DIAGS, Piece::Bishop)] {
+            for dir in dirs {
+                let mut curr_pos = king_pos;
+                let mut pinned = None;
+                loop {
+                    curr_pos = self.wrap(curr_pos + dir);
+                    if curr_pos == king_pos {
+                        break;
                     }
-                } else if let Action::Take(take_pos) = action {
-                    if take_pos == king_pos {
-                        return true;
+                    match self.get(curr_pos) {
+                        Some(Some((piece_color, piece))) => {
+                            if *piece_color == color {
+                                if pinned.is_some() {
+                                    // two of our pieces in the way, none of them is pinned
+                                    break;
+                                }
+                                pinned = Some(curr_pos);
+                            } else {
+                                if *piece == pinner || *piece == Piece::Queen {
+                                    if let Some(pinned) = pinned {
+                                        res.insert(pinned, dir);
+                                    }
+                                }
+                                break;
+                            }
+                        }
+                        Some(None) => {}
+                        None => break,
                     }
                 }
             }
         }
-        false
+        res
+    }
+
+    pub fn is_checked(&self, color: Color) -> bool {
+        // check if the opponent can capture the king, the search plays pseudo legal moves
+        // so the king might be gone already and then there's nothing left to check
+        match self.king_pos(color) {
+            Some(king_pos) => self.attacked_by(color.next()).contains(&king_pos),
+            None => false,
+        }
     }
 
     pub fn filter_safe_moves(
@@ -94,17 +267,12 @@ impl Board {
     pub fn takes(&self, color: Color, safe_moves: bool) -> Vec<(Pos, Vec<Action>)> {
         // generate all taking moves for color
         let mut res = Vec::new();
-        for (i, square) in self.squares.iter().enumerate() {
-            if let Some((piece_color, piece)) = square {
-                if *piece_color == color {
-                    let pos = self.pos(i);
-                    let mut moves = piece.takes(self, pos, color);
-                    if safe_moves {
-                        moves = self.filter_safe_moves(color, pos, moves);
-                    }
-                    res.extend(moves.into_iter().map(|actions| (pos, actions)));
-                }
+        for (pos, piece) in self.pieces_of(color) {
+            let mut moves = piece.takes(self, pos, color);
+            if safe_moves {
+                moves = self.filter_safe_moves(color, pos, moves);
             }
+            res.extend(moves.into_iter().map(|actions| (pos, actions)));
         }
         res
     }
@@ -112,17 +280,12 @@ impl Board {
     pub fn moves(&self, color: Color, safe_moves: bool) -> Vec<(Pos, Vec<Action>)> {
         // generate all moves for color
         let mut res = Vec::new();
-        for (i, square) in self.squares.iter().enumerate() {
-            if let Some((piece_color, piece)) = square {
-                if *piece_color == color {
-                    let pos = self.pos(i);
-                    let mut moves = piece.moves(self, pos, color);
-                    if safe_moves {
-                        moves = self.filter_safe_moves(color, pos, moves);
-                    }
-                    res.extend(moves.into_iter().map(|actions| (pos, actions)));
-                }
+        for (pos, piece) in self.pieces_of(color) {
+            let mut moves = piece.moves(self, pos, color);
+            if safe_moves {
+                moves = self.filter_safe_moves(color, pos, moves);
             }
+            res.extend(moves.into_iter().map(|actions| (pos, actions)));
         }
         res
     }
@@ -142,12 +305,13 @@ impl Board {
         self.set(target, Some((color, piece.moved(start, target))));
     }
 
-    pub fn play(&self, color: Color, pos: Pos, actions: &Vec<Action>) -> Self {
+    pub fn play(&self, color: Color, pos: Pos, actions: &[Action]) -> Self {
         let mut res = self.clone();
         res.begin_turn(color);
         let mut last_pos = pos;
         // we unwrap because no move can be played out of the board's bound
         let square = self.get(pos).unwrap();
+        let move_count = self.move_count(pos) + 1;
         for action in actions {
             match action {
                 Action::Go(go_pos) => {
@@ -161,12 +325,34 @@ impl Board {
                     let (color, _) = square.unwrap();
                     res.set(last_pos, Some((color, *piece)));
                 }
+                Action::Castle(rook_pos, rook_target) => {
+                    // the rook moves once too
+                    let rook_count = self.move_count(*rook_pos) + 1;
+                    res.set(*rook_pos, None);
+                    res.set(*rook_target, *self.get(*rook_pos).unwrap());
+                    let i = res.i(*rook_target);
+                    res.move_counts[i] = rook_count;
+                }
             };
         }
+        // a promoted pawn carries its count over to its new piece
+        let i = res.i(last_pos);
+        res.move_counts[i] = move_count;
         res
     }
 }
 
+impl PartialEq for Board {
+    fn eq(&self, other: &Self) -> bool {
+        // the wrapping is part of the shape of the board,
+        // the move counts aren't part of the position so they don't break repetitions
+        self.width == other.width
+            && self.height == other.height
+            && self.cylinder == other.cylinder
+            && self.squares == other.squares
+    }
+}
+
 impl Display for Board {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for (i, square) in self.squares.iter().enumerate() {
@@ -209,3 +395,431 @@ impl Display for Board {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::PawnStatus;
+
+    #[test]
+    fn rook_attacks_across_the_wrap() {
+        let mut board = Board {
+            cylinder: true,
+            ..Board::new(5, 8)
+        };
+        board.set(Pos(0, 7), Some((Color::White, Piece::King)));
+        board.set(Pos(4, 0), Some((Color::Black, Piece::King)));
+        board.set(Pos(1, 3), Some((Color::White, Piece::Rook)));
+        board.set(Pos(4, 3), Some((Color::Black, Piece::Rook)));
+        // (2,3) and (3,3) are empty, so the rooks see each other both ways round
+        let rook_moves = Piece::Rook.moves(&board, Pos(1, 3), Color::White);
+        assert!(rook_moves.contains(&vec![Action::Go(Pos(4, 3))]));
+        assert!(rook_moves.contains(&vec![Action::Go(Pos(0, 3))]));
+        // the moves are expressed with wrapped positions
+        assert!(rook_moves.iter().flatten().all(|action| match action {
+            Action::Go(pos) => board.on_board(*pos),
+            _ => true,
+        }));
+        // a rook on the a file checks a king on the last file through the wrap
+        board.set(Pos(1, 3), None);
+        board.set(Pos(4, 3), None);
+        board.set(Pos(0, 0), Some((Color::White, Piece::Rook)));
+        board.set(Pos(2, 0), Some((Color::White, Piece::Knight)));
+        assert!(board.is_checked(Color::Black));
+        assert!(!Board {
+            cylinder: false,
+            ..board
+        }
+        .is_checked(Color::Black));
+    }
+
+    #[test]
+    fn rays_halt_on_an_empty_rank() {
+        let mut board = Board {
+            cylinder: true,
+            ..Board::new(6, 6)
+        };
+        board.set(Pos(2, 2), Some((Color::White, Piece::Queen)));
+        // the horizontal rays come back to the queen, they must stop there
+        let queen_moves = Piece::Queen.moves(&board, Pos(2, 2), Color::White);
+        let horizontal = queen_moves
+            .iter()
+            .filter(|actions| matches!(actions[0], Action::Go(Pos(_, 2))))
+            .count();
+        assert_eq!(horizontal, 10);
+        assert_eq!(Piece::Queen.takes(&board, Pos(2, 2), Color::White).len(), 0);
+    }
+
+    #[test]
+    fn insufficient_material() {
+        let insufficient = |fen| Board::from_fen(fen).unwrap().has_insufficient_material();
+        assert!(insufficient("k4/5/5/5/5/5/5/4K w"));
+        assert!(insufficient("k4/5/5/5/5/5/5/3NK w"));
+        assert!(!insufficient("k4/5/5/5/5/5/5/2BNK w"));
+        assert!(!insufficient("kn3/5/5/5/5/5/5/3BK w"));
+        assert!(!insufficient("k4/5/5/5/5/5/5/3PK w"));
+    }
+
+    #[test]
+    fn lone_minors_cant_mate() {
+        let kings = || {
+            let mut board = Board::new(5, 8);
+            board.set(Pos(4, 7), Some((Color::White, Piece::King)));
+            board.set(Pos(0, 0), Some((Color::Black, Piece::King)));
+            board
+        };
+        assert!(kings().has_insufficient_material());
+        for minor in [Piece::Bishop, Piece::Knight] {
+            let mut board = kings();
+            board.set(Pos(2, 4), Some((Color::White, minor)));
+            assert!(board.has_insufficient_material());
+            // on either side
+            let mut board = kings();
+            board.set(Pos(2, 4), Some((Color::Black, minor)));
+            assert!(board.has_insufficient_material());
+        }
+        let mut board = kings();
+        board.set(Pos(2, 4), Some((Color::White, Piece::Rook)));
+        assert!(!board.has_insufficient_material());
+    }
+
+    #[test]
+    fn attacked_squares() {
+        let mut board = Board::new(5, 8);
+        board.set(Pos(2, 3), Some((Color::White, Piece::Rook)));
+        let attacked = board.attacked_by(Color::White);
+        assert_eq!(attacked.len(), 4 + 7);
+        assert!(attacked.contains(&Pos(0, 3)) && attacked.contains(&Pos(2, 7)));
+        assert!(!attacked.contains(&Pos(2, 3)) && !attacked.contains(&Pos(1, 2)));
+        assert!(board.attacked_by(Color::Black).is_empty());
+
+        let mut board = Board::new(5, 8);
+        board.set(Pos(2, 3), Some((Color::Black, Piece::Knight)));
+        let attacked = board.attacked_by(Color::Black);
+        let expected: HashSet<Pos> = [
+            (0, 2),
+            (0, 4),
+            (4, 2),
+            (4, 4),
+            (1, 1),
+            (3, 1),
+            (1, 5),
+            (3, 5),
+        ]
+        .into_iter()
+        .map(|(x, y)| Pos(x, y))
+        .collect();
+        assert_eq!(attacked, expected);
+        // in the corner most of the jumps fall off the board
+        let mut board = Board::new(5, 8);
+        board.set(Pos(0, 0), Some((Color::Black, Piece::Knight)));
+        assert_eq!(board.attacked_by(Color::Black).len(), 2);
+    }
+
+    #[test]
+    fn pins() {
+        // the bishop pins the knight to its king
+        let board = Board::from_fen("k4/5/5/4b/5/2N2/5/K4 w").unwrap();
+        let pinned = board.pinned_pieces(Color::White);
+        assert_eq!(pinned.len(), 1);
+        assert_eq!(pinned.get(&Pos(2, 5)), Some(&Pos(1, -1)));
+        assert!(board.pinned_pieces(Color::Black).is_empty());
+        // with a second piece in the way nothing is pinned
+        let board = Board::from_fen("k4/5/5/4b/3P1/2N2/5/K4 w").unwrap();
+        assert!(board.pinned_pieces(Color::White).is_empty());
+        // a rook doesn't pin on a diagonal
+        let board = Board::from_fen("k4/5/5/4r/5/2N2/5/K4 w").unwrap();
+        assert!(board.pinned_pieces(Color::White).is_empty());
+    }
+
+    #[test]
+    fn standard_board_pieces() {
+        let board = crate::make_board::standard_board();
+        assert_eq!(board.pieces().count(), 32);
+        assert_eq!(board.pieces_of(Color::White).count(), 16);
+        assert_eq!(
+            board.pieces().next(),
+            Some((Pos(0, 0), Color::Black, Piece::Rook))
+        );
+        assert!(Board::new(5, 8).pieces().next().is_none());
+    }
+
+    #[test]
+    fn equality_and_diff() {
+        let board = crate::make_board::halved_board();
+        assert_eq!(board.clone(), board);
+        assert!(board.diff(&board).is_empty());
+        assert_ne!(
+            Board {
+                cylinder: true,
+                ..board.clone()
+            },
+            board
+        );
+
+        let next = board.play(Color::White, Pos(1, 7), &[Action::Go(Pos(2, 5))]);
+        assert_ne!(next, board);
+        assert_eq!(
+            board.diff(&next),
+            vec![
+                (Pos(2, 5), None, Some((Color::White, Piece::Knight))),
+                (Pos(1, 7), Some((Color::White, Piece::Knight)), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn move_counts_follow_the_pieces() {
+        let board = Board::from_fen("4k/5/5/1r3/5/2N2/5/K4 w").unwrap();
+        assert_eq!(board.move_count(Pos(2, 5)), 0);
+        let board = board.play(Color::White, Pos(2, 5), &[Action::Go(Pos(3, 3))]);
+        assert_eq!(board.move_count(Pos(3, 3)), 1);
+        assert_eq!(board.move_count(Pos(2, 5)), 0);
+        let board = board.play(Color::Black, Pos(1, 3), &[Action::Go(Pos(2, 3))]);
+        // the knight takes the rook, the capture leaves no count behind
+        let board = board.play(Color::White, Pos(3, 3), &[Action::Go(Pos(2, 5))]);
+        let board = board.play(Color::Black, Pos(2, 3), &[Action::Go(Pos(2, 5))]);
+        assert_eq!(board.move_count(Pos(2, 5)), 2);
+        assert_eq!(
+            board.get(Pos(2, 5)),
+            Some(&Some((Color::Black, Piece::Rook)))
+        );
+        assert_eq!(board.move_count(Pos(3, 3)), 0);
+        let board = board.play(Color::White, Pos(0, 7), &[Action::Go(Pos(1, 7))]);
+        assert_eq!(board.move_count(Pos(1, 7)), 1);
+        assert_eq!(board.move_count(Pos(0, 7)), 0);
+        // the counts don't make otherwise identical positions different
+        let mut fresh = board.clone();
+        fresh.set(Pos(1, 7), Some((Color::White, Piece::King)));
+        assert_eq!(fresh.move_count(Pos(1, 7)), 0);
+        assert_eq!(fresh, board);
+    }
+
+    fn random_board(rng: &mut impl rand::Rng) -> Board {
+        // one king each and a handful of other pieces anywhere they could legally stand
+        let mut board = Board {
+            cylinder: rng.gen_bool(0.2),
+            ..Board::new(5, 8)
+        };
+        let mut pieces = vec![(Color::White, Piece::King), (Color::Black, Piece::King)];
+        for _ in 0..rng.gen_range(0..10) {
+            let color = if rng.gen_bool(0.5) {
+                Color::White
+            } else {
+                Color::Black
+            };
+            let piece =
+                [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen][rng.gen_range(0..4)];
+            pieces.push((color, piece));
+        }
+        let squares = rand::seq::index::sample(rng, board.squares.len(), pieces.len());
+        for (i, square) in squares.into_iter().zip(pieces) {
+            board.set(board.pos(i), Some(square));
+        }
+        for _ in 0..rng.gen_range(0..6) {
+            // pawns never stand on the first or last rank
+            let pos = Pos(rng.gen_range(0..5), rng.gen_range(1..7));
+            if board.get(pos) != Some(&None) {
+                continue;
+            }
+            let (color, orientation, start) = if rng.gen_bool(0.5) {
+                (Color::White, Pos(0, -1), 6)
+            } else {
+                (Color::Black, Pos(0, 1), 1)
+            };
+            let status = if pos.1 == start {
+                PawnStatus::CanLeap
+            } else {
+                PawnStatus::CannotLeap
+            };
+            board.set(
+                pos,
+                Some((
+                    color,
+                    Piece::Pawn {
+                        orientation,
+                        status,
+                    },
+                )),
+            );
+        }
+        board
+    }
+
+    fn slides(board: &Board, from: Pos, dir: Pos, distance: i32) -> bool {
+        // every square before the last one is empty
+        (1..distance).all(|i| board.get(board.wrap(from + dir * i)) == Some(&None))
+    }
+
+    fn follows_the_rules(
+        board: &Board,
+        color: Color,
+        piece: Piece,
+        pos: Pos,
+        actions: &[Action],
+    ) -> bool {
+        // the piece's own movement rules, written out again rather than taken from the move generation
+        let target = match actions.first() {
+            Some(Action::Go(target)) if board.on_board(*target) => *target,
+            _ => return false,
+        };
+        let captures = match board.get(target) {
+            Some(Some((target_color, _))) if *target_color == color => return false,
+            Some(Some(_)) => true,
+            _ => false,
+        };
+        let rest = &actions[1..];
+        let dy = target.1 - pos.1;
+        // on a cylinder the piece can get there going either way around
+        let dxs = if board.cylinder {
+            let dx = target.0 - pos.0;
+            vec![dx, dx - board.width as i32, dx + board.width as i32]
+        } else {
+            vec![target.0 - pos.0]
+        };
+        dxs.into_iter().any(|dx| {
+            let (adx, ady) = (dx.abs(), dy.abs());
+            let dir = Pos(dx.signum(), dy.signum());
+            let straight = (dx == 0) != (dy == 0);
+            let diagonal = adx == ady && adx > 0;
+            match piece {
+                Piece::King => match rest {
+                    [] => adx.max(ady) == 1,
+                    [Action::Castle(rook_pos, rook_target)] => {
+                        board.castling
+                            && dy == 0
+                            && adx == 2
+                            && !captures
+                            && *rook_target == pos + dir
+                            && board.get(*rook_pos) == Some(&Some((color, Piece::Rook)))
+                    }
+                    _ => false,
+                },
+                Piece::Knight => rest.is_empty() && matches!((adx, ady), (1, 2) | (2, 1)),
+                Piece::Bishop => rest.is_empty() && diagonal && slides(board, pos, dir, adx),
+                Piece::Rook => rest.is_empty() && straight && slides(board, pos, dir, adx.max(ady)),
+                Piece::Queen => {
+                    rest.is_empty() && (straight || diagonal) && slides(board, pos, dir, adx.max(ady))
+                }
+                Piece::Pawn {
+                    orientation,
+                    status,
+                } => {
+                    // the last rank is where there's no square left in front
+                    let promotes = board.get(board.wrap(target + orientation)).is_none();
+                    let rest = match rest.split_last() {
+                        Some((Action::Promotion(new_piece), rest)) if promotes => {
+                            if matches!(new_piece, Piece::Pawn { .. } | Piece::King) {
+                                return false;
+                            }
+                            rest
+                        }
+                        _ if promotes => return false,
+                        _ => rest,
+                    };
+                    let forward = orientation.1;
+                    match rest {
+                        [] if dx == 0 => {
+                            !captures
+                                && (dy == forward
+                                    || (dy == 2 * forward
+                                        && status == PawnStatus::CanLeap
+                                        && slides(board, pos, orientation, 2)))
+                        }
+                        [] => adx == 1 && dy == forward && captures,
+                        [Action::Take(taken)] => {
+                            adx == 1
+                                && dy == forward
+                                && !captures
+                                && *taken == board.wrap(target + orientation * -1)
+                                && matches!(
+                                    board.get(*taken),
+                                    Some(Some((taken_color, Piece::Pawn { status: PawnStatus::JustLeaped, .. })))
+                                        if *taken_color != color
+                                )
+                        }
+                        _ => false,
+                    }
+                }
+            }
+        })
+    }
+
+    fn is_legal(board: &Board, color: Color, pos: Pos, actions: &[Action]) -> bool {
+        let piece = match board.get(pos) {
+            Some(Some((piece_color, piece))) if *piece_color == color => *piece,
+            _ => return false,
+        };
+        follows_the_rules(board, color, piece, pos, actions)
+            && !board.play(color, pos, actions).is_checked(color)
+    }
+
+    #[test]
+    fn legality_follows_the_pieces_rules() {
+        let board = Board::from_fen("k4/5/5/5/5/5/1p3/R1N1K w").unwrap();
+        let legal = |pos, actions: &[Action]| is_legal(&board, Color::White, pos, actions);
+        // a knight can't move like a rook, nor a rook like a knight
+        assert!(legal(Pos(2, 7), &[Action::Go(Pos(3, 5))]));
+        assert!(!legal(Pos(2, 7), &[Action::Go(Pos(2, 3))]));
+        assert!(legal(Pos(0, 7), &[Action::Go(Pos(0, 1))]));
+        assert!(!legal(Pos(0, 7), &[Action::Go(Pos(1, 5))]));
+        // nor go through a piece
+        assert!(legal(Pos(0, 7), &[Action::Go(Pos(1, 7))]));
+        assert!(!legal(Pos(0, 7), &[Action::Go(Pos(3, 7))]));
+        // the king goes one square at a time
+        assert!(legal(Pos(4, 7), &[Action::Go(Pos(3, 6))]));
+        assert!(!legal(Pos(4, 7), &[Action::Go(Pos(2, 6))]));
+        // and the kings can be missing from the searched positions
+        assert!(!Board::new(5, 8).is_checked(Color::White));
+    }
+
+    #[test]
+    fn random_positions_give_legal_moves() {
+        use crate::ai::{negamax, PieceValues, QUIESCENCE_DEPTH};
+        use crate::choss::WinCondition;
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut tested = 0;
+        while tested < 100 {
+            let board = random_board(&mut rng);
+            let color = if tested % 2 == 0 {
+                Color::White
+            } else {
+                Color::Black
+            };
+            // the side that just moved can't have left its king in check
+            if board.is_checked(color.next()) {
+                continue;
+            }
+            tested += 1;
+            let legal =
+                |(pos, actions): &(Pos, Vec<Action>)| is_legal(&board, color, *pos, actions);
+            let moves = board.moves(color, true);
+            assert!(moves.iter().all(legal), "{}", board);
+            let takes = board.takes(color, true);
+            assert!(takes.iter().all(legal), "{}", board);
+            assert!(takes.iter().all(|take| moves.contains(take)), "{}", board);
+            for (pos, piece) in board.pieces_of(color) {
+                let safe = board.filter_safe_moves(color, pos, piece.moves(&board, pos, color));
+                assert!(safe
+                    .iter()
+                    .all(|actions| is_legal(&board, color, pos, actions)));
+            }
+            let searched = negamax(
+                &board,
+                color,
+                1,
+                WinCondition::Checkmate,
+                [0, 0],
+                0.,
+                &PieceValues::default(),
+                QUIESCENCE_DEPTH,
+            );
+            assert_eq!(searched.len(), moves.len(), "{}", board);
+            assert!(searched
+                .iter()
+                .all(|(_, pos, actions)| is_legal(&board, color, *pos, actions)));
+        }
+    }
+}
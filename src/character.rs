@@ -1,8 +1,15 @@
 use crate::choss::{ChossGame, HSIZE};
+use crate::keybindings::{Control, Controls};
+use crate::settings::Settings;
 use bevy::prelude::*;
+use bevy::window::WindowResized;
 use std::collections::HashMap;
 
 const CHAR_SEC: f64 = 0.04;
+// the faces are square, the dialogue is centered on them
+const FACE_SIZE: f32 = 128.;
+// how many lines of the dialogue log fit next to the board
+const LOG_LINES: usize = 10;
 #[derive(Component)]
 pub struct Character {
     pub name: String,
@@ -18,17 +25,22 @@ impl Character {
         for face in facenames {
             faces.insert(
                 face.clone(),
-                server.load(format!("{}/{}.png", name.to_lowercase(), face).as_str()),
+                server.load(Character::face_path(&name, &face).as_str()),
             );
         }
 
         // try to load voice
-        let voice = server.load(&format!(
-            "sounds/{}.ogg",
-            name.to_lowercase().replace(" ", "_")
-        ));
+        let voice = server.load(Character::voice_path(&name).as_str());
         Character { name, faces, voice }
     }
+
+    pub fn face_path(name: &str, face: &str) -> String {
+        format!("{}/{}.png", name.to_lowercase(), face)
+    }
+
+    pub fn voice_path(name: &str) -> String {
+        format!("sounds/{}.ogg", name.to_lowercase().replace(" ", "_"))
+    }
 }
 
 #[derive(Component)]
@@ -80,13 +92,49 @@ impl Say {
     }
 }
 
+// every line said during the match, to read again the ones that were missed
+#[derive(Default)]
+pub struct DialogueLog {
+    // the speaker and what they said, oldest first
+    entries: Vec<(String, String)>,
+    shown: bool,
+    // how many lines the view is scrolled up from the last one
+    scroll: usize,
+}
+
+impl DialogueLog {
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.scroll = 0;
+    }
+
+    fn push(&mut self, speaker: &str, text: &str) {
+        self.entries
+            .push((speaker.to_string(), text.replace('\n', " ")));
+        // a view scrolled up stays on the same lines
+        if self.scroll > 0 {
+            self.scroll += 1;
+        }
+    }
+
+    fn scroll_by(&mut self, lines: isize) {
+        let max_scroll = self.entries.len().saturating_sub(LOG_LINES) as isize;
+        self.scroll = (self.scroll as isize + lines).clamp(0, max_scroll) as usize;
+    }
+
+    fn visible(&self) -> &[(String, String)] {
+        let end = self.entries.len().saturating_sub(self.scroll);
+        &self.entries[end.saturating_sub(LOG_LINES)..end]
+    }
+}
+
 #[derive(Component)]
 pub struct DialogueText;
 
 #[derive(Component)]
 pub struct DialogueFace;
 
-fn setup(mut commands: Commands, server: Res<AssetServer>, choss: Res<ChossGame>) {
+fn setup(mut commands: Commands, server: Res<AssetServer>) {
     let font = server.load("fonts/RobotoMono-Regular.ttf");
     let text_style = TextStyle {
         font,
@@ -100,38 +148,48 @@ fn setup(mut commands: Commands, server: Res<AssetServer>, choss: Res<ChossGame>
     commands
         .spawn_bundle(Text2dBundle {
             text: Text::with_section("", text_style, text_alignment),
-            transform: Transform::from_xyz(
-                -HSIZE * choss.board.width as f32,
-                HSIZE * (choss.board.height + 2) as f32,
-                0.,
-            ),
             ..Default::default()
         })
         .insert(DialogueText);
     commands
         .spawn_bundle(SpriteBundle {
             sprite: Sprite {
-                custom_size: Some(Vec2::new(128., 128.)),
+                custom_size: Some(Vec2::new(FACE_SIZE, FACE_SIZE)),
                 ..Default::default()
             },
             texture: server.load("empty.png"),
-            transform: Transform::from_xyz(
-                -HSIZE * choss.board.width as f32 - 80.,
-                HSIZE * (choss.board.height + 2) as f32,
-                0.,
-            ),
             ..Default::default()
         })
         .insert(DialogueFace);
+    let text_style = TextStyle {
+        font: server.load("fonts/RobotoMono-Regular.ttf"),
+        font_size: 14.0,
+        color: Color::rgb(0.8, 0.8, 0.8),
+    };
+    let text_alignment = TextAlignment {
+        vertical: VerticalAlign::Top,
+        horizontal: HorizontalAlign::Right,
+    };
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::with_section("", text_style, text_alignment),
+            ..Default::default()
+        })
+        .insert(DialogueLogText);
 }
 
+#[derive(Component)]
+struct DialogueLogText;
+
 fn dialogue(
     mut commands: Commands,
     mut query: Query<(Entity, &Character, &mut Say)>,
     mut query_text: Query<&mut Text, With<DialogueText>>,
     mut query_face: Query<&mut Handle<Image>, With<DialogueFace>>,
+    mut log: ResMut<DialogueLog>,
     audio: Res<Audio>,
     time: Res<Time>,
+    settings: Res<Settings>,
 ) {
     if let Ok((entity, character, mut say)) = query.get_single_mut() {
         if let Ok(mut text) = query_text.get_single_mut() {
@@ -146,17 +204,26 @@ fn dialogue(
                 let now = time.seconds_since_startup();
                 let mut new_i = say.compute_i(now);
                 // if we finished
-                if say.i >= say.text.len() {
-                    // and 1 sec has passed
-                    if now - say.duration - say.start > 1. {
+                // i counts characters rather than bytes, the lines aren't all ascii
+                let len = say.text.chars().count();
+                if say.i >= len {
+                    // and the move delay has passed
+                    if now - say.duration - say.start > settings.move_delay {
+                        log.push(&character.name, &say.text);
                         commands.entity(entity).remove::<Say>();
                     }
                 } else if new_i != say.i {
                     // there's new characters to say
-                    new_i = new_i.min(say.text.len());
-                    text.sections[0].value = say.text[0..new_i].to_string();
+                    new_i = new_i.min(len);
+                    text.sections[0].value = say.text.chars().take(new_i).collect();
                     // if i..new_i is not only spaces, produce a sound
-                    if say.text[say.i..new_i].trim().len() > 0 {
+                    if say
+                        .text
+                        .chars()
+                        .skip(say.i)
+                        .take(new_i - say.i)
+                        .any(|char| !char.is_whitespace())
+                    {
                         audio.play(character.voice.clone());
                     }
                     say.i = new_i;
@@ -166,10 +233,159 @@ fn dialogue(
     }
 }
 
+fn dialogue_anchor(board_size: (usize, usize), window: Vec2, margin: f32) -> (Vec3, Vec3) {
+    // where the dialogue text starts and where the face is, right above the board's left edge,
+    // pushed right if the face would go past the left of the window
+    let (width, height) = board_size;
+    let text_x = (-HSIZE * width as f32).max(-window.x / 2. + FACE_SIZE + 2. * margin);
+    let y = HSIZE * height as f32 + margin + FACE_SIZE / 2.;
+    (
+        Vec3::new(text_x, y, 0.),
+        Vec3::new(text_x - margin - FACE_SIZE / 2., y, 0.),
+    )
+}
+
+fn place_dialogue(
+    mut query_text: Query<&mut Transform, (With<DialogueText>, Without<DialogueFace>)>,
+    mut query_face: Query<&mut Transform, (With<DialogueFace>, Without<DialogueText>)>,
+    mut resized: EventReader<WindowResized>,
+    windows: Res<Windows>,
+    choss: Res<ChossGame>,
+    settings: Res<Settings>,
+) {
+    if !(resized.iter().count() > 0 || choss.is_changed() || settings.is_changed()) {
+        return;
+    }
+    let window = match windows.get_primary() {
+        Some(window) => Vec2::new(window.width(), window.height()),
+        None => return,
+    };
+    let (text_pos, face_pos) = dialogue_anchor(
+        (choss.board.width, choss.board.height),
+        window,
+        settings.board_margin,
+    );
+    if let Ok(mut transform) = query_text.get_single_mut() {
+        transform.translation = text_pos;
+    }
+    if let Ok(mut transform) = query_face.get_single_mut() {
+        transform.translation = face_pos;
+    }
+}
+
+fn toggle_log(controls: Controls, mut log: ResMut<DialogueLog>) {
+    if controls.just_pressed(Control::DialogueLog) {
+        log.shown = !log.shown;
+    }
+    if log.shown {
+        if controls.just_pressed(Control::LogUp) {
+            log.scroll_by(1);
+        }
+        if controls.just_pressed(Control::LogDown) {
+            log.scroll_by(-1);
+        }
+    }
+}
+
+fn display_log(
+    mut query_text: Query<(&mut Text, &mut Transform), With<DialogueLogText>>,
+    log: Res<DialogueLog>,
+    choss: Res<ChossGame>,
+    settings: Res<Settings>,
+) {
+    if !(log.is_changed() || choss.is_changed() || settings.is_changed()) {
+        return;
+    }
+    if let Ok((mut text, mut transform)) = query_text.get_single_mut() {
+        text.sections[0].value = if log.shown {
+            log.visible()
+                .iter()
+                .map(|(speaker, line)| format!("{}: {}\n", speaker, line))
+                .collect()
+        } else {
+            "".to_string()
+        };
+        // left of the board, the analysis is on the right
+        transform.translation = Vec3::new(
+            -HSIZE * choss.board.width as f32 - settings.board_margin,
+            HSIZE * choss.board.height as f32,
+            0.,
+        );
+    }
+}
+
 pub struct CharacterPlugin;
 
 impl Plugin for CharacterPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(setup).add_system(dialogue);
+        app.insert_resource(DialogueLog::default())
+            .add_startup_system(setup)
+            .add_system(dialogue)
+            .add_system(place_dialogue)
+            .add_system(toggle_log)
+            .add_system(display_log);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_scrolling() {
+        let mut log = DialogueLog::default();
+        assert!(log.visible().is_empty());
+        log.push(
+            "Alice",
+            "Welcome to the Choss club !\nIt's your first game right ?",
+        );
+        assert_eq!(
+            log.visible(),
+            [(
+                "Alice".to_string(),
+                "Welcome to the Choss club ! It's your first game right ?".to_string()
+            )]
+        );
+        for i in 0..LOG_LINES + 2 {
+            log.push("Carl Blok", &i.to_string());
+        }
+        // only the last lines fit
+        assert_eq!(log.visible().len(), LOG_LINES);
+        assert_eq!(log.visible()[LOG_LINES - 1].1, (LOG_LINES + 1).to_string());
+        log.scroll_by(100);
+        assert_eq!(log.visible()[0].0, "Alice");
+        // new lines don't move a view scrolled to the top
+        log.push("Carl Blok", "Again ...");
+        assert_eq!(log.visible()[0].0, "Alice");
+        log.scroll_by(-100);
+        assert_eq!(log.visible()[LOG_LINES - 1].1, "Again ...");
+        log.clear();
+        assert!(log.visible().is_empty());
+    }
+
+    #[test]
+    fn dialogue_stays_off_the_board() {
+        let margin = Settings::default().board_margin;
+        for (width, height) in [(5, 8), (8, 8), (3, 4), (10, 10), (12, 6)] {
+            for window in [
+                Vec2::new(720., 720.),
+                Vec2::new(1280., 900.),
+                Vec2::new(400., 400.),
+            ] {
+                let (text, face) = dialogue_anchor((width, height), window, margin);
+                let board_top = HSIZE * height as f32;
+                // the face and the text centered on it are fully above the board
+                assert!(face.y - FACE_SIZE / 2. >= board_top + margin);
+                assert_eq!(text.y, face.y);
+                // the face is left of the text, which starts at the board's edge or to its right
+                assert!(face.x + FACE_SIZE / 2. + margin <= text.x);
+                assert!(text.x >= -HSIZE * width as f32);
+                // and the face stays in the window
+                assert!(face.x - FACE_SIZE / 2. >= -window.x / 2.);
+            }
+        }
+        // with room to spare the text lines up with the board
+        let (text, _) = dialogue_anchor((5, 8), Vec2::new(720., 720.), margin);
+        assert_eq!(text.x, -HSIZE * 5.);
     }
 }
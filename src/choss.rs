@@ -1,22 +1,94 @@
 use crate::{
-    ai::piece_value,
+    ai::{negamax_pv, Line, PieceValues, Search, TranspositionTable},
     board::Board,
+    fen::{parse_turn, FenError},
     make_board::*,
+    pgn::pos2pgn,
     piece::{Action, Color, Piece},
     pos::Pos,
+    settings::{Palette, Settings},
 };
 use bevy::prelude::*;
 use bevy::render::render_resource::{Extent3d, TextureDimension};
 use bevy::render::texture::BevyDefault;
+use serde::{Deserialize, Serialize};
 
 pub const SIZE: u32 = 64;
 pub const HSIZE: f32 = SIZE as f32 / 2.;
 
-#[derive(Clone)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum WinCondition {
+    // the usual rules, only checkmate wins
+    Checkmate,
+    // checking the opponent for the third time also wins
+    ThreeCheck,
+    // bringing your king to the center of the board also wins
+    KingOfTheHill,
+}
+
+impl WinCondition {
+    pub fn next(self) -> Self {
+        match self {
+            WinCondition::Checkmate => WinCondition::ThreeCheck,
+            WinCondition::ThreeCheck => WinCondition::KingOfTheHill,
+            WinCondition::KingOfTheHill => WinCondition::Checkmate,
+        }
+    }
+
+    pub fn record_check(self, board: &Board, color: Color, checks: &mut [u32; 2]) {
+        if self == WinCondition::ThreeCheck && board.is_checked(color.next()) {
+            checks[color as usize] += 1;
+        }
+    }
+
+    pub fn winner(self, board: &Board, checks: [u32; 2]) -> Option<Color> {
+        // checkmate is not detected here since it depends on the legal moves
+        match self {
+            WinCondition::Checkmate => None,
+            WinCondition::ThreeCheck => [Color::White, Color::Black]
+                .into_iter()
+                .find(|color| checks[*color as usize] >= 3),
+            WinCondition::KingOfTheHill => [Color::White, Color::Black]
+                .into_iter()
+                .find(|color| matches!(board.king_pos(*color), Some(pos) if is_hill(board, pos))),
+        }
+    }
+}
+
+pub fn is_hill(board: &Board, pos: Pos) -> bool {
+    // the middle square(s) of the board, 1 or 2 per axis depending on parity
+    (2 * pos.0 - (board.width as i32 - 1)).abs() <= 1
+        && (2 * pos.1 - (board.height as i32 - 1)).abs() <= 1
+}
+
+// Clone is a deep copy (the board owns its squares), undo relies on it
+// to snapshot and later restore the whole game, turn counter included
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ChossGame {
     pub board: Board,
     pub player: Color,
     pub turn: u32,
+    // the turn the game was set up on, past 0 when it comes from a FEN
+    #[serde(default)]
+    pub start_turn: u32,
+    pub win_condition: WinCondition,
+    // checks given by each color, indexed by color
+    pub checks: [u32; 2],
+    // the hash of every position reached so far with the side to play, to spot repetitions
+    pub history: Vec<u64>,
+    // half moves since the last capture or pawn move
+    pub quiet_moves: u32,
+    // a copy of BoardOrientation, so the coordinates can be converted from the game alone
+    pub turned: bool,
+    // the game before each move played, without their own stack, popped by undo
+    #[serde(default)]
+    pub undo_stack: Vec<ChossGame>,
+}
+
+// the board can be turned around at any time, on top of showing the player's side at the bottom
+#[derive(Default)]
+pub struct BoardOrientation {
+    pub turned: bool,
 }
 
 impl ChossGame {
@@ -25,6 +97,43 @@ impl ChossGame {
             board: halved_board(),
             player: player,
             turn: 0,
+            start_turn: 0,
+            win_condition: WinCondition::Checkmate,
+            checks: [0, 0],
+            history: Vec::new(),
+            quiet_moves: 0,
+            turned: false,
+            undo_stack: Vec::new(),
+        }
+    }
+
+    pub fn from_fen(fen: &str, player: Color) -> Result<Self, FenError> {
+        let board = Board::from_fen(fen)?;
+        let (side, counters) = parse_turn(fen)?;
+        let turn = (counters.fullmove_number - 1) * 2 + if side == Color::White { 0 } else { 1 };
+        Ok(ChossGame {
+            board,
+            turn,
+            start_turn: turn,
+            quiet_moves: counters.quiet_moves,
+            ..ChossGame::new(player)
+        })
+    }
+
+    pub fn flipped(&self) -> bool {
+        // the player's pieces are at the bottom of the screen unless the board was turned
+        (self.player == Color::Black) != self.turned
+    }
+
+    fn screen_pos(&self, pos: Pos) -> Pos {
+        // where a square is drawn, turning the board around is its own inverse
+        if self.flipped() {
+            Pos(
+                self.board.width as i32 - 1 - pos.0,
+                self.board.height as i32 - 1 - pos.1,
+            )
+        } else {
+            pos
         }
     }
 
@@ -35,13 +144,14 @@ impl ChossGame {
                 self.board.height as f32 * HSIZE,
             ))
             / SIZE as f32;
-        Pos(
+        self.screen_pos(Pos(
             world_pos.x as i32,
             self.board.height as i32 - 1 - world_pos.y as i32,
-        )
+        ))
     }
 
     pub fn board_to_world(&self, pos: Pos) -> Transform {
+        let pos = self.screen_pos(pos);
         Transform::from_xyz(
             -HSIZE * self.board.width as f32 + (0.5 + pos.0 as f32) * SIZE as f32,
             HSIZE * self.board.height as f32 - (0.5 + pos.1 as f32) * SIZE as f32,
@@ -49,6 +159,31 @@ impl ChossGame {
         )
     }
 
+    pub fn halfmove(&self) -> u32 {
+        // number of moves played so far by both sides, counting the ones before a FEN's position
+        self.turn
+    }
+
+    pub fn moves_played(&self) -> u32 {
+        // the moves recorded since the game was set up, where the move list and the times start
+        self.turn - self.start_turn
+    }
+
+    pub fn fullmove_number(&self) -> u32 {
+        // starts at 1 and goes up after black's move, like in PGN/FEN
+        self.turn / 2 + 1
+    }
+
+    pub fn to_fen(&self) -> String {
+        // the full FEN, with the move counters that the board alone doesn't know
+        format!(
+            "{} {} {}",
+            self.board.to_fen(self.turn_color()),
+            self.quiet_moves,
+            self.fullmove_number()
+        )
+    }
+
     pub fn turn_color(&self) -> Color {
         if self.turn % 2 == 0 {
             Color::White
@@ -57,6 +192,20 @@ impl ChossGame {
         }
     }
 
+    pub fn is_player_piece(&self, pos: Pos) -> bool {
+        matches!(self.board.get(pos), Some(Some((color, _))) if *color == self.player)
+    }
+
+    pub fn threatened_pieces(&self) -> Vec<Pos> {
+        // the player's pieces the opponent could take, the king being shown by the check highlight
+        let attacked = self.board.attacked_by(self.player.next());
+        self.board
+            .pieces_of(self.player)
+            .filter(|(pos, piece)| *piece != Piece::King && attacked.contains(pos))
+            .map(|(pos, _)| pos)
+            .collect()
+    }
+
     fn safe_moves(&self, piece: Piece, from: Pos) -> Vec<Vec<Action>> {
         self.board.filter_safe_moves(
             self.turn_color(),
@@ -74,56 +223,200 @@ impl ChossGame {
         None
     }
 
-    pub fn playable_move(&self, from: Pos, to: Pos) -> Option<Vec<Action>> {
-        if let Some(moves) = self.playable_moves(from) {
-            for actions in moves {
-                for action in &actions {
-                    if let Action::Go(pos) = action {
-                        if *pos == to {
-                            return Some(actions);
-                        }
-                    }
+    pub fn move_markers(&self, from: Pos) -> Option<Vec<(Pos, MoveMarker)>> {
+        let moves = self.playable_moves(from)?;
+        // several moves can land on the same square (en passant, promotion choices),
+        // only keep one marker per square
+        let mut markers: Vec<(Pos, MoveMarker)> = Vec::new();
+        for actions in moves {
+            let mut target = None;
+            let mut marker = MoveMarker {
+                capture: self.board.is_capture(self.player, &actions),
+                promotion: false,
+            };
+            for action in actions.iter() {
+                match action {
+                    Action::Go(go_pos) => target = Some(*go_pos),
+                    Action::Promotion(_) => marker.promotion = true,
+                    Action::Take(_) | Action::Castle(_, _) => {}
+                }
+            }
+            if let Some(target) = target {
+                if let Some((_, other)) = markers.iter_mut().find(|(pos, _)| *pos == target) {
+                    other.capture |= marker.capture;
+                    other.promotion |= marker.promotion;
+                } else {
+                    markers.push((target, marker));
                 }
             }
         }
-        None
+        Some(markers)
+    }
+
+    pub fn playable_move(&self, from: Pos, to: Pos) -> Option<Vec<Action>> {
+        let moves: Vec<Vec<Action>> = self
+            .playable_moves(from)?
+            .into_iter()
+            .filter(|actions| actions.contains(&Action::Go(to)))
+            .collect();
+        // the player always promotes to a queen, the other pieces are left to the AI
+        moves
+            .iter()
+            .find(|actions| actions.contains(&Action::Promotion(Piece::Queen)))
+            .or_else(|| moves.first())
+            .cloned()
     }
 
     pub fn play(&mut self, pos: Pos, actions: &Vec<Action>) {
+        // the snapshot leaves the stack out, or every move would copy all the ones before it
+        let undo_stack = std::mem::take(&mut self.undo_stack);
+        let before = self.clone();
+        self.undo_stack = undo_stack;
+        self.undo_stack.push(before);
         let color = self.turn_color();
+        if self.history.is_empty() {
+            // the board may have been set up by hand, so the starting position is only known now
+            self.history.push(self.board.position_hash(color));
+        }
+        let irreversible = self.board.is_capture(color, actions)
+            || matches!(self.board.get(pos), Some(Some((_, Piece::Pawn { .. }))));
         self.board = self.board.play(color, pos, &actions);
+        self.win_condition
+            .record_check(&self.board, color, &mut self.checks);
         self.turn += 1;
+        self.quiet_moves = if irreversible {
+            0
+        } else {
+            self.quiet_moves + 1
+        };
+        self.history
+            .push(self.board.position_hash(self.turn_color()));
     }
 
-    pub fn remaining_value(&self) -> f32 {
-        let mut sum = 0.;
-        for square in &self.board.squares {
-            if let Some((color, piece)) = square {
-                if *color == self.player && *piece != Piece::King {
-                    sum += piece_value(*piece);
-                }
+    pub fn undo(&mut self) -> bool {
+        // checks, repetitions and the fifty-move clock come back with the snapshot,
+        // the orientation is the player's choice and stays as it is
+        match self.undo_stack.pop() {
+            Some(before) => {
+                let undo_stack = std::mem::take(&mut self.undo_stack);
+                let turned = self.turned;
+                *self = before;
+                self.undo_stack = undo_stack;
+                self.turned = turned;
+                true
             }
+            None => false,
         }
-        sum
+    }
+
+    pub fn repetitions(&self) -> usize {
+        // how many times the current position was reached, itself included
+        match self.history.last() {
+            Some(last) => self
+                .history
+                .iter()
+                .filter(|position| *position == last)
+                .count(),
+            None => 1,
+        }
+    }
+
+    pub fn reversible_history(&self) -> &[u64] {
+        // the positions that can still come back, since the last capture or pawn move
+        &self.history[self
+            .history
+            .len()
+            .saturating_sub(self.quiet_moves as usize + 1)..]
+    }
+
+    pub fn is_threefold_repetition(&self) -> bool {
+        self.repetitions() >= 3
+    }
+
+    pub fn is_fifty_move_draw(&self) -> bool {
+        // fifty moves each without a capture or a pawn move
+        self.quiet_moves >= 100
+    }
+
+    pub fn draw_claimable(&self) -> bool {
+        self.is_threefold_repetition() || self.is_fifty_move_draw()
+    }
+
+    pub fn winner(&self) -> Option<Color> {
+        self.win_condition.winner(&self.board, self.checks)
+    }
+
+    pub fn analyze(
+        &self,
+        depth: u32,
+        n: usize,
+        values: &PieceValues,
+        quiescence: u32,
+    ) -> Vec<(f32, Pos, Vec<Action>, Line)> {
+        // the n best moves for the side to play, best first, each with its expected continuation
+        let mut lines = negamax_pv(
+            Search::new(
+                self.turn_color(),
+                self.win_condition,
+                // the analysis stays neutral about draws
+                0.,
+                values,
+                quiescence,
+                self.reversible_history(),
+                &mut TranspositionTable::default(),
+            ),
+            &self.board,
+            depth,
+            self.checks,
+        );
+        lines.truncate(n);
+        lines
+    }
+
+    pub fn remaining_value(&self, values: &PieceValues) -> f32 {
+        self.board
+            .pieces_of(self.player)
+            .filter(|(_, piece)| *piece != Piece::King)
+            .map(|(_, piece)| values.value(piece))
+            .sum()
     }
 }
 
-fn board_tex(board: &Board, size: u32) -> Image {
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MoveMarker {
+    pub capture: bool,
+    pub promotion: bool,
+}
+
+pub fn board_tex(board: &Board, size: u32, palette: &Palette, flipped: bool) -> Image {
+    let to_rgb = |color: bevy::prelude::Color| {
+        [
+            (color.r() * 255.) as u8,
+            (color.g() * 255.) as u8,
+            (color.b() * 255.) as u8,
+        ]
+    };
+    let light = to_rgb(palette.light_square);
+    let dark = to_rgb(palette.dark_square);
+    // turning the board around swaps the colors when its width and height don't have the same parity
+    let offset = if flipped {
+        board.width + board.height
+    } else {
+        0
+    };
     let mut data = vec![255; 4 * board.width * board.height * size as usize * size as usize];
     for i in 0..(data.len() / 4) {
-        if (i / (board.width * size as usize * size as usize)
-            + (i / size as usize) % board.width as usize)
+        let rgb = if (i / (board.width * size as usize * size as usize)
+            + (i / size as usize) % board.width as usize
+            + offset)
             % 2
             == 0
         {
-            data[i * 4] = 200;
-            data[i * 4 + 1] = 200;
-            data[i * 4 + 2] = 200;
+            light
         } else {
-            data[i * 4] = 100;
-            data[i * 4 + 1] = 100;
-            data[i * 4 + 2] = 200;
-        }
+            dark
+        };
+        data[i * 4..i * 4 + 3].copy_from_slice(&rgb);
     }
     Image::new(
         Extent3d {
@@ -137,21 +430,556 @@ fn board_tex(board: &Board, size: u32) -> Image {
     )
 }
 
+fn ring_tex(size: u32) -> Image {
+    // a white ring with a transparent center, tinted by the sprite color
+    let mut data = vec![255; 4 * size as usize * size as usize];
+    let radius = size as f32 / 2.;
+    for i in 0..(data.len() / 4) {
+        let x = (i % size as usize) as f32 + 0.5 - radius;
+        let y = (i / size as usize) as f32 + 0.5 - radius;
+        let dist = (x * x + y * y).sqrt();
+        if dist > radius || dist < radius * 0.8 {
+            data[i * 4 + 3] = 0;
+        }
+    }
+    Image::new(
+        Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        BevyDefault::bevy_default(),
+    )
+}
+
+pub struct CaptureMarker(pub Handle<Image>);
+
 pub fn piece_tex_name(piece: &Piece, color: &Color) -> String {
-    format!("{}_", piece) + &format!("{:?}", color)[0..1].to_lowercase()
+    format!("{}_{}", piece.name(), color.letter())
 }
 
+#[derive(Component)]
+pub struct BoardSprite;
+
 pub fn draw_choss(
     mut commands: Commands,
     choss: Res<ChossGame>,
+    settings: Res<Settings>,
     mut textures: ResMut<Assets<Image>>,
 ) {
     let mut camera = OrthographicCameraBundle::new_2d();
     camera.transform.translation += Vec3::new(0., SIZE as f32, 0.);
     commands.spawn_bundle(camera);
-    let board_tex = board_tex(&choss.board, SIZE);
-    commands.spawn_bundle(SpriteBundle {
-        texture: textures.add(board_tex),
-        ..Default::default()
+    let board_tex = board_tex(&choss.board, SIZE, &settings.palette(), choss.flipped());
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: textures.add(board_tex),
+            ..Default::default()
+        })
+        .insert(BoardSprite);
+    commands.insert_resource(CaptureMarker(textures.add(ring_tex(SIZE))));
+}
+
+#[derive(Component)]
+pub struct CoordinateLabel;
+
+fn coordinate_labels(choss: &ChossGame, margin: f32) -> Vec<(String, Vec2)> {
+    // the files under the board and the ranks left of it, in the margin around the board
+    let bottom = -HSIZE * choss.board.height as f32 - margin / 2.;
+    let left = -HSIZE * choss.board.width as f32 - margin / 2.;
+    let files = (0..choss.board.width as i32).map(|file| {
+        let name = pos2pgn(Pos(file, 0), choss.board.height);
+        let x = choss.board_to_world(Pos(file, 0)).translation.x;
+        (name[..1].to_string(), Vec2::new(x, bottom))
+    });
+    let ranks = (0..choss.board.height as i32).map(|row| {
+        let name = pos2pgn(Pos(0, row), choss.board.height);
+        let y = choss.board_to_world(Pos(0, row)).translation.y;
+        (name[1..].to_string(), Vec2::new(left, y))
     });
+    files.chain(ranks).collect()
+}
+
+pub fn draw_coordinates(
+    mut commands: Commands,
+    query: Query<Entity, With<CoordinateLabel>>,
+    choss: Res<ChossGame>,
+    settings: Res<Settings>,
+    server: Res<AssetServer>,
+    mut drawn: Local<Option<(usize, usize, bool)>>,
+) {
+    // drawn on the first frame, then again when the board changes size or is turned around
+    let shape = Some((choss.board.width, choss.board.height, choss.flipped()));
+    if *drawn == shape && !settings.is_changed() {
+        return;
+    }
+    *drawn = shape;
+    for entity in query.iter() {
+        commands.entity(entity).despawn();
+    }
+    let text_style = TextStyle {
+        font: server.load("fonts/RobotoMono-Regular.ttf"),
+        font_size: SIZE as f32 / 5.,
+        color: settings.palette().light_square,
+    };
+    let text_alignment = TextAlignment {
+        vertical: VerticalAlign::Center,
+        horizontal: HorizontalAlign::Center,
+    };
+    for (label, pos) in coordinate_labels(&choss, settings.board_margin) {
+        commands
+            .spawn_bundle(Text2dBundle {
+                text: Text::with_section(label, text_style.clone(), text_alignment),
+                transform: Transform::from_translation(pos.extend(0.)),
+                ..Default::default()
+            })
+            .insert(CoordinateLabel);
+    }
+}
+
+pub fn redraw_choss(
+    mut query: Query<&mut Handle<Image>, With<BoardSprite>>,
+    choss: Res<ChossGame>,
+    settings: Res<Settings>,
+    mut textures: ResMut<Assets<Image>>,
+    mut flipped: Local<bool>,
+) {
+    let palette_changed = settings.is_changed() && !settings.is_added();
+    let turned = *flipped != choss.flipped();
+    *flipped = choss.flipped();
+    for mut handle in query.iter_mut() {
+        // the board can also change size when a new game starts with another setup
+        let resized = textures.get(&*handle).map(|image| {
+            let size = image.texture_descriptor.size;
+            (size.width, size.height)
+        }) != Some((
+            choss.board.width as u32 * SIZE,
+            choss.board.height as u32 * SIZE,
+        ));
+        if palette_changed || turned || (choss.is_changed() && resized) {
+            *handle = textures.add(board_tex(
+                &choss.board,
+                SIZE,
+                &settings.palette(),
+                choss.flipped(),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::QUIESCENCE_DEPTH;
+    use crate::piece::PawnStatus;
+
+    #[test]
+    fn every_piece_has_a_texture() {
+        let pieces = [
+            Piece::Pawn {
+                orientation: Pos(0, -1),
+                status: PawnStatus::CanLeap,
+            },
+            Piece::Knight,
+            Piece::Bishop,
+            Piece::Rook,
+            Piece::Queen,
+            Piece::King,
+        ];
+        for piece in pieces {
+            for color in [Color::White, Color::Black] {
+                let path = format!("assets/choss_pieces/{}.png", piece_tex_name(&piece, &color));
+                assert!(std::path::Path::new(&path).exists(), "missing {}", path);
+            }
+        }
+        assert_eq!(piece_tex_name(&Piece::Queen, &Color::Black), "queen_b");
+    }
+
+    #[test]
+    fn threats() {
+        let choss = ChossGame::from_fen("4k/5/5/1r1N1/5/2N2/5/K4 w", Color::White).unwrap();
+        // the rook attacks the knight on its rank, the other knight is out of its reach
+        assert_eq!(choss.threatened_pieces(), vec![Pos(3, 3)]);
+        let choss = ChossGame {
+            player: Color::Black,
+            ..choss
+        };
+        assert_eq!(choss.threatened_pieces(), vec![Pos(1, 3)]);
+        assert!(ChossGame::new(Color::White).threatened_pieces().is_empty());
+    }
+
+    #[test]
+    fn fen_counters_round_trip() {
+        let mut choss = ChossGame::new(Color::White);
+        play(&mut choss, Pos(1, 7), Pos(2, 5));
+        play(&mut choss, Pos(1, 0), Pos(2, 2));
+        play(&mut choss, Pos(2, 6), Pos(2, 4));
+        let fen = choss.to_fen();
+        assert!(fen.ends_with(" 0 2"));
+        let copy = ChossGame::from_fen(&fen, Color::White).unwrap();
+        assert_eq!(copy.board, choss.board);
+        assert_eq!((copy.halfmove(), copy.quiet_moves), (3, 0));
+        assert_eq!(copy.turn_color(), Color::Black);
+        assert_eq!(copy.moves_played(), 0);
+        assert_eq!(copy.to_fen(), fen);
+        // the fifty-move clock carries over too
+        let copy = ChossGame::from_fen("k4/5/5/5/5/5/5/4K w - - 37 60", Color::White).unwrap();
+        assert_eq!((copy.quiet_moves, copy.fullmove_number()), (37, 60));
+        assert_eq!(copy.to_fen(), "k4/5/5/5/5/5/5/4K w - - 37 60");
+        // the largest counters still leave room to play on
+        let mut copy =
+            ChossGame::from_fen("k4/5/5/5/5/5/5/4K b - - 1000000 1000000", Color::White).unwrap();
+        play(&mut copy, Pos(0, 0), Pos(1, 0));
+        assert_eq!(copy.fullmove_number(), 1_000_001);
+        assert!(ChossGame::from_fen("k4/5/5/5/5/5/5/4K b - - 0 4294967295", Color::White).is_err());
+    }
+
+    #[test]
+    fn board_tex_on_a_big_board() {
+        let board = Board::new(12, 10);
+        let size = 4;
+        let image = board_tex(&board, size, &Palette::standard(), false);
+        assert_eq!(image.texture_descriptor.size.width, 12 * size);
+        assert_eq!(image.texture_descriptor.size.height, 10 * size);
+        let square_rgb = |pos: Pos| {
+            let i = (pos.1 as usize * size as usize * board.width * size as usize
+                + pos.0 as usize * size as usize)
+                * 4;
+            image.data[i..i + 3].to_vec()
+        };
+        // the pattern must stay a checkerboard on every row, even past the 8th file
+        assert_eq!(square_rgb(Pos(0, 0)), square_rgb(Pos(11, 9)));
+        assert_eq!(square_rgb(Pos(11, 0)), square_rgb(Pos(0, 9)));
+        assert_ne!(square_rgb(Pos(0, 0)), square_rgb(Pos(11, 0)));
+        assert_ne!(square_rgb(Pos(9, 9)), square_rgb(Pos(10, 9)));
+    }
+
+    #[test]
+    fn coordinates_follow_the_board() {
+        let white = ChossGame::new(Color::White);
+        let labels = coordinate_labels(&white, 16.);
+        assert_eq!(labels.len(), 5 + 8);
+        // the a file is on the left and rank 1 at the bottom
+        assert_eq!(labels[0].0, "a");
+        assert!(labels[0].1.x < labels[4].1.x);
+        let rank_1 = labels.iter().find(|(label, _)| label == "1").unwrap();
+        let rank_8 = labels.iter().find(|(label, _)| label == "8").unwrap();
+        assert!(rank_1.1.y < rank_8.1.y);
+        // everything is turned around for black
+        let black = coordinate_labels(&ChossGame::new(Color::Black), 16.);
+        assert_eq!(black[0].1.x, labels[4].1.x);
+        let rank_1 = black.iter().find(|(label, _)| label == "1").unwrap();
+        assert_eq!(rank_1.1.y, rank_8.1.y);
+    }
+
+    #[test]
+    fn flipped_for_black() {
+        let white = ChossGame::new(Color::White);
+        let black = ChossGame::new(Color::Black);
+        // each side sees its own back rank at the bottom of the screen
+        assert_eq!(
+            white.board_to_world(Pos(0, 7)),
+            black.board_to_world(Pos(4, 0))
+        );
+        for pos in [Pos(0, 0), Pos(3, 5), Pos(4, 7)] {
+            let world_pos = black.board_to_world(pos).translation;
+            assert_eq!(
+                black.world_to_board(Vec2::new(world_pos.x, world_pos.y)),
+                pos
+            );
+        }
+        // the squares keep their color once turned around
+        let size = 2;
+        let square_rgb = |image: &Image, pos: Pos| {
+            let i = (pos.1 as usize * size as usize * 5 * size as usize
+                + pos.0 as usize * size as usize)
+                * 4;
+            image.data[i..i + 3].to_vec()
+        };
+        let palette = Palette::standard();
+        let image = board_tex(&white.board, size, &palette, false);
+        let flipped = board_tex(&black.board, size, &palette, true);
+        assert_eq!(
+            square_rgb(&image, Pos(0, 0)),
+            square_rgb(&flipped, Pos(4, 7))
+        );
+    }
+
+    #[test]
+    fn turned_board_round_trip() {
+        for player in [Color::White, Color::Black] {
+            let choss = ChossGame::new(player);
+            let turned = ChossGame {
+                turned: true,
+                ..choss.clone()
+            };
+            assert_ne!(choss.flipped(), turned.flipped());
+            for x in 0..5 {
+                for y in 0..8 {
+                    let pos = Pos(x, y);
+                    for game in [&choss, &turned] {
+                        let world_pos = game.board_to_world(pos).translation;
+                        assert_eq!(
+                            game.world_to_board(Vec2::new(world_pos.x, world_pos.y)),
+                            pos
+                        );
+                    }
+                    // turning the board puts each square where the opposite one was
+                    assert_eq!(
+                        turned.board_to_world(pos),
+                        choss.board_to_world(Pos(4 - x, 7 - y))
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn one_marker_per_square() {
+        let mut board = Board::new(5, 8);
+        board.set(Pos(0, 7), Some((Color::White, Piece::King)));
+        board.set(Pos(4, 0), Some((Color::Black, Piece::King)));
+        board.set(Pos(1, 0), Some((Color::Black, Piece::Rook)));
+        board.set(
+            Pos(2, 1),
+            Some((
+                Color::White,
+                Piece::Pawn {
+                    orientation: Pos(0, -1),
+                    status: PawnStatus::CannotLeap,
+                },
+            )),
+        );
+        let choss = ChossGame {
+            board,
+            ..ChossGame::new(Color::White)
+        };
+        let markers = choss.move_markers(Pos(2, 1)).unwrap();
+        assert_eq!(
+            markers,
+            vec![
+                (
+                    Pos(2, 0),
+                    MoveMarker {
+                        capture: false,
+                        promotion: true
+                    }
+                ),
+                (
+                    Pos(1, 0),
+                    MoveMarker {
+                        capture: true,
+                        promotion: true
+                    }
+                ),
+            ]
+        );
+        // the player's promotions are queens
+        assert_eq!(
+            choss.playable_move(Pos(2, 1), Pos(2, 0)),
+            Some(vec![Action::Go(Pos(2, 0)), Action::Promotion(Piece::Queen)])
+        );
+    }
+
+    fn play(choss: &mut ChossGame, from: Pos, to: Pos) {
+        choss.play(from, &vec![Action::Go(to)]);
+    }
+
+    #[test]
+    fn three_check_wins_on_the_third_check() {
+        let mut board = Board::new(5, 8);
+        board.set(Pos(0, 7), Some((Color::White, Piece::King)));
+        board.set(Pos(4, 0), Some((Color::Black, Piece::King)));
+        board.set(Pos(0, 3), Some((Color::White, Piece::Rook)));
+        let mut choss = ChossGame {
+            board,
+            win_condition: WinCondition::ThreeCheck,
+            ..ChossGame::new(Color::White)
+        };
+        play(&mut choss, Pos(0, 3), Pos(4, 3));
+        play(&mut choss, Pos(4, 0), Pos(3, 0));
+        play(&mut choss, Pos(4, 3), Pos(3, 3));
+        assert_eq!(choss.checks, [2, 0]);
+        assert_eq!(choss.winner(), None);
+        play(&mut choss, Pos(3, 0), Pos(4, 0));
+        assert_eq!(choss.winner(), None);
+        play(&mut choss, Pos(3, 3), Pos(4, 3));
+        assert_eq!(choss.winner(), Some(Color::White));
+        // the same checks mean nothing with the usual rules
+        choss.win_condition = WinCondition::Checkmate;
+        assert_eq!(choss.winner(), None);
+    }
+
+    #[test]
+    fn king_of_the_hill_wins_on_the_center() {
+        let mut board = Board::new(5, 8);
+        board.set(Pos(2, 5), Some((Color::White, Piece::King)));
+        board.set(Pos(0, 0), Some((Color::Black, Piece::King)));
+        let mut choss = ChossGame {
+            board,
+            win_condition: WinCondition::KingOfTheHill,
+            ..ChossGame::new(Color::White)
+        };
+        assert_eq!(choss.winner(), None);
+        // the AI sees it too
+        let moves = crate::ai::negamax(
+            &choss.board,
+            Color::White,
+            1,
+            choss.win_condition,
+            choss.checks,
+            0.,
+            &PieceValues::classical(),
+            QUIESCENCE_DEPTH,
+        );
+        assert_eq!(moves[0].2, vec![Action::Go(Pos(2, 4))]);
+        play(&mut choss, Pos(2, 5), Pos(2, 4));
+        assert_eq!(choss.winner(), Some(Color::White));
+        choss.win_condition = WinCondition::Checkmate;
+        assert_eq!(choss.winner(), None);
+    }
+
+    #[test]
+    fn hill_squares() {
+        let board = Board::new(5, 8);
+        let hills: Vec<Pos> = (0..40)
+            .map(|i| board.pos(i))
+            .filter(|pos| is_hill(&board, *pos))
+            .collect();
+        assert_eq!(hills, vec![Pos(2, 3), Pos(2, 4)]);
+        let board = Board::new(8, 8);
+        assert_eq!(
+            (0..64).filter(|i| is_hill(&board, board.pos(*i))).count(),
+            4
+        );
+    }
+
+    #[test]
+    fn move_numbers() {
+        let mut choss = ChossGame::new(Color::White);
+        assert_eq!((choss.halfmove(), choss.fullmove_number()), (0, 1));
+        play(&mut choss, Pos(2, 6), Pos(2, 4));
+        assert_eq!((choss.halfmove(), choss.fullmove_number()), (1, 1));
+        assert_eq!(choss.turn_color(), Color::Black);
+        play(&mut choss, Pos(2, 1), Pos(2, 3));
+        assert_eq!((choss.halfmove(), choss.fullmove_number()), (2, 2));
+        // the undo snapshot is taken before the AI's move and restored wholesale
+        let snapshot = choss.clone();
+        play(&mut choss, Pos(1, 6), Pos(1, 5));
+        play(&mut choss, Pos(1, 1), Pos(1, 2));
+        assert_eq!((choss.halfmove(), choss.fullmove_number()), (4, 3));
+        choss = snapshot;
+        assert_eq!((choss.halfmove(), choss.fullmove_number()), (2, 2));
+        assert_eq!(choss.turn_color(), Color::White);
+    }
+
+    #[test]
+    fn clone_is_independent() {
+        let mut choss = ChossGame {
+            win_condition: WinCondition::ThreeCheck,
+            ..ChossGame::new(Color::White)
+        };
+        let snapshot = choss.clone();
+        let squares = snapshot.board.squares.clone();
+        play(&mut choss, Pos(2, 6), Pos(2, 4));
+        choss.checks[0] += 1;
+        choss.board.set(Pos(0, 0), None);
+        assert_eq!(snapshot.board.squares, squares);
+        assert_eq!(snapshot.halfmove(), 0);
+        assert_eq!(snapshot.checks, [0, 0]);
+        assert_ne!(choss.board.squares, squares);
+        // restoring the snapshot restores the turn, not just the board
+        choss = snapshot.clone();
+        assert_eq!(choss.halfmove(), 0);
+        assert_eq!(choss.turn_color(), Color::White);
+        assert_eq!(choss.board.squares, squares);
+    }
+
+    #[test]
+    fn undo_restores_the_game() {
+        let mut choss = ChossGame {
+            win_condition: WinCondition::ThreeCheck,
+            ..ChossGame::new(Color::White)
+        };
+        assert!(!choss.undo());
+        let start = choss.board.clone();
+        play(&mut choss, Pos(1, 7), Pos(2, 5));
+        let knight_out = choss.clone();
+        play(&mut choss, Pos(1, 0), Pos(2, 2));
+        play(&mut choss, Pos(2, 5), Pos(1, 7));
+        assert_eq!(choss.undo_stack.len(), 3);
+        // the snapshots don't carry the moves before them
+        assert!(choss
+            .undo_stack
+            .iter()
+            .all(|before| before.undo_stack.is_empty()));
+        choss.turned = true;
+        assert!(choss.undo());
+        assert!(choss.undo());
+        assert_eq!(choss.halfmove(), 1);
+        assert_eq!(choss.board, knight_out.board);
+        assert_eq!(choss.history, knight_out.history);
+        assert_eq!(choss.quiet_moves, 1);
+        assert!(choss.turned);
+        assert!(choss.undo());
+        assert_eq!(choss.board, start);
+        assert_eq!(choss.turn_color(), Color::White);
+        assert!(!choss.undo());
+        assert_eq!(choss.halfmove(), 0);
+    }
+
+    #[test]
+    fn analysis_is_ranked() {
+        let choss = ChossGame::new(Color::White);
+        let lines = choss.analyze(2, 3, &PieceValues::classical(), QUIESCENCE_DEPTH);
+        assert_eq!(lines.len(), 3);
+        let best = &crate::ai::negamax(
+            &choss.board,
+            Color::White,
+            2,
+            choss.win_condition,
+            choss.checks,
+            0.,
+            &PieceValues::classical(),
+            QUIESCENCE_DEPTH,
+        )[0];
+        assert_eq!(
+            (lines[0].0, lines[0].1, &lines[0].2),
+            (best.0, best.1, &best.2)
+        );
+        assert!(lines.windows(2).all(|pair| pair[0].0 >= pair[1].0));
+        // the continuation is black's answer to each move
+        for (_, _, _, pv) in lines {
+            let (pos, _) = pv[0];
+            let board = &choss.board;
+            assert!(matches!(board.get(pos), Some(Some((Color::Black, _)))));
+        }
+    }
+
+    #[test]
+    fn repetitions_and_quiet_moves() {
+        let mut choss = ChossGame::new(Color::White);
+        assert_eq!(choss.repetitions(), 1);
+        for round in 1..=2 {
+            play(&mut choss, Pos(1, 7), Pos(2, 5));
+            play(&mut choss, Pos(1, 0), Pos(2, 2));
+            play(&mut choss, Pos(2, 5), Pos(1, 7));
+            play(&mut choss, Pos(2, 2), Pos(1, 0));
+            assert_eq!(choss.repetitions(), round + 1);
+        }
+        assert!(choss.is_threefold_repetition());
+        assert!(choss.draw_claimable());
+        // a pawn move resets the count and the old positions can't come back
+        play(&mut choss, Pos(2, 6), Pos(2, 5));
+        assert_eq!(choss.repetitions(), 1);
+        assert_eq!(choss.quiet_moves, 0);
+        assert!(!choss.draw_claimable());
+        choss.quiet_moves = 99;
+        assert!(!choss.is_fifty_move_draw());
+        choss.quiet_moves = 100;
+        assert!(choss.is_fifty_move_draw());
+        assert!(choss.draw_claimable());
+    }
 }
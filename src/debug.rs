@@ -0,0 +1,112 @@
+use crate::{
+    choss::{ChossGame, HSIZE},
+    game::HoveredSquare,
+    keybindings::{Control, Controls},
+    pgn::pos2pgn,
+    pos::Pos,
+};
+use bevy::prelude::*;
+
+#[derive(Component)]
+struct DebugText;
+
+#[derive(Default)]
+pub struct DebugOverlay {
+    enabled: bool,
+}
+
+fn setup(mut commands: Commands, server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: server.load("fonts/RobotoMono-Regular.ttf"),
+        font_size: 14.0,
+        color: Color::rgb(0.8, 0.8, 0.8),
+    };
+    let text_alignment = TextAlignment {
+        vertical: VerticalAlign::Bottom,
+        horizontal: HorizontalAlign::Left,
+    };
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::with_section("", text_style, text_alignment),
+            ..Default::default()
+        })
+        .insert(DebugText);
+}
+
+fn toggle_debug(controls: Controls, mut overlay: ResMut<DebugOverlay>) {
+    if controls.just_pressed(Control::Debug) {
+        overlay.enabled = !overlay.enabled;
+    }
+}
+
+fn hover_info(choss: &ChossGame, pos: Pos) -> String {
+    // the square's names, then what's on it and how many moves it has right now
+    let mut res = format!(
+        "{} {:?} index {}\n",
+        pos2pgn(pos, choss.board.height),
+        pos,
+        choss.board.i(pos)
+    );
+    match choss.board.get(pos) {
+        Some(Some((color, piece))) => {
+            let moves =
+                choss
+                    .board
+                    .filter_safe_moves(*color, pos, piece.moves(&choss.board, pos, *color));
+            res += &format!("{:?} {}, {} legal moves", color, piece.name(), moves.len());
+        }
+        _ => res += "empty",
+    }
+    res
+}
+
+fn debug_overlay(
+    mut query_text: Query<(&mut Text, &mut Transform), With<DebugText>>,
+    overlay: Res<DebugOverlay>,
+    hovered: Res<HoveredSquare>,
+    choss: Res<ChossGame>,
+) {
+    if !(overlay.is_changed() || hovered.is_changed() || choss.is_changed()) {
+        return;
+    }
+    if let Ok((mut text, mut transform)) = query_text.get_single_mut() {
+        text.sections[0].value = match hovered.0 {
+            Some(pos) if overlay.enabled => hover_info(&choss, pos),
+            _ => "".to_string(),
+        };
+        // right of the board, under the analysis
+        transform.translation = Vec3::new(
+            HSIZE * choss.board.width as f32 + 16.,
+            -HSIZE * choss.board.height as f32,
+            0.,
+        );
+    }
+}
+
+pub struct DebugPlugin;
+
+impl Plugin for DebugPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(DebugOverlay::default())
+            .add_startup_system(setup)
+            .add_system(toggle_debug)
+            .add_system(debug_overlay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::Color as PieceColor;
+
+    #[test]
+    fn hovered_square_info() {
+        let choss = ChossGame::from_fen("4k/5/5/5/5/2N2/5/K4 w", PieceColor::White).unwrap();
+        let info = hover_info(&choss, Pos(2, 5));
+        assert!(info.starts_with("c3 Pos(2, 5) index 27\n"), "{}", info);
+        assert!(info.ends_with("White knight, 8 legal moves"), "{}", info);
+        assert!(hover_info(&choss, Pos(1, 1)).ends_with("empty"));
+        // the opponent's pieces are described too
+        assert!(hover_info(&choss, Pos(4, 0)).ends_with("Black king, 3 legal moves"));
+    }
+}
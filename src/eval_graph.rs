@@ -0,0 +1,132 @@
+use crate::{
+    choss::{ChossGame, HSIZE},
+    keybindings::{Control, Controls},
+    settings::Settings,
+};
+use bevy::prelude::*;
+
+// in pixels
+const GRAPH_WIDTH: f32 = 200.;
+const GRAPH_HEIGHT: f32 = 120.;
+// in pawns, bigger advantages are drawn at the edge of the graph
+const MAX_EVAL: f32 = 10.;
+// the graph only starts squeezing the moves together after that many half moves
+const MIN_HALFMOVES: usize = 20;
+
+#[derive(Component)]
+struct EvalGraphLine;
+
+// the evaluation for the player after each judged move, kept in sync with the game's eval log
+#[derive(Default)]
+pub struct EvalGraph {
+    pub points: Vec<(usize, f32)>,
+    // the match is over, the whole graph is shown even if it was hidden
+    pub end_screen: bool,
+    enabled: bool,
+}
+
+fn toggle_graph(controls: Controls, mut graph: ResMut<EvalGraph>) {
+    if controls.just_pressed(Control::EvalGraph) {
+        graph.enabled = !graph.enabled;
+    }
+}
+
+fn graph_points(points: &[(usize, f32)]) -> Vec<Vec2> {
+    // from (half move, eval) to pixels, from the middle of the graph's left edge
+    let halfmoves = points
+        .iter()
+        .map(|(halfmove, _)| halfmove + 1)
+        .max()
+        .unwrap_or(0)
+        .max(MIN_HALFMOVES);
+    points
+        .iter()
+        .map(|(halfmove, eval)| {
+            Vec2::new(
+                *halfmove as f32 / halfmoves as f32 * GRAPH_WIDTH,
+                eval.clamp(-MAX_EVAL, MAX_EVAL) / MAX_EVAL * GRAPH_HEIGHT / 2.,
+            )
+        })
+        .collect()
+}
+
+fn segment(from: Vec2, to: Vec2, width: f32, color: Color) -> SpriteBundle {
+    let diff = to - from;
+    let mut transform = Transform::from_translation(((from + to) / 2.).extend(0.5));
+    transform.rotation = Quat::from_rotation_z(diff.y.atan2(diff.x));
+    SpriteBundle {
+        sprite: Sprite {
+            color,
+            custom_size: Some(Vec2::new(diff.length(), width)),
+            ..Default::default()
+        },
+        transform,
+        ..Default::default()
+    }
+}
+
+fn display_graph(
+    mut commands: Commands,
+    query: Query<Entity, With<EvalGraphLine>>,
+    graph: Res<EvalGraph>,
+    choss: Res<ChossGame>,
+    settings: Res<Settings>,
+) {
+    if !(graph.is_changed() || choss.is_changed() || settings.is_changed()) {
+        return;
+    }
+    for entity in query.iter() {
+        commands.entity(entity).despawn();
+    }
+    if !(graph.enabled || graph.end_screen) {
+        return;
+    }
+    // right of the board, between the analysis and the debug overlay
+    let origin = Vec2::new(HSIZE * choss.board.width as f32 + settings.board_margin, 0.);
+    commands
+        .spawn_bundle(segment(
+            origin,
+            origin + Vec2::new(GRAPH_WIDTH, 0.),
+            1.,
+            Color::rgb(0.4, 0.4, 0.4),
+        ))
+        .insert(EvalGraphLine);
+    let points = graph_points(&graph.points);
+    for pair in points.windows(2) {
+        commands
+            .spawn_bundle(segment(
+                origin + pair[0],
+                origin + pair[1],
+                2.,
+                Color::rgb(0.8, 0.8, 0.8),
+            ))
+            .insert(EvalGraphLine);
+    }
+}
+
+pub struct EvalGraphPlugin;
+
+impl Plugin for EvalGraphPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(EvalGraph::default())
+            .add_system(toggle_graph)
+            .add_system(display_graph);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn graph_fits_its_box() {
+        let points = graph_points(&[(0, 0.), (1, 3.), (2, -50.)]);
+        assert_eq!(points[0], Vec2::new(0., 0.));
+        assert_eq!(points[1], Vec2::new(GRAPH_WIDTH / 20., GRAPH_HEIGHT * 0.15));
+        // a lost position stays on the graph
+        assert_eq!(points[2].y, -GRAPH_HEIGHT / 2.);
+        // a long game is squeezed into the same width
+        let long: Vec<(usize, f32)> = (0..100).map(|halfmove| (halfmove, 1.)).collect();
+        assert_eq!(graph_points(&long)[99].x, GRAPH_WIDTH * 0.99);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::board::Board;
+use crate::piece::{Action, Color};
+use crate::pos::Pos;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+
+// what happened in the game, for the systems that react to it without being part of it
+
+pub struct MovePlayed {
+    pub color: Color,
+    pub pos: Pos,
+    pub actions: Vec<Action>,
+}
+
+// the sounds only need to know these happened
+pub struct PieceCaptured;
+
+pub struct Check;
+
+pub struct Promotion;
+
+pub struct Undo {
+    // the half move the game went back to
+    pub halfmove: u32,
+}
+
+pub struct IllegalMove {
+    // the square the player tried to move to
+    pub pos: Pos,
+}
+
+pub struct GameOver {
+    // None for a draw
+    pub winner: Option<Color>,
+}
+
+#[derive(SystemParam)]
+pub struct GameEvents<'w, 's> {
+    moves: EventWriter<'w, 's, MovePlayed>,
+    captures: EventWriter<'w, 's, PieceCaptured>,
+    checks: EventWriter<'w, 's, Check>,
+    promotions: EventWriter<'w, 's, Promotion>,
+    undos: EventWriter<'w, 's, Undo>,
+    illegal_moves: EventWriter<'w, 's, IllegalMove>,
+    game_overs: EventWriter<'w, 's, GameOver>,
+}
+
+impl<'w, 's> GameEvents<'w, 's> {
+    pub fn send_move(
+        &mut self,
+        before: &Board,
+        after: &Board,
+        color: Color,
+        pos: Pos,
+        actions: &[Action],
+    ) {
+        // all the events of a move are sent together, so they're read in the same frame
+        self.moves.send(MovePlayed {
+            color,
+            pos,
+            actions: actions.to_vec(),
+        });
+        for (_, was, _) in before.diff(after) {
+            if matches!(was, Some((piece_color, _)) if piece_color != color) {
+                self.captures.send(PieceCaptured);
+            }
+        }
+        for action in actions {
+            if matches!(action, Action::Promotion(_)) {
+                self.promotions.send(Promotion);
+            }
+        }
+        let opponent = color.next();
+        if after.is_checked(opponent) {
+            self.checks.send(Check);
+        }
+    }
+
+    pub fn send_undo(&mut self, halfmove: u32) {
+        self.undos.send(Undo { halfmove });
+    }
+
+    pub fn send_illegal_move(&mut self, pos: Pos) {
+        self.illegal_moves.send(IllegalMove { pos });
+    }
+
+    pub fn send_game_over(&mut self, winner: Option<Color>) {
+        self.game_overs.send(GameOver { winner });
+    }
+}
+
+pub struct EventsPlugin;
+
+impl Plugin for EventsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<MovePlayed>()
+            .add_event::<PieceCaptured>()
+            .add_event::<Check>()
+            .add_event::<Promotion>()
+            .add_event::<Undo>()
+            .add_event::<IllegalMove>()
+            .add_event::<GameOver>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::app::Events;
+
+    fn take_the_rook(mut events: GameEvents) {
+        let before = Board::from_fen("4k/5/5/1r3/5/2N2/5/K4 w").unwrap();
+        let actions = [Action::Go(Pos(1, 3))];
+        let after = before.play(Color::White, Pos(2, 5), &actions);
+        events.send_move(&before, &after, Color::White, Pos(2, 5), &actions);
+    }
+
+    #[test]
+    fn capture_events() {
+        let mut world = World::new();
+        world.insert_resource(Events::<MovePlayed>::default());
+        world.insert_resource(Events::<PieceCaptured>::default());
+        world.insert_resource(Events::<Check>::default());
+        world.insert_resource(Events::<Promotion>::default());
+        world.insert_resource(Events::<Undo>::default());
+        world.insert_resource(Events::<IllegalMove>::default());
+        world.insert_resource(Events::<GameOver>::default());
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(take_the_rook);
+        stage.run(&mut world);
+
+        let moves = world.get_resource::<Events<MovePlayed>>().unwrap();
+        let played: Vec<_> = moves
+            .get_reader()
+            .iter(moves)
+            .map(|played| (played.color, played.pos))
+            .collect();
+        assert_eq!(played, vec![(Color::White, Pos(2, 5))]);
+        let captures = world.get_resource::<Events<PieceCaptured>>().unwrap();
+        assert_eq!(captures.get_reader().iter(captures).count(), 1);
+        let promotions = world.get_resource::<Events<Promotion>>().unwrap();
+        assert_eq!(promotions.get_reader().iter(promotions).count(), 0);
+        // the knight doesn't check the king from there
+        let checks = world.get_resource::<Events<Check>>().unwrap();
+        assert_eq!(checks.get_reader().iter(checks).count(), 0);
+    }
+}
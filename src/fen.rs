@@ -0,0 +1,524 @@
+use crate::board::Board;
+use crate::pgn::pos2pgn;
+use crate::piece::{Color, PawnStatus, Piece};
+use crate::pos::Pos;
+use std::fmt::Display;
+
+// the files are named from a to z
+const MAX_WIDTH: usize = 26;
+// the board texture grows with the ranks too
+const MAX_HEIGHT: usize = 26;
+// far more than any game lasts, and the half moves still fit in a u32 when counting on from there
+const MAX_MOVE_COUNTER: u32 = 1_000_000;
+
+// Choss-FEN is regular FEN where the board can be any size,
+// empty squares are counted with numbers that can go past 8
+#[derive(Debug, PartialEq, Eq)]
+pub enum FenError {
+    Empty,
+    UnknownPiece(char),
+    UnevenRanks,
+    TooWide,
+    TooTall,
+    BadSideToMove(String),
+    BadCastling(String),
+    BadEnPassant(String),
+    BadMoveCounter(String),
+    KingCount(Color),
+}
+
+// the last two FEN fields, the board alone doesn't keep them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MoveCounters {
+    // half moves since the last capture or pawn move
+    pub quiet_moves: u32,
+    // starts at 1 and goes up after black's move
+    pub fullmove_number: u32,
+}
+
+impl Default for MoveCounters {
+    fn default() -> Self {
+        MoveCounters {
+            quiet_moves: 0,
+            fullmove_number: 1,
+        }
+    }
+}
+
+impl Display for FenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FenError::Empty => write!(f, "there's no position"),
+            FenError::UnknownPiece(c) => write!(f, "'{}' is not a piece", c),
+            FenError::UnevenRanks => write!(f, "the ranks don't have the same width"),
+            FenError::TooWide => write!(f, "the ranks can't be wider than {} squares", MAX_WIDTH),
+            FenError::TooTall => write!(f, "there can't be more than {} ranks", MAX_HEIGHT),
+            FenError::BadSideToMove(side) => write!(f, "'{}' should be w or b", side),
+            FenError::BadCastling(rights) => {
+                write!(f, "'{}' should be - or some of KQkq", rights)
+            }
+            FenError::BadEnPassant(square) => {
+                write!(f, "'{}' is not an en passant square", square)
+            }
+            FenError::BadMoveCounter(counter) => {
+                write!(f, "'{}' is not a move count", counter)
+            }
+            FenError::KingCount(color) => write!(f, "{:?} needs exactly one king", color),
+        }
+    }
+}
+
+fn pawn(color: Color, status: PawnStatus) -> Piece {
+    Piece::Pawn {
+        orientation: if color == Color::White {
+            Pos(0, -1)
+        } else {
+            Pos(0, 1)
+        },
+        status,
+    }
+}
+
+fn letter2piece(letter: char) -> Option<Piece> {
+    match letter.to_ascii_uppercase() {
+        'P' => Some(pawn(Color::White, PawnStatus::CannotLeap)),
+        'N' => Some(Piece::Knight),
+        'B' => Some(Piece::Bishop),
+        'R' => Some(Piece::Rook),
+        'Q' => Some(Piece::Queen),
+        'K' => Some(Piece::King),
+        _ => None,
+    }
+}
+
+fn parse_placement(placement: &str) -> Result<Board, FenError> {
+    let mut rows = Vec::new();
+    for rank in placement.split('/') {
+        if rows.len() == MAX_HEIGHT {
+            return Err(FenError::TooTall);
+        }
+        let mut row = Vec::new();
+        let mut empty: usize = 0;
+        for c in rank.chars() {
+            if let Some(digit) = c.to_digit(10) {
+                empty = empty
+                    .checked_mul(10)
+                    .and_then(|empty| empty.checked_add(digit as usize))
+                    .filter(|empty| row.len() + empty <= MAX_WIDTH)
+                    .ok_or(FenError::TooWide)?;
+                continue;
+            }
+            if row.len() + empty >= MAX_WIDTH {
+                return Err(FenError::TooWide);
+            }
+            row.extend(vec![None; empty]);
+            empty = 0;
+            let piece = letter2piece(c).ok_or(FenError::UnknownPiece(c))?;
+            let color = if c.is_ascii_uppercase() {
+                Color::White
+            } else {
+                Color::Black
+            };
+            row.push(Some((color, piece)));
+        }
+        row.extend(vec![None; empty]);
+        rows.push(row);
+    }
+    let width = rows[0].len();
+    if width == 0 {
+        return Err(FenError::Empty);
+    }
+    if rows.iter().any(|row| row.len() != width) {
+        return Err(FenError::UnevenRanks);
+    }
+    let mut board = Board::new(width, rows.len());
+    board.squares = rows.into_iter().flatten().collect();
+    // pawns walk toward the other side and can leap from their starting rank
+    for i in 0..board.squares.len() {
+        if let Some((color, Piece::Pawn { .. })) = board.squares[i] {
+            let start_row = if color == Color::White {
+                board.height as i32 - 2
+            } else {
+                1
+            };
+            let status = if board.pos(i).1 == start_row {
+                PawnStatus::CanLeap
+            } else {
+                PawnStatus::CannotLeap
+            };
+            board.squares[i] = Some((color, pawn(color, status)));
+        }
+    }
+    for color in [Color::White, Color::Black] {
+        let kings = board
+            .squares
+            .iter()
+            .filter(|square| **square == Some((color, Piece::King)))
+            .count();
+        if kings != 1 {
+            return Err(FenError::KingCount(color));
+        }
+    }
+    Ok(board)
+}
+
+pub fn parse_square(board: &Board, square: &str) -> Option<Pos> {
+    let mut chars = square.chars();
+    let file = chars.next()?;
+    if !file.is_ascii_lowercase() {
+        return None;
+    }
+    let rank: i32 = chars.as_str().parse().ok()?;
+    let pos = Pos(file as i32 - 'a' as i32, board.height as i32 - rank);
+    Some(pos).filter(|pos| board.on_board(*pos))
+}
+
+fn castling_rooks(board: &Board, color: Color) -> Vec<(Pos, char)> {
+    // the rooks that can still castle, with the letter of their side of the king,
+    // a letter only names the outermost one of its side
+    let king_pos = match board.king_pos(color) {
+        Some(king_pos) if board.castling && board.move_count(king_pos) == 0 => king_pos,
+        _ => return Vec::new(),
+    };
+    let rooks: Vec<Pos> = board
+        .pieces_of(color)
+        .filter(|(pos, piece)| {
+            // a rook next to the king, or one square away, never castles
+            *piece == Piece::Rook
+                && pos.1 == king_pos.1
+                && (pos.0 - king_pos.0).abs() >= 3
+                && board.move_count(*pos) == 0
+        })
+        .map(|(pos, _)| pos)
+        .collect();
+    let king_side = rooks
+        .iter()
+        .filter(|pos| pos.0 > king_pos.0)
+        .max_by_key(|pos| pos.0);
+    let queen_side = rooks
+        .iter()
+        .filter(|pos| pos.0 < king_pos.0)
+        .min_by_key(|pos| pos.0);
+    [(king_side, 'K'), (queen_side, 'Q')]
+        .into_iter()
+        .filter_map(|(rook, side)| {
+            let side = if color == Color::White {
+                side
+            } else {
+                side.to_ascii_lowercase()
+            };
+            rook.map(|pos| (*pos, side))
+        })
+        .collect()
+}
+
+fn parse_castling(board: &mut Board, rights: &str) -> Result<(), FenError> {
+    board.castling = true;
+    let mut granted = Vec::new();
+    for color in [Color::White, Color::Black] {
+        let rooks: Vec<Pos> = castling_rooks(board, color)
+            .into_iter()
+            .filter(|(_, side)| rights.contains(*side))
+            .map(|(pos, side)| {
+                granted.push(side);
+                pos
+            })
+            .collect();
+        // the other rooks of the king's rank count as moved, so only the named ones can castle
+        let king_rank = board.king_pos(color).map(|king_pos| king_pos.1);
+        let unnamed: Vec<Pos> = board
+            .pieces_of(color)
+            .filter(|(pos, piece)| {
+                *piece == Piece::Rook
+                    && Some(pos.1) == king_rank
+                    && board.move_count(*pos) == 0
+                    && !rooks.contains(pos)
+            })
+            .map(|(pos, _)| pos)
+            .collect();
+        for pos in unnamed {
+            let i = board.i(pos);
+            board.move_counts[i] = 1;
+        }
+    }
+    // every letter needs a rook to castle with, and only once
+    if rights.len() != granted.len() || !rights.chars().all(|side| granted.contains(&side)) {
+        return Err(FenError::BadCastling(rights.to_string()));
+    }
+    Ok(())
+}
+
+fn parse_counter(counter: Option<&str>, default: u32, min: u32) -> Result<u32, FenError> {
+    match counter {
+        None => Ok(default),
+        Some(counter) => counter
+            .parse()
+            .ok()
+            .filter(|count| (min..=MAX_MOVE_COUNTER).contains(count))
+            .ok_or_else(|| FenError::BadMoveCounter(counter.to_string())),
+    }
+}
+
+fn parse_side(side: Option<&str>) -> Result<Color, FenError> {
+    match side {
+        None | Some("w") => Ok(Color::White),
+        Some("b") => Ok(Color::Black),
+        Some(other) => Err(FenError::BadSideToMove(other.to_string())),
+    }
+}
+
+// the side to move and the move counters, what the board itself doesn't keep
+pub fn parse_turn(fen: &str) -> Result<(Color, MoveCounters), FenError> {
+    let fields: Vec<&str> = fen.split_whitespace().collect();
+    let side = parse_side(fields.get(1).copied())?;
+    let counters = MoveCounters {
+        quiet_moves: parse_counter(fields.get(4).copied(), 0, 0)?,
+        fullmove_number: parse_counter(fields.get(5).copied(), 1, 1)?,
+    };
+    Ok((side, counters))
+}
+
+impl Board {
+    pub fn from_fen(fen: &str) -> Result<Board, FenError> {
+        let mut fields = fen.split_whitespace();
+        let mut board = parse_placement(fields.next().ok_or(FenError::Empty)?)?;
+        // the side to move tells which pawn can be taken en passant
+        let side = parse_side(fields.next())?;
+        match fields.next() {
+            None | Some("-") => {}
+            Some(rights) => parse_castling(&mut board, rights)?,
+        }
+        match fields.next() {
+            None | Some("-") => {}
+            Some(square) => {
+                // the pawn that just leaped is in front of the en passant square
+                let leaper = side.next();
+                let pawn_pos = parse_square(&board, square).map(|pos| {
+                    pos + if leaper == Color::White {
+                        Pos(0, -1)
+                    } else {
+                        Pos(0, 1)
+                    }
+                });
+                match pawn_pos.and_then(|pos| board.get(pos).map(|square| (pos, *square))) {
+                    Some((pos, Some((color, Piece::Pawn { .. })))) if color == leaper => {
+                        board.set(pos, Some((color, pawn(color, PawnStatus::JustLeaped))))
+                    }
+                    _ => return Err(FenError::BadEnPassant(square.to_string())),
+                }
+            }
+        }
+        Ok(board)
+    }
+
+    pub fn to_fen(&self, turn_color: Color) -> String {
+        let placement = self
+            .squares
+            .chunks(self.width)
+            .map(|rank| {
+                let mut res = String::new();
+                let mut empty = 0;
+                for square in rank {
+                    match square {
+                        None => empty += 1,
+                        Some((color, piece)) => {
+                            if empty > 0 {
+                                res += &empty.to_string();
+                                empty = 0;
+                            }
+                            res.push(if *color == Color::White {
+                                piece.letter()
+                            } else {
+                                piece.letter().to_ascii_lowercase()
+                            });
+                        }
+                    }
+                }
+                if empty > 0 {
+                    res += &empty.to_string();
+                }
+                res
+            })
+            .collect::<Vec<String>>()
+            .join("/");
+        let mut castling: Vec<char> = [Color::White, Color::Black]
+            .into_iter()
+            .flat_map(|color| castling_rooks(self, color))
+            .map(|(_, side)| side)
+            .collect();
+        // in the usual order, KQkq
+        castling.sort_by_key(|side| "KQkq".find(*side));
+        let castling = if castling.is_empty() {
+            "-".to_string()
+        } else {
+            castling.into_iter().collect()
+        };
+        // behind the pawn of the other side that just leaped
+        let en_passant = self
+            .pieces_of(turn_color.next())
+            .find_map(|(pos, piece)| match piece {
+                Piece::Pawn {
+                    orientation,
+                    status: PawnStatus::JustLeaped,
+                } => Some(pos2pgn(pos + orientation * -1, self.height)),
+                _ => None,
+            })
+            .unwrap_or_else(|| "-".to_string());
+        format!(
+            "{} {} {} {}",
+            placement,
+            turn_color.letter(),
+            castling,
+            en_passant
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::make_board::standard_board;
+    use crate::piece::Action;
+
+    fn parse_fen(fen: &str) -> Result<(Board, Color, MoveCounters), FenError> {
+        let board = Board::from_fen(fen)?;
+        let (side, counters) = parse_turn(fen)?;
+        Ok((board, side, counters))
+    }
+
+    #[test]
+    fn standard_position() {
+        let (board, side, counters) =
+            parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        assert_eq!(side, Color::White);
+        assert_eq!(counters, MoveCounters::default());
+        assert_eq!(board.squares, standard_board().squares);
+        assert!(board.castling);
+    }
+
+    #[test]
+    fn round_trip() {
+        let fen = standard_board().to_fen(Color::White);
+        assert_eq!(fen, "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -");
+        let (board, side, _) = parse_fen(&fen).unwrap();
+        assert_eq!(side, Color::White);
+        assert_eq!(board, standard_board());
+        assert_eq!(board.to_fen(side), fen);
+        // the rights that are gone stay gone
+        for fen in [
+            "r3k2r/8/8/8/8/8/8/R3K2R b Kq -",
+            "r3k2r/8/8/8/8/8/8/R3K2R w - -",
+            "k11/12/12/12/12/11K w - -",
+        ] {
+            let (board, side, _) = parse_fen(fen).unwrap();
+            assert_eq!(board.to_fen(side), fen);
+        }
+    }
+
+    #[test]
+    fn castling_rights_name_the_outer_rooks() {
+        // b1 and b8 could castle too, but the letters only name a1 and a8
+        let fen = "rr2k2r/8/8/8/8/8/8/RR2K2R w KQkq -";
+        let (board, side, _) = parse_fen(fen).unwrap();
+        assert_eq!(board.to_fen(side), fen);
+        assert_eq!(board.move_count(Pos(1, 7)), 1);
+        assert!(!board
+            .moves(Color::White, true)
+            .iter()
+            .any(|(_, actions)| actions.contains(&Action::Castle(Pos(1, 7), Pos(3, 7)))));
+        // a right needs a rook to castle with
+        for rights in ["KQq", "KK", "Kkk"] {
+            assert_eq!(
+                parse_fen(&format!("r3k3/8/8/8/8/8/8/4K2R w {} -", rights)).err(),
+                Some(FenError::BadCastling(rights.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn any_board_size() {
+        let board = Board::from_fen("k11/12/12/12/12/11K").unwrap();
+        assert_eq!((board.width, board.height), (12, 6));
+        assert_eq!(board.king_pos(Color::White), Some(Pos(11, 5)));
+        assert_eq!(board.king_pos(Color::Black), Some(Pos(0, 0)));
+    }
+
+    #[test]
+    fn en_passant() {
+        let (board, side, _) = parse_fen("4k/5/5/5/2P2/5/5/K4 b - c3").unwrap();
+        assert_eq!(side, Color::Black);
+        assert_eq!(
+            board.get(Pos(2, 4)),
+            Some(&Some((
+                Color::White,
+                pawn(Color::White, PawnStatus::JustLeaped)
+            )))
+        );
+    }
+
+    #[test]
+    fn en_passant_round_trip() {
+        let board = standard_board().play(Color::White, Pos(4, 6), &[Action::Go(Pos(4, 4))]);
+        let fen = board.to_fen(Color::Black);
+        assert_eq!(
+            fen,
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3"
+        );
+        let (parsed, side, _) = parse_fen(&fen).unwrap();
+        assert_eq!(side, Color::Black);
+        assert_eq!(parsed, board);
+        assert_eq!(parsed.to_fen(side), fen);
+        // the pawn can't be taken en passant anymore once black has moved
+        let board = board.play(Color::Black, Pos(0, 1), &[Action::Go(Pos(0, 2))]);
+        assert!(board.to_fen(Color::White).ends_with(" KQkq -"));
+    }
+
+    #[test]
+    fn malformed() {
+        let error = |fen: &str| parse_fen(fen).err();
+        assert_eq!(error(""), Some(FenError::Empty));
+        assert_eq!(error("kx3/4K"), Some(FenError::UnknownPiece('x')));
+        assert_eq!(error("k4/3K"), Some(FenError::UnevenRanks));
+        assert_eq!(error("k25/25K"), None);
+        assert_eq!(error("k26/26K"), Some(FenError::TooWide));
+        assert_eq!(error("k25K/26"), Some(FenError::TooWide));
+        assert_eq!(
+            error("99999999999999999999k/K99999999999999999999"),
+            Some(FenError::TooWide)
+        );
+        let ranks = |count| format!("k4/{}4K", "5/".repeat(count - 2));
+        assert_eq!(error(&ranks(MAX_HEIGHT)), None);
+        assert_eq!(error(&ranks(MAX_HEIGHT + 1)), Some(FenError::TooTall));
+        assert_eq!(error(&ranks(5000)), Some(FenError::TooTall));
+        assert_eq!(
+            error("k3/3K z"),
+            Some(FenError::BadSideToMove("z".to_string()))
+        );
+        assert_eq!(error("k3/4"), Some(FenError::KingCount(Color::White)));
+        assert_eq!(
+            error("k3/3K w KX"),
+            Some(FenError::BadCastling("KX".to_string()))
+        );
+        assert_eq!(
+            error("k3/3K w - a9"),
+            Some(FenError::BadEnPassant("a9".to_string()))
+        );
+        assert_eq!(
+            error("k3/3K w - - x 1"),
+            Some(FenError::BadMoveCounter("x".to_string()))
+        );
+        assert_eq!(
+            error("k3/3K w - - 0 0"),
+            Some(FenError::BadMoveCounter("0".to_string()))
+        );
+        assert_eq!(error("k3/3K w - - 1000000 1000000"), None);
+        assert_eq!(
+            error("k3/3K b - - 0 1000001"),
+            Some(FenError::BadMoveCounter("1000001".to_string()))
+        );
+        assert_eq!(
+            error("k3/3K b - - 4294967295 1"),
+            Some(FenError::BadMoveCounter("4294967295".to_string()))
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use crate::keybindings::{Control, Controls};
+use crate::lang::Lang;
+use crate::notice::Notice;
+use crate::settings::{on_off, Settings};
+use bevy::prelude::*;
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::utils::{Duration, Instant};
+
+#[cfg(not(target_arch = "wasm32"))]
+fn frame_wait(frame_time: Duration, fps_cap: u32) -> Duration {
+    // how long to wait for the frame to last its share of a second
+    Duration::from_secs_f64(1. / fps_cap as f64).saturating_sub(frame_time)
+}
+
+fn apply_vsync(settings: Res<Settings>, mut windows: ResMut<Windows>) {
+    // the window is created with the setting, this is for when it's changed while playing
+    if !settings.is_changed() || settings.is_added() {
+        return;
+    }
+    if let Some(window) = windows.get_primary_mut() {
+        if window.vsync() != settings.vsync {
+            window.set_vsync(settings.vsync);
+        }
+    }
+}
+
+// only the render loop waits, the AI searches in the task pool or between frames and isn't slowed down
+#[cfg(not(target_arch = "wasm32"))]
+fn limit_fps(settings: Res<Settings>, mut frame_start: Local<Option<Instant>>) {
+    if let (Some(fps_cap), Some(start)) = (settings.fps_cap, *frame_start) {
+        std::thread::sleep(frame_wait(start.elapsed(), fps_cap));
+    }
+    *frame_start = Some(Instant::now());
+}
+
+fn toggle_vsync(
+    controls: Controls,
+    mut settings: ResMut<Settings>,
+    mut notice: ResMut<Notice>,
+    lang: Res<Lang>,
+) {
+    if controls.just_pressed(Control::Vsync) {
+        settings.vsync = !settings.vsync;
+        notice.show(lang.format("notice.vsync", &[&on_off(&lang, settings.vsync)]));
+    }
+}
+
+pub struct FrameRatePlugin;
+
+impl Plugin for FrameRatePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(toggle_vsync.before("settings"))
+            .add_system(apply_vsync.after("settings"));
+        // the browser already paces the frames with the screen
+        #[cfg(not(target_arch = "wasm32"))]
+        app.add_system_to_stage(CoreStage::Last, limit_fps);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_wait_for_the_cap() {
+        assert_eq!(
+            frame_wait(Duration::from_millis(10), 50),
+            Duration::from_millis(10)
+        );
+        // a slow frame doesn't wait at all
+        assert_eq!(frame_wait(Duration::from_millis(30), 50), Duration::ZERO);
+    }
+}
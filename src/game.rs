@@ -1,15 +1,49 @@
+#[cfg(not(target_arch = "wasm32"))]
+use crate::pgn::save_game;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::recovery::{clear_recovery, from_recovery, load_recovery, save_recovery, to_recovery};
 use crate::{
-    ai::negamax,
-    character::{Character, CharacterPlugin, DialogueFace, DialogueText, Say},
-    choss::{draw_choss, piece_tex_name, ChossGame, SIZE},
+    ai::{negamax_pv, negamax_timed_pv, PieceValues, ScoredLines, Search, TranspositionTable},
+    analysis::AnalysisPlugin,
+    board::{Board, Square},
+    character::{Character, CharacterPlugin, DialogueFace, DialogueLog, DialogueText, Say},
+    choss::{
+        draw_choss, draw_coordinates, piece_tex_name, redraw_choss, BoardOrientation,
+        CaptureMarker, ChossGame, WinCondition, HSIZE, SIZE,
+    },
+    debug::DebugPlugin,
+    eval_graph::{EvalGraph, EvalGraphPlugin},
+    events::{EventsPlugin, GameEvents, IllegalMove, MovePlayed, Undo},
+    frame_rate::FrameRatePlugin,
+    help::HelpPlugin,
+    keybindings::{key_name, Control, Controls, KeyBindingsPlugin},
+    lang::{Lang, LangPlugin},
+    make_board::{with_handicap, Setup},
+    move_log::MoveLogPlugin,
+    notice::{Notice, NoticePlugin},
+    pace::{MoveTimes, PacePlugin},
+    pgn::{annotation, deserialize_annotation, Annotation, PgnGame},
     piece::{Action, Color as PieceColor, Piece},
+    piece_set::{piece_tex_path, PieceSet, PieceSetPlugin},
     pos::Pos,
-    utils::screen_to_world,
+    puzzle::{is_expected, parse_move, parse_puzzles, Puzzle},
+    settings::{Settings, SettingsPlugin},
+    sounds::SoundsPlugin,
+    tutorial::TutorialPlugin,
+    utils::{copy_to_clipboard, paste_from_clipboard, screen_to_world},
 };
 use bevy::prelude::*;
-use bevy::{render::color::Color, tasks::Task};
-use rand::seq::SliceRandom;
+use bevy::{
+    render::color::Color,
+    tasks::{AsyncComputeTaskPool, Task},
+    utils::Instant,
+};
+use futures_lite::future;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 
 #[derive(Component)]
 struct MovingTo(Transform);
@@ -20,7 +54,7 @@ struct Die;
 #[derive(Component)]
 struct PromoteTo(Piece, PieceColor);
 
-#[derive(PartialEq, Eq)]
+#[derive(PartialEq, Eq, Debug)]
 enum GameStatus {
     Playing,
     Draw,
@@ -50,29 +84,279 @@ struct Game {
     last_state: Option<ChossGame>,
     carl_lines: Vec<String>,
     last_move_time: f64,
+    // the game before the player's last move, and how many times they can ask to go back to it
+    takeback_state: Option<ChossGame>,
+    takebacks: u32,
+    // how many more times Carl can undo one of his moves this match
+    undos: u32,
+    // set while solving puzzles instead of playing a match
+    puzzle: Option<PuzzleRun>,
+    // how much each judged move lost, for the summary at the end of the match
+    eval_log: Vec<EvalEntry>,
+    end_reason: Option<EndReason>,
+    // what the piece sprites show and if the board was turned around,
+    // so placing only has to touch the squares that changed
+    shown: Option<(Board, bool)>,
+    // the player's answer the AI expects, with the half move it would be played on
+    predicted: Option<(u32, Pos, Vec<Action>)>,
+    // once Carl has been beaten, the player can undo his undos too
+    undo_unlocked: bool,
+    // the game and its record right before Carl's last undo, to put him back there
+    undone: Option<(ChossGame, PgnGame)>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct EvalEntry {
+    halfmove: usize,
+    color: PieceColor,
+    // in pawns, negative if the move was better than expected
+    loss: f32,
+    #[serde(deserialize_with = "deserialize_annotation")]
+    annotation: Annotation,
+    // in pawns for the player, how the position looks once the move is played
+    eval: f32,
+}
+
+impl EvalEntry {
+    fn new(
+        halfmove: usize,
+        color: PieceColor,
+        before: f32,
+        after: f32,
+        eval: f32,
+        material: f32,
+    ) -> Self {
+        // mate scores are infinite, keep them to a big but finite swing
+        let loss = after.clamp(-20., 20.) - before.clamp(-20., 20.);
+        EvalEntry {
+            halfmove,
+            color,
+            loss,
+            annotation: annotation(loss / threshold_scale(material)),
+            eval: eval.clamp(-20., 20.),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct GameSummary {
+    blunders: [u32; 2],
+    mistakes: [u32; 2],
+    // the move that lost the most, and who played it
+    biggest_swing: Option<(usize, PieceColor, f32)>,
+    // in centipawns
+    average_loss: [f32; 2],
+}
+
+impl GameSummary {
+    fn new(eval_log: &[EvalEntry]) -> Self {
+        let mut summary = GameSummary {
+            blunders: [0; 2],
+            mistakes: [0; 2],
+            biggest_swing: None,
+            average_loss: [0.; 2],
+        };
+        let mut judged = [0; 2];
+        for entry in eval_log {
+            let side = entry.color as usize;
+            match entry.annotation {
+                "??" => summary.blunders[side] += 1,
+                "?" => summary.mistakes[side] += 1,
+                _ => {}
+            }
+            // a good move doesn't make up for the bad ones
+            summary.average_loss[side] += entry.loss.max(0.) * 100.;
+            judged[side] += 1;
+            match summary.biggest_swing {
+                Some((_, _, loss)) if loss >= entry.loss => {}
+                _ => summary.biggest_swing = Some((entry.halfmove, entry.color, entry.loss)),
+            }
+        }
+        for (average_loss, judged) in summary.average_loss.iter_mut().zip(judged) {
+            if judged > 0 {
+                *average_loss /= judged as f32;
+            }
+        }
+        summary
+    }
+
+    fn text(&self, player: PieceColor, lang: &Lang) -> String {
+        let (you, them) = (player as usize, player.next() as usize);
+        let mut res = lang.format(
+            "summary.counts",
+            &[
+                &self.blunders[you],
+                &self.blunders[them],
+                &self.mistakes[you],
+                &self.mistakes[them],
+                &format!("{:.0}", self.average_loss[you]),
+                &format!("{:.0}", self.average_loss[them]),
+            ],
+        );
+        if let Some((halfmove, color, loss)) = self.biggest_swing {
+            res += &lang.format(
+                "summary.swing",
+                &[
+                    &format!("{:.1}", loss),
+                    &(halfmove / 2 + 1),
+                    &lang.get(if color == player {
+                        "summary.you"
+                    } else {
+                        "summary.them"
+                    }),
+                ],
+            );
+        }
+        res
+    }
+}
+
+struct PuzzleRun {
+    index: usize,
+    // how far we are in the solution
+    step: usize,
+    // the player's move that was already checked, waiting to be played
+    accepted: Option<(Pos, Vec<Action>)>,
+    solved: bool,
+}
+
+const MAX_TAKEBACKS: u32 = 3;
+// one undo per line in Carl's escalation
+const MAX_UNDOS: u32 = 5;
+
+// in seconds, how long a square stays red after an illegal move on it
+const ILLEGAL_FLASH: f64 = 0.3;
+
+// roughly the player's material at the start of a game on the halved board
+const REFERENCE_VALUE: f32 = 25.;
+
+fn automatic_draw(choss: &ChossGame, claim_draws: bool) -> bool {
+    // with claimable draws the game goes on until a player asks for it
+    !claim_draws && choss.draw_claimable()
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum EndReason {
+    Checkmate,
+    // the variant's own goal, like three checks or the king on the hill
+    Variant,
+    Stalemate,
+    InsufficientMaterial,
+    Repetition,
+    FiftyMoves,
+    MoveLimit,
+    Claimed,
+}
+
+impl EndReason {
+    fn key(self) -> &'static str {
+        match self {
+            EndReason::Checkmate => "end.checkmate",
+            EndReason::Variant => "end.variant",
+            EndReason::Stalemate => "end.stalemate",
+            EndReason::InsufficientMaterial => "end.insufficient_material",
+            EndReason::Repetition => "end.repetition",
+            EndReason::FiftyMoves => "end.fifty_moves",
+            EndReason::MoveLimit => "end.move_limit",
+            EndReason::Claimed => "end.claimed",
+        }
+    }
+}
+
+fn game_over(
+    choss: &ChossGame,
+    color: PieceColor,
+    settings: &Settings,
+) -> Option<(GameStatus, EndReason)> {
+    // the game after color's move, from the player's point of view
+    let won_by = |winner: PieceColor| {
+        if winner == choss.player {
+            GameStatus::Win
+        } else {
+            GameStatus::Loss
+        }
+    };
+    if let Some(winner) = choss.winner() {
+        Some((won_by(winner), EndReason::Variant))
+    } else if choss.win_condition != WinCondition::KingOfTheHill
+        && choss.board.has_insufficient_material()
+    {
+        // nobody can mate anymore, typically once the last piece next to the kings was taken
+        Some((GameStatus::Draw, EndReason::InsufficientMaterial))
+    } else if choss.board.moves(color.next(), true).is_empty() {
+        if choss.board.is_checked(color.next()) {
+            Some((won_by(color), EndReason::Checkmate))
+        } else {
+            Some((GameStatus::Draw, EndReason::Stalemate))
+        }
+    } else if automatic_draw(choss, settings.claim_draws) {
+        if choss.is_threefold_repetition() {
+            Some((GameStatus::Draw, EndReason::Repetition))
+        } else {
+            Some((GameStatus::Draw, EndReason::FiftyMoves))
+        }
+    } else if matches!(settings.move_limit, Some(limit) if choss.halfmove() >= 2 * limit) {
+        // a safety valve for games that would otherwise never end
+        Some((GameStatus::Draw, EndReason::MoveLimit))
+    } else {
+        None
+    }
+}
+
+fn threshold_scale(material: f32) -> f32 {
+    // a 2 points swing is a blunder in an endgame but barely matters with queens on the board,
+    // so the eval thresholds grow and shrink with the material left
+    (material / REFERENCE_VALUE).clamp(0.25, 2.)
+}
+
+fn carl_lines() -> Vec<String> {
+    // the keys of Carl's escalating undo lines, popped from the end
+    (1..=5).rev().map(|i| format!("carl.undo.{}", i)).collect()
 }
 
 impl Game {
     fn new() -> Self {
-        let mut carl_lines = vec![
-            "Oh... that won't do.".to_string(),
-            "Mh, that doesn't work.".to_string(),
-            "Nope, this is not good.".to_string(),
-            "Ugh, I need another move !".to_string(),
-            "Again ...".to_string(),
-        ];
-        carl_lines.reverse();
         Game {
-            carl_lines,
+            carl_lines: carl_lines(),
             ..Default::default()
         }
     }
 
+    fn new_match(&mut self) {
+        // every match gets the full dialogue again, including Carl's escalating undo lines
+        self.lines_sent.clear();
+        self.carl_lines = carl_lines();
+        self.last_eval = Some(0.);
+        self.cached_moves = Vec::new();
+        self.last_state = None;
+        self.takeback_state = None;
+        self.eval_log.clear();
+        self.predicted = None;
+        self.undone = None;
+        self.end_reason = None;
+        // Carl doesn't do takebacks
+        self.takebacks = if self.opponent == 0 { MAX_TAKEBACKS } else { 0 };
+        // and Alice doesn't undo
+        self.undos = if self.opponent == 1 { MAX_UNDOS } else { 0 };
+    }
+
+    fn request_takeback(&mut self) -> bool {
+        // if the opponent agrees, the undo will restore the game from before the player's move
+        if self.takebacks == 0 || self.takeback_state.is_none() {
+            return false;
+        }
+        self.takebacks -= 1;
+        self.last_state = self.takeback_state.take();
+        true
+    }
+
     fn opponent(&self) -> Entity {
         self.opponents[self.opponent]
     }
 
-    fn get_dialogue(&mut self, score: f32) -> Option<(String, String)> {
+    // the face and the key of the line to say after the AI's search
+    fn get_dialogue(&mut self, score: f32, material: f32) -> Option<(String, String)> {
+        let scale = threshold_scale(material);
         let mut res = None;
         if self.opponent == 0 {
             // Alice's dialogues
@@ -82,33 +366,30 @@ impl Game {
                     "prev e: {}, new e: {}, diff: {}",
                     last_eval, score, score_diff
                 );
-                if score < -5. {
-                    res = Some(("neutral", "Oof, now I'm in trouble ..."));
+                if score < -5. * scale {
+                    res = Some(("neutral", "alice.trouble"));
                 }
-                if score_diff.abs() > 2. {
+                if score_diff.abs() > 2. * scale {
                     if score_diff < 0. {
                         // player made a mistake (probably)
-                        res = Some((
-                            "neutral",
-                            "Oh, that looks like a mistake ?\nWell it happens.",
-                        ));
+                        res = Some(("neutral", "alice.player_mistake"));
                     } else if score < 0. {
                         // alice made a mistake (probably)
-                        res = Some(("weary", "Ugh, I think I blundered...\nDon't you wish you could \nundo your moves sometimes ?"));
+                        res = Some(("weary", "alice.blunder"));
                     }
                 }
             } else {
-                res = Some(("happy", "You got it !\nNow may the best player win !"));
+                res = Some(("happy", "alice.ready"));
             }
         } else if self.opponent == 1 {
             // Carl's dialogues
             if let Some(last_eval) = self.last_eval {
                 let score_diff = last_eval - score;
-                if score_diff.abs() > 2. {
+                if score_diff.abs() > 2. * scale {
                     if score_diff < 0. {
                         // player made a mistake (probably)
-                        res = Some(("smug", "All according to my calculations."));
-                    } else if self.should_undo(score) {
+                        res = Some(("smug", "carl.player_mistake"));
+                    } else if self.should_undo(score, material) {
                         // Carl made a mistake (probably)
                         let line = if self.carl_lines.len() > 1 {
                             self.carl_lines.pop().unwrap()
@@ -117,7 +398,7 @@ impl Game {
                         };
                         return Some(("neutral".to_string(), line));
                     } else if score < 0. {
-                        res = Some(("panicked", "Nothing is working !!"));
+                        res = Some(("panicked", "carl.panicked"));
                     }
                 }
             }
@@ -133,7 +414,8 @@ impl Game {
     }
 
     fn cached_moves_mut(&mut self, turn: u32) -> Option<&mut Vec<(f32, Pos, Vec<Action>)>> {
-        if turn == self.turn {
+        // nothing is cached yet when the AI opens the game
+        if turn == self.turn && !self.cached_moves.is_empty() {
             return Some(&mut self.cached_moves);
         }
         None
@@ -144,10 +426,25 @@ impl Game {
         self.turn = turn;
     }
 
-    fn should_undo(&self, score: f32) -> bool {
-        if self.opponent == 1 && self.cached_moves.len() > 0 {
+    fn ai_claims_draw(&self, choss: &ChossGame) -> bool {
+        // the AI only takes the draw if it doesn't think it's winning
+        choss.draw_claimable() && !matches!(self.last_eval, Some(eval) if eval > 0.)
+    }
+
+    fn move_ready(&self, now: f64, move_delay: f64) -> bool {
+        now - self.last_move_time >= move_delay
+    }
+
+    fn undos_left(&self) -> Option<u32> {
+        // Alice never undoes so she has no counter
+        (self.opponent == 1).then_some(self.undos)
+    }
+
+    fn should_undo(&self, score: f32, material: f32) -> bool {
+        if self.opponent == 1 && self.undos > 0 && self.cached_moves.len() > 0 {
             if let Some(last_eval) = self.last_eval {
-                return last_eval - score > 2. && score < 2.;
+                let threshold = 2. * threshold_scale(material);
+                return last_eval - score > threshold && score < threshold;
             }
         }
         false
@@ -169,36 +466,112 @@ impl UndoingComp {
             ascending: true,
         }
     }
+
+    fn advance(&mut self, delta: f32, skip: bool) -> (bool, bool) {
+        // returns if the board must be replaced this frame and if the undoing is over,
+        // skipping the animation does both at once
+        if skip {
+            let zenith = self.ascending;
+            self.speed = 0.;
+            self.ascending = false;
+            return (zenith, true);
+        }
+        if self.ascending {
+            self.speed += self.max_speed * 0.5 * delta;
+            if self.speed > self.max_speed {
+                // "zenith" of the undoing, we can replace the board here
+                self.speed = self.max_speed;
+                self.ascending = false;
+                return (true, false);
+            }
+        } else {
+            self.speed -= self.max_speed * 0.5 * delta;
+            if self.speed < 400. {
+                self.speed = 0.;
+                return (false, true);
+            }
+        }
+        (false, false)
+    }
+}
+
+fn redo(
+    game: &mut Game,
+    choss: &mut ChossGame,
+    last_move: &mut LastMove,
+    pgn: &mut PgnGame,
+) -> bool {
+    // back to the position Carl ran from, without alternatives he has to play on from there
+    match game.undone.take() {
+        Some((undone_choss, undone_pgn)) => {
+            *choss = undone_choss;
+            *pgn = undone_pgn;
+            game.cached_moves.clear();
+            game.to_play = None;
+            game.predicted = None;
+            last_move.0 = None;
+            game.status = GameStatus::Placing;
+            true
+        }
+        None => false,
+    }
+}
+
+fn rewind(game: &mut Game, choss: &mut ChossGame, last_move: &mut LastMove, pgn: &mut PgnGame) {
+    if game.undo_unlocked && game.opponent == 1 {
+        // kept in case the player undoes Carl's undo
+        game.undone = Some((choss.clone(), pgn.clone()));
+    }
+    // go back to the game saved before the move being undone, the record follows the Undo event
+    *choss = game.last_state.clone().unwrap();
+    last_move.0 = None;
+    game.status = GameStatus::Placing;
+}
+
+fn rewind_player_move(game: &mut Game, choss: &mut ChossGame, last_move: &mut LastMove) -> bool {
+    // back to the player's last move, taking the opponent's answer back with it,
+    // it costs a takeback like asking for one
+    if game.takebacks == 0
+        || !choss
+            .undo_stack
+            .iter()
+            .any(|before| before.turn_color() == choss.player)
+    {
+        return false;
+    }
+    game.takebacks -= 1;
+    while choss.undo() && choss.turn_color() != choss.player {}
+    // what the AI knew about the moves after this one is gone with them
+    game.cached_moves.clear();
+    game.to_play = None;
+    game.predicted = None;
+    game.last_state = None;
+    game.takeback_state = None;
+    game.undone = None;
+    game.last_eval = None;
+    last_move.0 = None;
+    game.status = GameStatus::Placing;
+    true
 }
 
+// the opponents in the order they're met, with their faces
+pub const OPPONENTS: [(&str, &[&str]); 2] = [
+    ("Alice", &["happy", "neutral", "weary"]),
+    ("Carl Blok", &["exhausted", "neutral", "panicked", "smug"]),
+];
+
 fn create_opponents(mut commands: Commands, server: Res<AssetServer>, mut game: ResMut<Game>) {
-    let alice_entity = commands
-        .spawn()
-        .insert(Character::new(
-            "Alice",
-            vec![
-                "happy".to_string(),
-                "neutral".to_string(),
-                "weary".to_string(),
-            ],
-            &server,
-        ))
-        .id();
-    game.opponents.push(alice_entity);
-    let carl_entity = commands
-        .spawn()
-        .insert(Character::new(
-            "Carl Blok",
-            vec![
-                "exhausted".to_string(),
-                "neutral".to_string(),
-                "panicked".to_string(),
-                "smug".to_string(),
-            ],
-            &server,
-        ))
-        .id();
-    game.opponents.push(carl_entity);
+    for (name, faces) in OPPONENTS {
+        let entity = commands
+            .spawn()
+            .insert(Character::new(
+                name,
+                faces.iter().map(|face| face.to_string()).collect(),
+                &server,
+            ))
+            .id();
+        game.opponents.push(entity);
+    }
 }
 
 fn play_move(
@@ -206,24 +579,27 @@ fn play_move(
     mut choss: ResMut<ChossGame>,
     mut piece_ents: ResMut<HashMap<Pos, Entity>>,
     mut game: ResMut<Game>,
+    mut arrows: ResMut<Arrows>,
+    mut last_move: ResMut<LastMove>,
     query_say: Query<(), With<Say>>,
     query_undo: Query<(), With<UndoingComp>>,
     mut query_text: Query<&mut Text, With<DialogueText>>,
     mut query_face: Query<&mut Handle<Image>, With<DialogueFace>>,
     server: Res<AssetServer>,
-    audio: Res<Audio>,
+    mut events: GameEvents,
     time: Res<Time>,
+    settings: Res<Settings>,
 ) {
     // only play the move if no one's talking and no one's undoing
     if query_say.is_empty()
         && query_undo.is_empty()
-        && time.seconds_since_startup() - game.last_move_time > 1.
+        && game.move_ready(time.seconds_since_startup(), settings.move_delay)
     {
         if let Some((pos, actions)) = &game.to_play {
             let color = choss.turn_color();
+            let before = (*choss).clone();
             choss.play(*pos, actions);
             let ent = *piece_ents.get(&pos).unwrap();
-            let mut is_take = false;
             for action in actions {
                 match action {
                     Action::Go(new_pos) => {
@@ -232,21 +608,27 @@ fn play_move(
                             .insert(MovingTo(choss.board_to_world(*new_pos)));
                         // if anything was on this new square, it should die
                         if let Some(o_ent) = piece_ents.get(&new_pos) {
-                            is_take = true;
                             commands.entity(*o_ent).insert(Die);
                         }
                         piece_ents.remove_entry(&pos);
                         piece_ents.insert(*new_pos, ent);
+                        last_move.0 = Some((*pos, *new_pos));
                     }
                     Action::Take(new_pos) => {
                         let o_ent = *piece_ents.get(&new_pos).unwrap();
                         commands.entity(o_ent).insert(Die);
                         piece_ents.remove_entry(&new_pos);
-                        is_take = true;
                     }
                     Action::Promotion(new_piece) => {
                         commands.entity(ent).insert(PromoteTo(*new_piece, color));
                     }
+                    Action::Castle(rook_pos, rook_target) => {
+                        let rook = piece_ents.remove(rook_pos).unwrap();
+                        commands
+                            .entity(rook)
+                            .insert(MovingTo(choss.board_to_world(*rook_target)));
+                        piece_ents.insert(*rook_target, rook);
+                    }
                 }
             }
             if color == choss.player {
@@ -257,221 +639,1104 @@ fn play_move(
                     *face = server.load("empty.png");
                 }
             }
-            if choss.board.is_checked(color.next()) {
-                audio.play(server.load("sounds/check.ogg"));
-            } else if is_take {
-                audio.play(server.load("sounds/take.ogg"));
-            } else {
-                audio.play(server.load("sounds/move.ogg"));
-            }
+            events.send_move(&before.board, &choss.board, color, *pos, actions);
             game.to_play = None;
+            // the window to undo Carl's undo closes once the game moves on
+            game.undone = None;
+            game.shown = Some((choss.board.clone(), choss.flipped()));
+            if color == choss.player {
+                game.takeback_state = Some(before);
+            }
+            if !arrows.arrows.is_empty() {
+                arrows.arrows.clear();
+            }
             // check if the game is over
-            if choss.board.moves(color.next(), true).len() == 0 {
-                if choss.board.is_checked(color.next()) {
-                    if color == choss.player {
-                        game.status = GameStatus::Win;
-                    } else {
-                        game.status = GameStatus::Loss;
-                    }
+            if let Some((status, reason)) = game_over(&choss, color, &settings) {
+                game.status = status;
+                game.end_reason = Some(reason);
+            }
+            game.last_move_time = time.seconds_since_startup();
+        }
+    }
+}
+
+fn record_moves(
+    mut moves: EventReader<MovePlayed>,
+    mut undos: EventReader<Undo>,
+    mut game: ResMut<Game>,
+    mut pgn: ResMut<PgnGame>,
+    choss: Res<ChossGame>,
+) {
+    // the move list and its annotations follow the game through its events
+    for undo in undos.iter() {
+        pgn.truncate(undo.halfmove.saturating_sub(choss.start_turn) as usize);
+        game.eval_log
+            .retain(|entry| entry.halfmove < undo.halfmove as usize);
+    }
+    for played in moves.iter() {
+        pgn.push(played.pos, &played.actions);
+    }
+}
+
+fn cursor_world_pos(
+    windows: &Windows,
+    q_camera: &Query<(&Camera, &GlobalTransform)>,
+) -> Option<Vec2> {
+    let window = windows.get_primary().unwrap();
+    let screen_pos = window.cursor_position()?;
+    let (camera, camera_transform) = q_camera.single();
+    Some(screen_to_world(
+        window,
+        camera,
+        camera_transform,
+        screen_pos,
+    ))
+}
+
+fn click_square(
+    pos: Pos,
+    choss: &ChossGame,
+    settings: &Settings,
+    game: &mut Game,
+    selected: &mut SelectedSquare,
+    premove: &mut PreMove,
+    pending: &mut PendingMove,
+) -> bool {
+    // returns true if the click was an attempt at an illegal move
+    if let Some((from, to)) = pending.0.take() {
+        // clicking the pending target again confirms the move, anything else cancels it
+        if pos == to {
+            game.to_play = choss.playable_move(from, to).map(|actions| (from, actions));
+            return false;
+        }
+    }
+    let mut illegal = false;
+    if choss.board.on_board(pos) {
+        if choss.player != choss.turn_color() {
+            // it's the opponent's turn, queue the move to be played right after
+            match selected.0 {
+                Some(old_pos) if choss.is_player_piece(old_pos) && !choss.is_player_piece(pos) => {
+                    premove.0 = Some((old_pos, pos));
+                    selected.0 = None;
+                }
+                _ => selected.0 = Some(pos),
+            }
+        } else if let Some(old_pos) = selected.0 {
+            // if the old and new pos correspond to a playable action, play it
+            if let Some(actions) = choss.playable_move(old_pos, pos) {
+                if settings.confirm_moves {
+                    pending.0 = Some((old_pos, pos));
                 } else {
-                    game.status = GameStatus::Draw;
+                    game.to_play = Some((old_pos, actions));
                 }
+                selected.0 = None;
+            } else {
+                // picking another of our pieces is just a new selection
+                illegal = choss.is_player_piece(old_pos) && !choss.is_player_piece(pos);
+                selected.0 = Some(pos);
             }
-            game.last_move_time = time.seconds_since_startup();
+        } else {
+            selected.0 = Some(pos);
         }
+    } else {
+        selected.0 = None;
     }
+    illegal
 }
 
 fn mouse_button_input(
+    mut commands: Commands,
     q_camera: Query<(&Camera, &GlobalTransform)>,
     q_say: Query<(), With<Say>>,
+    q_moving: Query<(), With<MovingTo>>,
+    q_undo: Query<(), With<UndoingComp>>,
     buttons: Res<Input<MouseButton>>,
     windows: Res<Windows>,
+    piece_ents: Res<HashMap<Pos, Entity>>,
     mut selected: ResMut<SelectedSquare>,
+    mut dragged: ResMut<DraggedSquare>,
+    mut arrows: ResMut<Arrows>,
+    (mut premove, mut pending): (ResMut<PreMove>, ResMut<PendingMove>),
+    mut events: GameEvents,
     mut game: ResMut<Game>,
     choss: ResMut<ChossGame>,
+    settings: Res<Settings>,
 ) {
+    if buttons.just_pressed(MouseButton::Right) {
+        arrows.start = cursor_world_pos(&windows, &q_camera)
+            .map(|world_pos| choss.world_to_board(world_pos))
+            .filter(|pos| choss.board.on_board(*pos));
+    }
+    if buttons.just_released(MouseButton::Right) {
+        if let Some(start) = arrows.start.take() {
+            let end = cursor_world_pos(&windows, &q_camera)
+                .map(|world_pos| choss.world_to_board(world_pos))
+                .filter(|pos| choss.board.on_board(*pos));
+            match end {
+                Some(end) if end != start => {
+                    // drawing the same arrow twice erases it
+                    if let Some(i) = arrows
+                        .arrows
+                        .iter()
+                        .position(|arrow| *arrow == (start, end))
+                    {
+                        arrows.arrows.remove(i);
+                    } else {
+                        arrows.arrows.push((start, end));
+                    }
+                }
+                _ => {
+                    selected.0 = None;
+                    premove.0 = None;
+                }
+            }
+        }
+    }
+    if buttons.just_pressed(MouseButton::Left) && !arrows.arrows.is_empty() {
+        arrows.arrows.clear();
+    }
+    // only take input when no one's talking and nothing is moving
+    let can_play = q_say.is_empty() && game.status == GameStatus::Playing;
+    if buttons.just_pressed(MouseButton::Left)
+        && can_play
+        && q_moving.is_empty()
+        && q_undo.is_empty()
+    {
+        if let Some(world_pos) = cursor_world_pos(&windows, &q_camera) {
+            let pos = choss.world_to_board(world_pos);
+            // pick up the piece if it's one of ours that isn't the target of the current selection
+            let is_target = selected
+                .0
+                .and_then(|old_pos| choss.playable_move(old_pos, pos))
+                .is_some();
+            if !is_target && choss.playable_moves(pos).is_some() {
+                selected.0 = Some(pos);
+                dragged.0 = Some(pos);
+            }
+        }
+    }
     if buttons.just_released(MouseButton::Left) {
-        // only take input when no one's talking
-        if q_say.is_empty() && game.status == GameStatus::Playing {
-            let window = windows.get_primary().unwrap();
-            if let Some(screen_pos) = window.cursor_position() {
-                let (camera, camera_transform) = q_camera.single();
-                let world_pos: Vec2 = screen_to_world(window, camera, camera_transform, screen_pos);
-                let pos = choss.world_to_board(world_pos);
-                if choss.board.in_bound(pos) {
-                    if let Some(old_pos) = selected.0 {
-                        // if the old and new pos correspond to a playable action, play it
-                        if let Some(actions) = choss.playable_move(old_pos, pos) {
-                            game.to_play = Some((old_pos, actions));
-                            selected.0 = None;
+        let drag_start = dragged.0.take();
+        let world_pos = cursor_world_pos(&windows, &q_camera);
+        if let Some((from, &ent)) = drag_start.and_then(|from| Some((from, piece_ents.get(&from)?)))
+        {
+            pending.0 = None;
+            let to = world_pos.map(|world_pos| choss.world_to_board(world_pos));
+            if to == Some(from) {
+                // it was a simple click on the piece, put it back and keep it selected
+                commands.entity(ent).insert(choss.board_to_world(from));
+                return;
+            }
+            if let Some(to) = to {
+                if can_play {
+                    if let Some(actions) = choss.playable_move(from, to) {
+                        selected.0 = None;
+                        if settings.confirm_moves {
+                            // the piece goes back until the move is confirmed
+                            commands.entity(ent).insert(choss.board_to_world(from));
+                            pending.0 = Some((from, to));
                         } else {
-                            selected.0 = Some(pos);
+                            commands.entity(ent).insert(choss.board_to_world(to));
+                            game.to_play = Some((from, actions));
                         }
-                    } else {
-                        selected.0 = Some(pos);
+                        return;
                     }
-                } else {
-                    selected.0 = None;
+                    if choss.player == choss.turn_color() && !choss.is_player_piece(to) {
+                        events.send_illegal_move(to);
+                    }
+                }
+            }
+            // illegal drop, the piece snaps back
+            commands
+                .entity(ent)
+                .insert(MovingTo(choss.board_to_world(from)));
+            return;
+        }
+        // only take input when no one's talking
+        if can_play {
+            if let Some(world_pos) = world_pos {
+                let pos = choss.world_to_board(world_pos);
+                if click_square(
+                    pos,
+                    &choss,
+                    &settings,
+                    &mut game,
+                    &mut selected,
+                    &mut premove,
+                    &mut pending,
+                ) {
+                    events.send_illegal_move(pos);
                 }
             }
         }
     }
 }
 
+fn keyboard_input(
+    q_say: Query<(), With<Say>>,
+    controls: Controls,
+    mut cursor: ResMut<CursorSquare>,
+    mut hovered: ResMut<HoveredSquare>,
+    mut selected: ResMut<SelectedSquare>,
+    mut premove: ResMut<PreMove>,
+    mut pending: ResMut<PendingMove>,
+    mut game: ResMut<Game>,
+    mut events: GameEvents,
+    choss: Res<ChossGame>,
+    settings: Res<Settings>,
+) {
+    // the board's y axis points down on screen
+    let dirs = [
+        (Control::CursorUp, Pos(0, -1)),
+        (Control::CursorDown, Pos(0, 1)),
+        (Control::CursorLeft, Pos(-1, 0)),
+        (Control::CursorRight, Pos(1, 0)),
+    ];
+    for (control, dir) in dirs {
+        if controls.just_pressed(control) {
+            let new_pos = match cursor.0 {
+                // the cursor stays in place when it would leave the board
+                Some(pos) if choss.board.in_bound(pos + dir) => choss.board.wrap(pos + dir),
+                Some(pos) => pos,
+                // start from the middle of the player's side
+                None => Pos(choss.board.width as i32 / 2, choss.board.height as i32 - 1),
+            };
+            cursor.0 = Some(new_pos);
+            hovered.0 = Some(new_pos);
+        }
+    }
+    if controls.just_pressed(Control::Cancel) {
+        selected.0 = None;
+        premove.0 = None;
+        pending.0 = None;
+    }
+    // a pending move is confirmed with Enter by confirm_pending_move
+    if controls.just_pressed(Control::Play)
+        && pending.0.is_none()
+        && q_say.is_empty()
+        && game.status == GameStatus::Playing
+    {
+        if let Some(pos) = cursor.0 {
+            if click_square(
+                pos,
+                &choss,
+                &settings,
+                &mut game,
+                &mut selected,
+                &mut premove,
+                &mut pending,
+            ) {
+                events.send_illegal_move(pos);
+            }
+        }
+    }
+}
+
 #[derive(Component)]
-struct MoveDisplay;
+struct CursorDisplay;
 
-fn display_moves(
-    query: Query<Entity, With<MoveDisplay>>,
+fn display_cursor(
     mut commands: Commands,
-    selected: Res<SelectedSquare>,
+    query: Query<Entity, With<CursorDisplay>>,
+    cursor: Res<CursorSquare>,
     choss: Res<ChossGame>,
-    server: Res<AssetServer>,
+    settings: Res<Settings>,
 ) {
-    if selected.is_changed() {
-        // despawn all previously shown MoveDisplays
-        for move_display in query.iter() {
-            commands.entity(move_display).despawn();
+    if cursor.is_changed() || settings.is_changed() {
+        for cursor_display in query.iter() {
+            commands.entity(cursor_display).despawn();
         }
-        // check if the new selected pos corresponds to a player piece
-        if let Some(pos) = selected.0 {
-            if let Some(moves) = choss.playable_moves(pos) {
-                // spawn a move display for each move of this piece
-                let sprite = SpriteBundle {
-                    sprite: Sprite {
-                        color: Color::rgba(0., 0., 0., 0.5),
-                        custom_size: Some(Vec2::new(SIZE as f32 / 2.5, SIZE as f32 / 2.5)),
-                        ..Default::default()
-                    },
-                    texture: server.load("circle.png"),
-                    ..Default::default()
-                };
-                for actions in moves {
-                    for action in actions {
-                        if let Action::Go(go_pos) = action {
-                            let mut sprite_clone = sprite.clone();
-                            sprite_clone.transform = choss.board_to_world(go_pos);
-                            commands.spawn_bundle(sprite_clone).insert(MoveDisplay);
-                        }
-                    }
+        if let Some(pos) = cursor.0 {
+            commands
+                .spawn_bundle(square_highlight(
+                    choss.board_to_world(pos),
+                    settings.palette().cursor,
+                ))
+                .insert(CursorDisplay);
+        }
+    }
+}
+
+fn hover_square(
+    q_camera: Query<(&Camera, &GlobalTransform)>,
+    mut cursor_moved: EventReader<CursorMoved>,
+    windows: Res<Windows>,
+    mut hovered: ResMut<HoveredSquare>,
+    mut cursor: ResMut<CursorSquare>,
+    choss: Res<ChossGame>,
+) {
+    // only follow the mouse when it moves, so it doesn't fight with the keyboard cursor
+    if cursor_moved.iter().count() == 0 {
+        return;
+    }
+    if cursor.0.is_some() {
+        cursor.0 = None;
+    }
+    let pos = cursor_world_pos(&windows, &q_camera)
+        .map(|world_pos| choss.world_to_board(world_pos))
+        .filter(|pos| choss.board.on_board(*pos));
+    if hovered.0 != pos {
+        hovered.0 = pos;
+    }
+}
+
+fn drag_piece(
+    q_camera: Query<(&Camera, &GlobalTransform)>,
+    mut q_transform: Query<&mut Transform>,
+    windows: Res<Windows>,
+    piece_ents: Res<HashMap<Pos, Entity>>,
+    dragged: Res<DraggedSquare>,
+) {
+    if let Some(pos) = dragged.0 {
+        if let Some(world_pos) = cursor_world_pos(&windows, &q_camera) {
+            if let Some(ent) = piece_ents.get(&pos) {
+                if let Ok(mut transform) = q_transform.get_mut(*ent) {
+                    // the dragged piece is drawn above the others
+                    transform.translation = world_pos.extend(1.);
                 }
             }
         }
     }
 }
 
-fn move_to(
-    mut commands: Commands,
-    mut query: Query<(Entity, &mut Transform, &MovingTo)>,
-    time: Res<Time>,
+fn play_premove(
+    q_say: Query<(), With<Say>>,
+    q_moving: Query<(), With<MovingTo>>,
+    q_undo: Query<(), With<UndoingComp>>,
+    mut premove: ResMut<PreMove>,
+    mut game: ResMut<Game>,
+    mut events: GameEvents,
+    choss: Res<ChossGame>,
 ) {
-    for (ent, mut transform, moving_to) in query.iter_mut() {
-        let mut diff = moving_to.0.translation - transform.translation;
-        let mut length = time.delta_seconds() * SIZE as f32 * 20.;
-        // the piece finished moving
-        if length >= diff.length() {
-            length = diff.length();
-            commands.entity(ent).remove::<MovingTo>();
+    if let Some((from, to)) = premove.0 {
+        if !q_undo.is_empty() || game.status != GameStatus::Playing {
+            // the position is being rewound, the pre-move no longer makes sense
+            premove.0 = None;
+        } else if q_say.is_empty()
+            && q_moving.is_empty()
+            && game.to_play.is_none()
+            && choss.player == choss.turn_color()
+        {
+            // play it if it's still legal in the new position, drop it otherwise
+            if let Some(actions) = choss.playable_move(from, to) {
+                game.to_play = Some((from, actions));
+            } else {
+                events.send_illegal_move(to);
+            }
+            premove.0 = None;
         }
-        if length > 0. {
-            diff = length * diff / diff.length();
-            transform.translation = transform.translation + diff;
+    }
+}
+
+fn confirm_pending_move(
+    q_say: Query<(), With<Say>>,
+    controls: Controls,
+    mut pending: ResMut<PendingMove>,
+    mut game: ResMut<Game>,
+    choss: Res<ChossGame>,
+) {
+    if let Some((from, to)) = pending.0 {
+        if game.status != GameStatus::Playing {
+            pending.0 = None;
+        } else if controls.just_pressed(Control::Play) && q_say.is_empty() {
+            game.to_play = choss.playable_move(from, to).map(|actions| (from, actions));
+            pending.0 = None;
         }
     }
 }
 
-fn die(mut commands: Commands, mut query: Query<Entity, With<Die>>) {
-    for ent in query.iter_mut() {
-        // for now we just despawn the entity, might do fancy things later
-        commands.entity(ent).despawn();
+fn square_highlight(transform: Transform, color: Color) -> SpriteBundle {
+    SpriteBundle {
+        sprite: Sprite {
+            color,
+            custom_size: Some(Vec2::new(SIZE as f32, SIZE as f32)),
+            ..Default::default()
+        },
+        transform,
+        ..Default::default()
     }
 }
 
-fn promote(
+#[derive(Component)]
+struct PreMoveDisplay;
+
+fn display_premove(
+    query: Query<Entity, With<PreMoveDisplay>>,
     mut commands: Commands,
-    mut query: Query<(Entity, &mut Handle<Image>, &PromoteTo)>,
-    server: Res<AssetServer>,
+    premove: Res<PreMove>,
+    choss: Res<ChossGame>,
+    settings: Res<Settings>,
 ) {
-    for (entity, mut image, promote) in query.iter_mut() {
-        commands.entity(entity).remove::<PromoteTo>();
-        *image = server.load(
-            format!(
-                "choss_pieces/{}.png",
-                piece_tex_name(&promote.0, &promote.1)
-            )
-            .as_str(),
-        );
+    if premove.is_changed() || settings.is_changed() {
+        for premove_display in query.iter() {
+            commands.entity(premove_display).despawn();
+        }
+        if let Some((from, to)) = premove.0 {
+            for pos in [from, to] {
+                commands
+                    .spawn_bundle(square_highlight(
+                        choss.board_to_world(pos),
+                        settings.palette().premove,
+                    ))
+                    .insert(PreMoveDisplay);
+            }
+        }
     }
 }
 
 #[derive(Component)]
-struct WaitUntil(f64);
-
-#[derive(Component)]
-struct AITask(Task<Vec<(f32, Pos, Vec<Action>)>>);
+struct PendingMoveDisplay;
 
-fn start_ai_turn(
+fn display_pending_move(
+    query: Query<Entity, With<PendingMoveDisplay>>,
     mut commands: Commands,
-    mut game: ResMut<Game>,
+    pending: Res<PendingMove>,
     choss: Res<ChossGame>,
-    moving_query: Query<(), With<MovingTo>>,
-    query_undo: Query<(), With<UndoingComp>>,
+    settings: Res<Settings>,
+) {
+    if pending.is_changed() || settings.is_changed() {
+        for pending_display in query.iter() {
+            commands.entity(pending_display).despawn();
+        }
+        if let Some((from, to)) = pending.0 {
+            for pos in [from, to] {
+                commands
+                    .spawn_bundle(square_highlight(
+                        choss.board_to_world(pos),
+                        settings.palette().pending_move,
+                    ))
+                    .insert(PendingMoveDisplay);
+            }
+        }
+    }
+}
+
+#[derive(Component)]
+struct LastMoveDisplay;
+
+fn display_last_move(
+    query: Query<Entity, With<LastMoveDisplay>>,
+    mut commands: Commands,
+    last_move: Res<LastMove>,
+    choss: Res<ChossGame>,
+    settings: Res<Settings>,
+) {
+    if last_move.is_changed() || settings.is_changed() {
+        for last_move_display in query.iter() {
+            commands.entity(last_move_display).despawn();
+        }
+        if let Some((from, to)) = last_move.0 {
+            for pos in [from, to] {
+                commands
+                    .spawn_bundle(square_highlight(
+                        choss.board_to_world(pos),
+                        settings.palette().last_move,
+                    ))
+                    .insert(LastMoveDisplay);
+            }
+        }
+    }
+}
+
+#[derive(Component)]
+struct CheckDisplay;
+
+#[derive(Component)]
+struct IllegalMoveFlash(f64);
+
+fn display_illegal_move(
+    query: Query<(Entity, &IllegalMoveFlash)>,
+    mut commands: Commands,
+    mut illegal_moves: EventReader<IllegalMove>,
+    choss: Res<ChossGame>,
+    settings: Res<Settings>,
+    time: Res<Time>,
+) {
+    let now = time.seconds_since_startup();
+    for (entity, flash) in query.iter() {
+        if flash.0 < now {
+            commands.entity(entity).despawn();
+        }
+    }
+    for illegal_move in illegal_moves.iter() {
+        commands
+            .spawn_bundle(square_highlight(
+                choss.board_to_world(illegal_move.pos),
+                settings.palette().illegal,
+            ))
+            .insert(IllegalMoveFlash(now + ILLEGAL_FLASH));
+    }
+}
+
+fn display_check(
+    query: Query<Entity, With<CheckDisplay>>,
+    mut commands: Commands,
+    choss: Res<ChossGame>,
+    settings: Res<Settings>,
+) {
+    if choss.is_changed() || settings.is_changed() {
+        for check_display in query.iter() {
+            commands.entity(check_display).despawn();
+        }
+        let color = choss.turn_color();
+        if let Some(king_pos) = choss.board.king_pos(color) {
+            if choss.board.is_checked(color) {
+                commands
+                    .spawn_bundle(square_highlight(
+                        choss.board_to_world(king_pos),
+                        settings.palette().check,
+                    ))
+                    .insert(CheckDisplay);
+            }
+        }
+    }
+}
+
+#[derive(Component)]
+struct PinDisplay;
+
+fn display_pins(
+    query: Query<Entity, With<PinDisplay>>,
+    mut commands: Commands,
+    choss: Res<ChossGame>,
+    settings: Res<Settings>,
+) {
+    if choss.is_changed() || settings.is_changed() {
+        for pin_display in query.iter() {
+            commands.entity(pin_display).despawn();
+        }
+        // only the player's pieces, the point is to explain why they can't move
+        for pos in choss.board.pinned_pieces(choss.player).keys() {
+            commands
+                .spawn_bundle(square_highlight(
+                    choss.board_to_world(*pos),
+                    settings.palette().pinned,
+                ))
+                .insert(PinDisplay);
+        }
+    }
+}
+
+#[derive(Component)]
+struct ThreatDisplay;
+
+fn display_threats(
+    query: Query<Entity, With<ThreatDisplay>>,
+    mut commands: Commands,
+    choss: Res<ChossGame>,
+    settings: Res<Settings>,
+) {
+    if choss.is_changed() || settings.is_changed() {
+        for threat_display in query.iter() {
+            commands.entity(threat_display).despawn();
+        }
+        if !settings.show_threats {
+            return;
+        }
+        for pos in choss.threatened_pieces() {
+            commands
+                .spawn_bundle(square_highlight(
+                    choss.board_to_world(pos),
+                    settings.palette().threatened,
+                ))
+                .insert(ThreatDisplay);
+        }
+    }
+}
+
+#[derive(Component)]
+struct MoveDisplay;
+
+fn display_moves(
+    query: Query<Entity, With<MoveDisplay>>,
+    mut commands: Commands,
+    selected: Res<SelectedSquare>,
+    choss: Res<ChossGame>,
+    capture_marker: Res<CaptureMarker>,
+    settings: Res<Settings>,
+    server: Res<AssetServer>,
+) {
+    if selected.is_changed() || settings.is_changed() {
+        // despawn all previously shown MoveDisplays
+        for move_display in query.iter() {
+            commands.entity(move_display).despawn();
+        }
+        // check if the new selected pos corresponds to a player piece
+        if let Some(pos) = selected.0 {
+            if let Some(markers) = choss.move_markers(pos) {
+                // spawn a move display for each square this piece can reach:
+                // a dot for quiet moves, a ring around captures and a colored marker for promotions
+                let palette = settings.palette();
+                let dot = SpriteBundle {
+                    sprite: Sprite {
+                        color: palette.move_marker,
+                        custom_size: Some(Vec2::new(SIZE as f32 / 2.5, SIZE as f32 / 2.5)),
+                        ..Default::default()
+                    },
+                    texture: server.load("circle.png"),
+                    ..Default::default()
+                };
+                let mut ring = dot.clone();
+                ring.sprite.custom_size = Some(Vec2::new(SIZE as f32, SIZE as f32));
+                ring.texture = capture_marker.0.clone();
+                ring.sprite.color = palette.capture_marker;
+                for (target, marker) in markers {
+                    let mut sprite = if marker.capture {
+                        ring.clone()
+                    } else {
+                        dot.clone()
+                    };
+                    if marker.promotion {
+                        sprite.sprite.color = palette.promotion_marker;
+                    }
+                    sprite.transform = choss.board_to_world(target);
+                    commands.spawn_bundle(sprite).insert(MoveDisplay);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Component)]
+struct ArrowDisplay;
+
+fn display_arrows(
+    query: Query<Entity, With<ArrowDisplay>>,
+    mut commands: Commands,
+    arrows: Res<Arrows>,
+    choss: Res<ChossGame>,
+) {
+    if arrows.is_changed() {
+        for arrow_display in query.iter() {
+            commands.entity(arrow_display).despawn();
+        }
+        let color = Color::rgba(1., 0.6, 0., 0.7);
+        for (start, end) in arrows.arrows.iter() {
+            let start = choss.board_to_world(*start).translation.truncate();
+            let end = choss.board_to_world(*end).translation.truncate();
+            let diff = end - start;
+            let rotation = Quat::from_rotation_z(diff.y.atan2(diff.x));
+            // the shaft stops a bit before the center of the target square to leave room for the head
+            let head_size = SIZE as f32 / 3.;
+            let shaft_len = diff.length() - head_size / 2.;
+            let mut shaft = Transform::from_translation(
+                (start + diff.normalize() * shaft_len / 2.).extend(0.5),
+            );
+            shaft.rotation = rotation;
+            commands
+                .spawn_bundle(SpriteBundle {
+                    sprite: Sprite {
+                        color,
+                        custom_size: Some(Vec2::new(shaft_len, SIZE as f32 / 8.)),
+                        ..Default::default()
+                    },
+                    transform: shaft,
+                    ..Default::default()
+                })
+                .insert(ArrowDisplay);
+            let mut head = Transform::from_translation(end.extend(0.5));
+            head.rotation = rotation * Quat::from_rotation_z(std::f32::consts::FRAC_PI_4);
+            commands
+                .spawn_bundle(SpriteBundle {
+                    sprite: Sprite {
+                        color,
+                        custom_size: Some(Vec2::new(head_size, head_size)),
+                        ..Default::default()
+                    },
+                    transform: head,
+                    ..Default::default()
+                })
+                .insert(ArrowDisplay);
+        }
+    }
+}
+
+fn blindfold(
+    mut query: Query<&mut Visibility>,
+    mut blindfold: ResMut<Blindfold>,
+    piece_ents: Res<HashMap<Pos, Entity>>,
+    controls: Controls,
+    time: Res<Time>,
+) {
+    if controls.just_pressed(Control::Blindfold) {
+        blindfold.enabled = !blindfold.enabled;
+    }
+    if controls.just_pressed(Control::Peek) && blindfold.enabled {
+        // peek at the position for a moment
+        blindfold.reveal_until = time.seconds_since_startup() + 2.;
+    }
+    let visible = !blindfold.enabled || time.seconds_since_startup() < blindfold.reveal_until;
+    for ent in piece_ents.values() {
+        if let Ok(mut visibility) = query.get_mut(*ent) {
+            if visibility.is_visible != visible {
+                visibility.is_visible = visible;
+            }
+        }
+    }
+}
+
+fn move_to(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut Transform, &MovingTo)>,
+    time: Res<Time>,
+) {
+    for (ent, mut transform, moving_to) in query.iter_mut() {
+        let mut diff = moving_to.0.translation - transform.translation;
+        let mut length = time.delta_seconds() * SIZE as f32 * 20.;
+        // the piece finished moving
+        if length >= diff.length() {
+            length = diff.length();
+            commands.entity(ent).remove::<MovingTo>();
+        }
+        if length > 0. {
+            diff = length * diff / diff.length();
+            transform.translation = transform.translation + diff;
+        }
+    }
+}
+
+fn die(mut commands: Commands, mut query: Query<Entity, With<Die>>) {
+    for ent in query.iter_mut() {
+        // for now we just despawn the entity, might do fancy things later
+        commands.entity(ent).despawn();
+    }
+}
+
+fn promote(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut Handle<Image>, &PromoteTo)>,
+    server: Res<AssetServer>,
+    piece_set: Res<PieceSet>,
+) {
+    for (entity, mut image, promote) in query.iter_mut() {
+        commands.entity(entity).remove::<PromoteTo>();
+        *image = server.load(piece_tex_path(&piece_set.0, &promote.0, &promote.1).as_str());
+    }
+}
+
+fn change_piece_set(
+    mut query: Query<&mut Handle<Image>>,
+    piece_ents: Res<HashMap<Pos, Entity>>,
+    choss: Res<ChossGame>,
+    server: Res<AssetServer>,
+    piece_set: Res<PieceSet>,
+) {
+    // the pieces already on the board are redrawn, the new ones are spawned with the right set
+    if !piece_set.is_changed() || piece_set.is_added() {
+        return;
+    }
+    for (pos, entity) in piece_ents.iter() {
+        if let (Ok(mut image), Some(Some((color, piece)))) =
+            (query.get_mut(*entity), choss.board.get(*pos))
+        {
+            *image = server.load(piece_tex_path(&piece_set.0, piece, color).as_str());
+        }
+    }
+}
+
+// every random choice of the game goes through here, so a seed can replay it
+struct GameRng(StdRng);
+
+impl GameRng {
+    fn new(seed: Option<u64>) -> Self {
+        match seed {
+            Some(seed) => GameRng(StdRng::seed_from_u64(seed)),
+            None => GameRng(StdRng::from_entropy()),
+        }
+    }
+}
+
+fn setup_rng(mut commands: Commands, settings: Res<Settings>) {
+    commands.insert_resource(GameRng::new(settings.seed));
+}
+
+fn candidate_moves(
+    moves: Vec<(f32, Pos, Vec<Action>)>,
+    rng: &mut impl Rng,
+) -> Vec<(f32, Pos, Vec<Action>)> {
+    // Randomly pick a move with that's not too far away from best in the 3 first moves,
+    // the last one is played and the others are kept for when the game gets undone
+    let best_move = moves[0].clone();
+    let best_score = best_move.0;
+    let mut filtered_moves: Vec<_> = moves
+        .into_iter()
+        .take(3)
+        .filter(|(score, _, _)| *score >= best_score - 3.)
+        .collect();
+    if filtered_moves.len() == 0 {
+        // this shouldn't be possible but it seems like it is lol
+        println!("wtf ? {}", best_score);
+        filtered_moves = vec![best_move];
+    }
+    filtered_moves.shuffle(rng);
+    filtered_moves
+}
+
+#[derive(Component)]
+struct WaitUntil(f64);
+
+#[derive(Component)]
+struct AITask(Task<ScoredLines>);
+
+// a search started in the background on the position the AI expects after the player's move
+#[derive(Component)]
+struct Ponder {
+    // the half move the AI will play on
+    halfmove: u32,
+    board: Board,
+    // calls the search off, dropping the task doesn't stop it in the middle of a search
+    stop: Arc<AtomicBool>,
+    // when the AI started waiting for it, if the player played the expected move
+    waited_since: Option<Instant>,
+}
+
+impl Ponder {
+    fn call_off(&self) {
+        // the search gives the table back soon after, with what it found so far
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+// the positions the AI searched, kept from one of its searches to the next,
+// so a search on the player's time still helps if they play another move
+#[derive(Default)]
+struct AITable(Arc<Mutex<TranspositionTable>>);
+
+fn search_depth(value: f32) -> u32 {
+    // the less material there is, the deeper the AI can afford to look
+    if value < 5. {
+        4
+    } else if value < 10. {
+        2
+    } else {
+        1
+    }
+}
+
+fn think(
+    choss: &ChossGame,
+    contempt: f32,
+    values: &PieceValues,
+    quiescence: u32,
+    think_time: Option<u64>,
+    table: &Mutex<TranspositionTable>,
+    stop: Arc<AtomicBool>,
+) -> ScoredLines {
+    // waits for the table if a called off search still has it
+    let mut table = table.lock().unwrap();
+    let search = Search::new(
+        choss.turn_color(),
+        choss.win_condition,
+        contempt,
+        values,
+        quiescence,
+        choss.reversible_history(),
+        &mut table,
+    )
+    .stop_on(stop);
+    if let Some(max_millis) = think_time {
+        let (lines, _) = negamax_timed_pv(search, &choss.board, max_millis, choss.checks);
+        return lines;
+    }
+    negamax_pv(
+        search,
+        &choss.board,
+        search_depth(choss.remaining_value(values)),
+        choss.checks,
+    )
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn ponder(
+    mut commands: Commands,
+    mut game: ResMut<Game>,
+    choss: Res<ChossGame>,
+    settings: Res<Settings>,
+    (pool, table): (Res<AsyncComputeTaskPool>, Res<AITable>),
+    query_ponder: Query<(Entity, &Ponder)>,
+) {
+    for (entity, ponder) in query_ponder.iter() {
+        // keep the search while waiting for the player, or if they played the expected move,
+        // otherwise drop it, what it stored in the table is still there for the next search
+        let waiting = choss.halfmove() + 1 == ponder.halfmove;
+        let hit = choss.halfmove() == ponder.halfmove && choss.board == ponder.board;
+        if game.status != GameStatus::Playing || !(waiting || hit) {
+            ponder.call_off();
+            commands.entity(entity).despawn();
+        }
+    }
+    if game.status == GameStatus::Playing
+        && game.puzzle.is_none()
+        && choss.player == choss.turn_color()
+        && query_ponder.is_empty()
+    {
+        if let Some((halfmove, pos, actions)) = game.predicted.take() {
+            // an older prediction is just forgotten
+            if halfmove == choss.halfmove() {
+                let mut expected = (*choss).clone();
+                expected.play(pos, &actions);
+                let ponder = Ponder {
+                    halfmove: expected.halfmove(),
+                    board: expected.board.clone(),
+                    stop: Arc::default(),
+                    waited_since: None,
+                };
+                let (contempt, values, quiescence, think_time) = (
+                    settings.contempt,
+                    settings.piece_values,
+                    settings.quiescence_depth,
+                    settings.think_time,
+                );
+                let (table, stop) = (table.0.clone(), ponder.stop.clone());
+                let task = pool.spawn(async move {
+                    think(
+                        &expected, contempt, &values, quiescence, think_time, &table, stop,
+                    )
+                });
+                commands.spawn().insert(ponder).insert(AITask(task));
+            }
+        }
+    }
+}
+
+fn start_ai_turn(
+    mut commands: Commands,
+    mut game: ResMut<Game>,
+    mut pgn: ResMut<PgnGame>,
+    choss: Res<ChossGame>,
+    (settings, table): (Res<Settings>, Res<AITable>),
+    lang: Res<Lang>,
+    mut rng: ResMut<GameRng>,
+    moving_query: Query<(), With<MovingTo>>,
+    query_undo: Query<(), With<UndoingComp>>,
+    mut query_ponder: Query<(Entity, &mut Ponder, &mut AITask)>,
+    mut move_times: ResMut<MoveTimes>,
 ) {
     if moving_query.is_empty()
         && query_undo.is_empty()
         && game.status == GameStatus::Playing
         && choss.player != choss.turn_color()
         && game.to_play.is_none()
+        && game.puzzle.is_none()
     {
+        if settings.claim_draws && game.ai_claims_draw(&choss) {
+            game.status = GameStatus::Draw;
+            game.end_reason = Some(EndReason::Claimed);
+            return;
+        }
+        if choss.board.moves(choss.turn_color(), true).is_empty() {
+            // a resumed or imported game can leave the AI without a move, the game ended there
+            if let Some((status, reason)) = game_over(&choss, choss.player, &settings) {
+                game.status = status;
+                game.end_reason = Some(reason);
+            }
+            return;
+        }
         // play the AI move
-        if let Some(cached_moves) = game.cached_moves_mut(choss.turn) {
+        if let Some(cached_moves) = game.cached_moves_mut(choss.halfmove()) {
             let (_, pos, actions) = cached_moves.pop().unwrap();
             if cached_moves.len() == 0 {
                 commands
                     .entity(game.opponent())
-                    .insert(Say::new("panicked", "If this doesn't work ..."));
+                    .insert(Say::new("panicked", lang.get("carl.last_try")));
             }
+            game.predicted = None;
             game.to_play = Some((pos, actions));
+            // the alternatives were found by the search that came before
+            move_times.searched(0.);
         } else {
-            let value = choss.remaining_value();
-            let depth = if value < 5. {
-                4
-            } else if value < 10. {
-                2
-            } else {
-                1
+            let value = choss.remaining_value(&settings.piece_values);
+            let pondered = match query_ponder.get_single_mut() {
+                Ok((entity, mut ponder, mut task))
+                    if ponder.halfmove == choss.halfmove() && ponder.board == choss.board =>
+                {
+                    // the player played the expected move, the search is done or well under way,
+                    // it's checked on every frame so the game doesn't freeze until then
+                    let search_start = *ponder.waited_since.get_or_insert_with(Instant::now);
+                    match future::block_on(future::poll_once(&mut task.0)) {
+                        Some(searched) => {
+                            commands.entity(entity).despawn();
+                            Some((searched, search_start))
+                        }
+                        None => return,
+                    }
+                }
+                Ok((entity, ponder, _)) => {
+                    ponder.call_off();
+                    commands.entity(entity).despawn();
+                    None
+                }
+                Err(_) => None,
             };
-            println!("thinking with base depth {}", depth);
-            let moves = negamax(&choss.board, choss.turn_color(), depth);
-            // Randomly pick a move with that's not too far away from best in the 3 first moves
-            let best_move = moves[0].clone();
-            let best_score = best_move.0;
-            let mut filtered_moves: Vec<_> = moves
-                .into_iter()
-                .take(3)
-                .filter(|(score, _, _)| *score >= best_score - 3.)
+            let (searched, search_start) = pondered.unwrap_or_else(|| {
+                let search_start = Instant::now();
+                let searched = think(
+                    &choss,
+                    settings.contempt,
+                    &settings.piece_values,
+                    settings.quiescence_depth,
+                    settings.think_time,
+                    &table.0,
+                    Arc::default(),
+                );
+                (searched, search_start)
+            });
+            move_times.searched(search_start.elapsed().as_secs_f64());
+            let moves = searched
+                .iter()
+                .map(|(score, pos, actions, _)| (*score, *pos, actions.clone()))
                 .collect();
-            if filtered_moves.len() == 0 {
-                // this shouldn't be possible but it seems like it is lol
-                println!("wtf ? {}", best_score);
-                filtered_moves = vec![best_move];
-            }
-            filtered_moves.shuffle(&mut rand::thread_rng());
-            let (_, pos, actions) = filtered_moves.pop().unwrap();
-            if let Some((face, text)) = game.get_dialogue(best_score) {
+            let best_score = searched[0].0;
+            let mut filtered_moves = candidate_moves(moves, &mut rng.0);
+            let (score, pos, actions) = filtered_moves.pop().unwrap();
+            let halfmove = choss.halfmove() as usize;
+            if let (Some(last_eval), Some(judged)) = (game.last_eval, halfmove.checked_sub(1)) {
+                // the player's move is judged by how much it helped the AI
+                let entry = EvalEntry::new(
+                    judged,
+                    choss.player,
+                    last_eval,
+                    best_score,
+                    -best_score,
+                    value,
+                );
+                pgn.annotate_last(entry.annotation);
+                game.eval_log.push(entry);
+            }
+            if let Some((face, key)) = game.get_dialogue(best_score, value) {
                 commands
                     .entity(game.opponent())
-                    .insert(Say::new(face, text));
+                    .insert(Say::new(face, lang.get(&key)));
             }
             // check if we must undo here
-            if game.should_undo(best_score) {
+            if game.should_undo(best_score, value) {
+                game.undos -= 1;
+                game.predicted = None;
                 commands.spawn().insert(UndoingComp::new());
             } else {
+                // the line expected after the AI's move starts with the player's answer
+                game.predicted = searched
+                    .into_iter()
+                    .find(|(_, line_pos, line_actions, _)| {
+                        *line_pos == pos && *line_actions == actions
+                    })
+                    .and_then(|(_, _, _, line)| line.into_iter().next())
+                    .map(|(answer_pos, answer_actions)| {
+                        (choss.halfmove() + 1, answer_pos, answer_actions)
+                    });
                 game.last_state = Some((*choss).clone());
                 game.last_eval = Some(best_score);
+                // the AI's own move loses whatever it gave up by not picking the best one
+                game.eval_log.push(EvalEntry::new(
+                    halfmove,
+                    choss.turn_color(),
+                    score,
+                    best_score,
+                    -score,
+                    value,
+                ));
                 game.to_play = Some((pos, actions));
-                game.update_cached_moves(filtered_moves, choss.turn);
+                game.update_cached_moves(filtered_moves, choss.halfmove());
             }
         }
     }
@@ -486,8 +1751,11 @@ fn undo(
     mut query_face: Query<&mut Handle<Image>, With<DialogueFace>>,
     mut game: ResMut<Game>,
     mut choss: ResMut<ChossGame>,
+    (mut last_move, mut pgn): (ResMut<LastMove>, ResMut<PgnGame>),
+    mut events: GameEvents,
     server: Res<AssetServer>,
     time: Res<Time>,
+    settings: Res<Settings>,
 ) {
     if query_say.is_empty() {
         if let Ok((entity, mut undoingcomp)) = query_undo.get_single_mut() {
@@ -496,160 +1764,921 @@ fn undo(
                 if transform.translation.x > 1000. {
                     transform.translation.x = -1000.;
                 }
-                if undoingcomp.ascending {
-                    undoingcomp.speed += undoingcomp.max_speed * 0.5 * time.delta_seconds();
-                    if undoingcomp.speed > undoingcomp.max_speed {
-                        // "zenith" of the undoing, we can replace the board here
-                        if let Ok(mut text) = query_text.get_single_mut() {
-                            text.sections[0].value = "".to_string();
-                        }
-                        if let Ok(mut face) = query_face.get_single_mut() {
-                            *face = server.load("empty.png");
-                        }
-                        *choss = game.last_state.clone().unwrap();
-                        game.status = GameStatus::Placing;
-                        undoingcomp.speed = undoingcomp.max_speed;
-                        undoingcomp.ascending = false;
+                let (zenith, over) =
+                    undoingcomp.advance(time.delta_seconds(), settings.skip_undo_animation);
+                if zenith {
+                    if let Ok(mut text) = query_text.get_single_mut() {
+                        text.sections[0].value = "".to_string();
                     }
-                } else {
-                    undoingcomp.speed -= undoingcomp.max_speed * 0.5 * time.delta_seconds();
-                    if undoingcomp.speed < 400. {
-                        // undoing is over
-                        game.last_move_time = time.seconds_since_startup() + 1.;
-                        undoingcomp.speed = 0.;
-                        transform.translation.x = 0.;
-                        commands.entity(entity).despawn();
+                    if let Ok(mut face) = query_face.get_single_mut() {
+                        *face = server.load("empty.png");
                     }
+                    rewind(&mut game, &mut choss, &mut last_move, &mut pgn);
+                    events.send_undo(choss.halfmove());
                 }
+                if over {
+                    // the move delay starts once the board is back in place, even when skipping
+                    game.last_move_time = time.seconds_since_startup() + settings.move_delay;
+                    transform.translation.x = 0.;
+                    commands.entity(entity).despawn();
+                }
+            }
+        }
+    }
+}
+
+fn reset_game(game: &mut Game) {
+    // forget everything about the current match but keep the opponents,
+    // the status goes back to Preparing so the match starts over
+    *game = Game {
+        opponents: std::mem::take(&mut game.opponents),
+        opponent: game.opponent,
+        last_move_time: game.last_move_time,
+        undo_unlocked: game.undo_unlocked,
+        ..Game::new()
+    };
+}
+
+fn restart_match(
+    mut commands: Commands,
+    controls: Controls,
+    mut game: ResMut<Game>,
+    query_undo: Query<(), With<UndoingComp>>,
+    mut query_text: Query<&mut Text, With<DialogueText>>,
+    mut query_face: Query<&mut Handle<Image>, With<DialogueFace>>,
+    mut selected: ResMut<SelectedSquare>,
+    mut premove: ResMut<PreMove>,
+    mut pending: ResMut<PendingMove>,
+    server: Res<AssetServer>,
+) {
+    if controls.just_pressed(Control::NewMatch)
+        && game.status == GameStatus::Playing
+        && query_undo.is_empty()
+    {
+        // cut the opponent off, they'll introduce the match again
+        for opponent in game.opponents.iter() {
+            commands.entity(*opponent).remove::<Say>();
+        }
+        if let Ok(mut text) = query_text.get_single_mut() {
+            text.sections[0].value = "".to_string();
+        }
+        if let Ok(mut face) = query_face.get_single_mut() {
+            *face = server.load("empty.png");
+        }
+        selected.0 = None;
+        premove.0 = None;
+        pending.0 = None;
+        reset_game(&mut game);
+    }
+}
+
+fn takeback(
+    mut commands: Commands,
+    controls: Controls,
+    mut game: ResMut<Game>,
+    choss: Res<ChossGame>,
+    lang: Res<Lang>,
+    query_say: Query<(), With<Say>>,
+    query_undo: Query<(), With<UndoingComp>>,
+) {
+    if controls.just_pressed(Control::Takeback)
+        && game.status == GameStatus::Playing
+        && query_say.is_empty()
+        && query_undo.is_empty()
+        && game.to_play.is_none()
+        && choss.player == choss.turn_color()
+        && game.takeback_state.is_some()
+        && game.puzzle.is_none()
+    {
+        if game.request_takeback() {
+            commands
+                .entity(game.opponent())
+                .insert(Say::new("happy", lang.get("alice.takeback")));
+            commands.spawn().insert(UndoingComp::new());
+        } else {
+            refuse_takeback(&mut commands, &game, &lang);
+        }
+    }
+}
+
+fn refuse_takeback(commands: &mut Commands, game: &Game, lang: &Lang) {
+    let say = if game.opponent == 0 {
+        Say::new("neutral", lang.get("alice.no_takebacks"))
+    } else {
+        Say::new("smug", lang.get("carl.no_takebacks"))
+    };
+    commands.entity(game.opponent()).insert(say);
+}
+
+fn undo_the_undo(
+    mut commands: Commands,
+    controls: Controls,
+    mut game: ResMut<Game>,
+    mut choss: ResMut<ChossGame>,
+    (mut last_move, mut pgn): (ResMut<LastMove>, ResMut<PgnGame>),
+    lang: Res<Lang>,
+    query_undo: Query<(), With<UndoingComp>>,
+) {
+    if controls.just_pressed(Control::UndoUndo)
+        && game.status == GameStatus::Playing
+        && query_undo.is_empty()
+        && game.puzzle.is_none()
+        && redo(&mut game, &mut choss, &mut last_move, &mut pgn)
+    {
+        commands
+            .entity(game.opponent())
+            .insert(Say::new("panicked", lang.get("carl.undo_undone")));
+    }
+}
+
+fn undo_move(
+    (mut commands, controls, lang): (Commands, Controls, Res<Lang>),
+    mut game: ResMut<Game>,
+    mut choss: ResMut<ChossGame>,
+    mut last_move: ResMut<LastMove>,
+    (mut selected, mut premove, mut pending): (
+        ResMut<SelectedSquare>,
+        ResMut<PreMove>,
+        ResMut<PendingMove>,
+    ),
+    mut events: GameEvents,
+    (query_undo, moving_query): (Query<(), With<UndoingComp>>, Query<(), With<MovingTo>>),
+) {
+    if controls.just_pressed(Control::UndoMove)
+        && game.status == GameStatus::Playing
+        && query_undo.is_empty()
+        && moving_query.is_empty()
+        && game.to_play.is_none()
+        && game.puzzle.is_none()
+    {
+        if rewind_player_move(&mut game, &mut choss, &mut last_move) {
+            selected.0 = None;
+            premove.0 = None;
+            pending.0 = None;
+            events.send_undo(choss.halfmove());
+        } else if game.takebacks == 0 {
+            refuse_takeback(&mut commands, &game, &lang);
+        }
+    }
+}
+
+// everything needed to pick a match back up after the game was closed
+#[derive(Serialize, Deserialize)]
+struct SavedGame {
+    choss: ChossGame,
+    pgn: PgnGame,
+    opponent: usize,
+    last_eval: Option<f32>,
+    cached_moves: Vec<(f32, Pos, Vec<Action>)>,
+    turn: u32,
+    last_state: Option<ChossGame>,
+    lines_sent: HashSet<String>,
+    carl_lines: Vec<String>,
+    takeback_state: Option<ChossGame>,
+    takebacks: u32,
+    undos: u32,
+    eval_log: Vec<EvalEntry>,
+    undo_unlocked: bool,
+    move_times: MoveTimes,
+}
+
+impl SavedGame {
+    fn new(game: &Game, choss: &ChossGame, pgn: &PgnGame, move_times: &MoveTimes) -> Self {
+        SavedGame {
+            choss: choss.clone(),
+            pgn: pgn.clone(),
+            opponent: game.opponent,
+            last_eval: game.last_eval,
+            cached_moves: game.cached_moves.clone(),
+            turn: game.turn,
+            last_state: game.last_state.clone(),
+            lines_sent: game.lines_sent.clone(),
+            carl_lines: game.carl_lines.clone(),
+            takeback_state: game.takeback_state.clone(),
+            takebacks: game.takebacks,
+            undos: game.undos,
+            eval_log: game.eval_log.clone(),
+            undo_unlocked: game.undo_unlocked,
+            move_times: move_times.clone(),
+        }
+    }
+
+    fn resume(
+        self,
+        game: &mut Game,
+        choss: &mut ChossGame,
+        pgn: &mut PgnGame,
+        move_times: &mut MoveTimes,
+    ) {
+        reset_game(game);
+        game.opponent = self.opponent;
+        game.last_eval = self.last_eval;
+        game.cached_moves = self.cached_moves;
+        game.turn = self.turn;
+        game.last_state = self.last_state;
+        game.lines_sent = self.lines_sent;
+        game.carl_lines = self.carl_lines;
+        game.takeback_state = self.takeback_state;
+        game.takebacks = self.takebacks;
+        game.undos = self.undos;
+        game.eval_log = self.eval_log;
+        game.undo_unlocked = self.undo_unlocked;
+        // the pieces are placed from scratch and the match goes on
+        game.status = GameStatus::Placing;
+        *choss = self.choss;
+        *pgn = self.pgn;
+        *move_times = self.move_times;
+    }
+}
+
+fn update_eval_graph(game: Res<Game>, mut graph: ResMut<EvalGraph>) {
+    if !game.is_changed() {
+        return;
+    }
+    // undone moves are already out of the log, so they leave the graph too
+    let points: Vec<(usize, f32)> = game
+        .eval_log
+        .iter()
+        .map(|entry| (entry.halfmove, entry.eval))
+        .collect();
+    let end_screen = game.status == GameStatus::Ending;
+    if graph.points != points || graph.end_screen != end_screen {
+        graph.points = points;
+        graph.end_screen = end_screen;
+    }
+}
+
+// the match that was going on when the game was closed, offered until a new one gets going
+#[cfg(not(target_arch = "wasm32"))]
+struct PendingRecovery(Option<SavedGame>);
+
+// in seconds, how often the match is saved in case the game is closed or crashes
+#[cfg(not(target_arch = "wasm32"))]
+const AUTOSAVE_INTERVAL: f64 = 10.;
+
+#[cfg(not(target_arch = "wasm32"))]
+fn autosave(
+    game: Res<Game>,
+    choss: Res<ChossGame>,
+    pgn: Res<PgnGame>,
+    move_times: Res<MoveTimes>,
+    pending: Res<PendingRecovery>,
+    time: Res<Time>,
+    mut last_save: Local<Option<f64>>,
+    mut unsaved: Local<bool>,
+) {
+    *unsaved |= choss.is_changed();
+    let now = time.seconds_since_startup();
+    // the match left last time isn't overwritten while it can still be resumed
+    if *unsaved
+        && pending.0.is_none()
+        && game.status == GameStatus::Playing
+        && game.puzzle.is_none()
+        && !matches!(*last_save, Some(last) if now - last < AUTOSAVE_INTERVAL)
+    {
+        if let Some(content) = to_recovery(&SavedGame::new(&game, &choss, &pgn, &move_times)) {
+            save_recovery(&content);
+        }
+        *last_save = Some(now);
+        *unsaved = false;
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn resume_game(
+    mut commands: Commands,
+    controls: Controls,
+    mut pending: ResMut<PendingRecovery>,
+    mut game: ResMut<Game>,
+    mut choss: ResMut<ChossGame>,
+    (mut pgn, mut last_move): (ResMut<PgnGame>, ResMut<LastMove>),
+    mut move_times: ResMut<MoveTimes>,
+    mut moves: EventReader<MovePlayed>,
+    query_undo: Query<(), With<UndoingComp>>,
+    mut query_text: Query<&mut Text, With<DialogueText>>,
+    mut query_face: Query<&mut Handle<Image>, With<DialogueFace>>,
+    server: Res<AssetServer>,
+) {
+    if moves.iter().count() > 0 && pending.0.is_some() {
+        // the player started over
+        pending.0 = None;
+    }
+    if controls.just_pressed(Control::Resume) && query_undo.is_empty() && game.puzzle.is_none() {
+        if let Some(saved) = pending.0.take() {
+            // cut the introduction short
+            for opponent in game.opponents.iter() {
+                commands.entity(*opponent).remove::<Say>();
+            }
+            if let Ok(mut text) = query_text.get_single_mut() {
+                text.sections[0].value = "".to_string();
+            }
+            if let Ok(mut face) = query_face.get_single_mut() {
+                *face = server.load("empty.png");
+            }
+            saved.resume(&mut game, &mut choss, &mut pgn, &mut move_times);
+            last_move.0 = None;
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Component)]
+struct ResumePrompt;
+
+#[cfg(not(target_arch = "wasm32"))]
+fn setup_resume_prompt(mut commands: Commands, server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: server.load("fonts/RobotoMono-Regular.ttf"),
+        font_size: 14.0,
+        color: Color::rgb(0.8, 0.8, 0.8),
+    };
+    let text_alignment = TextAlignment {
+        vertical: VerticalAlign::Top,
+        horizontal: HorizontalAlign::Left,
+    };
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::with_section("", text_style, text_alignment),
+            ..Default::default()
+        })
+        .insert(ResumePrompt);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn display_resume_prompt(
+    mut query_text: Query<(&mut Text, &mut Transform), With<ResumePrompt>>,
+    pending: Res<PendingRecovery>,
+    choss: Res<ChossGame>,
+    settings: Res<Settings>,
+    lang: Res<Lang>,
+) {
+    if !(pending.is_changed() || choss.is_changed() || settings.is_changed() || lang.is_changed()) {
+        return;
+    }
+    if let Ok((mut text, mut transform)) = query_text.get_single_mut() {
+        text.sections[0].value = if pending.0.is_some() {
+            lang.format(
+                "resume_prompt",
+                &[&key_name(settings.key_bindings.key(Control::Resume))],
+            )
+        } else {
+            "".to_string()
+        };
+        // under the board and its file labels, a new game can't be claimed a draw yet
+        transform.translation = Vec3::new(
+            -HSIZE * choss.board.width as f32,
+            -HSIZE * choss.board.height as f32 - settings.board_margin,
+            0.,
+        );
+    }
+}
+
+pub fn side_key(color: PieceColor) -> &'static str {
+    match color {
+        PieceColor::White => "side.white",
+        PieceColor::Black => "side.black",
+    }
+}
+
+fn clean_up_pieces(commands: &mut Commands, piece_ents: &mut HashMap<Pos, Entity>) {
+    for entity in piece_ents.values() {
+        commands.entity(*entity).despawn();
+    }
+    piece_ents.clear();
+}
+
+fn start_game(
+    query_say: Query<(), With<Say>>,
+    mut commands: Commands,
+    mut game: ResMut<Game>,
+    mut choss: ResMut<ChossGame>,
+    mut last_move: ResMut<LastMove>,
+    mut pgn: ResMut<PgnGame>,
+    query_character: Query<&Character>,
+    variant: Res<Variant>,
+    mut rng: ResMut<GameRng>,
+    settings: Res<Settings>,
+    lang: Res<Lang>,
+    mut dialogue_log: ResMut<DialogueLog>,
+) {
+    if query_say.is_empty() && game.status == GameStatus::Preparing {
+        // the log only keeps the current match
+        dialogue_log.clear();
+        let player = variant.side.pick(&mut rng.0);
+        let coin_flip = match variant.side {
+            Side::Random => lang.format("coin_flip", &[&lang.get(side_key(player))]),
+            _ => String::new(),
+        };
+        if game.opponent == 0 {
+            // start the alice game
+            let first_move = if player == PieceColor::White {
+                lang.get("alice.first_move_white")
+            } else {
+                lang.get("alice.first_move_black")
+            };
+            commands.entity(game.opponent()).insert(Say::new(
+                "happy",
+                lang.format("alice.welcome", &[&coin_flip, &first_move]),
+            ));
+        } else {
+            // start the carl game
+            commands
+                .entity(game.opponent())
+                .insert(Say::new("smug", lang.format("carl.welcome", &[&coin_flip])));
+        }
+        // setup the board
+        *choss = ChossGame {
+            board: with_handicap(variant.setup.board(), player.next(), settings.handicap),
+            win_condition: variant.win_condition,
+            ..ChossGame::new(player)
+        };
+        last_move.0 = None;
+        *pgn = PgnGame::new(choss.board.clone(), choss.turn_color());
+        let opponent = query_character
+            .get(game.opponent())
+            .map(|character| character.name.clone())
+            .unwrap_or_else(|_| "?".to_string());
+        if choss.player == PieceColor::White {
+            (pgn.white, pgn.black) = (lang.get("player"), opponent);
+        } else {
+            (pgn.white, pgn.black) = (opponent, lang.get("player"));
+        }
+        game.new_match();
+        game.status = GameStatus::Placing;
+    }
+}
+
+struct SpriteChanges {
+    // sprites that can be slid from one square to another
+    moved: Vec<(Pos, Pos)>,
+    despawned: Vec<Pos>,
+    spawned: Vec<Pos>,
+}
+
+impl SpriteChanges {
+    fn new(shown: &Board, board: &Board) -> Self {
+        // squares only count as changed if they look different,
+        // a pawn that just leaped keeps its sprite
+        let tex = |square: Square| square.map(|(color, piece)| piece_tex_name(&piece, &color));
+        let mut despawned = Vec::new();
+        let mut spawned = Vec::new();
+        for (pos, before, after) in shown.diff(board) {
+            if tex(before) == tex(after) {
+                continue;
+            }
+            if before.is_some() {
+                despawned.push(pos);
+            }
+            if after.is_some() {
+                spawned.push(pos);
+            }
+        }
+        // reuse a disappearing sprite for each new piece that looks the same
+        let mut moved = Vec::new();
+        spawned.retain(|to| {
+            let to_tex = tex(*board.get(*to).unwrap());
+            match despawned
+                .iter()
+                .position(|from| tex(*shown.get(*from).unwrap()) == to_tex)
+            {
+                Some(i) => {
+                    moved.push((despawned.remove(i), *to));
+                    false
+                }
+                None => true,
+            }
+        });
+        SpriteChanges {
+            moved,
+            despawned,
+            spawned,
+        }
+    }
+}
+
+fn spawn_piece(
+    commands: &mut Commands,
+    server: &AssetServer,
+    choss: &ChossGame,
+    set: &str,
+    pos: Pos,
+    color: PieceColor,
+    piece: Piece,
+) -> Entity {
+    let handle = server.load(piece_tex_path(set, &piece, &color).as_str());
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                custom_size: Some(Vec2::new(SIZE as f32 * 0.8, SIZE as f32 * 0.8)),
+                ..Default::default()
+            },
+            texture: handle,
+            transform: choss.board_to_world(pos),
+            ..Default::default()
+        })
+        .id()
+}
+
+fn place_pieces(
+    mut commands: Commands,
+    mut piece_ents: ResMut<HashMap<Pos, Entity>>,
+    mut game: ResMut<Game>,
+    choss: Res<ChossGame>,
+    server: Res<AssetServer>,
+    piece_set: Res<PieceSet>,
+) {
+    if game.status == GameStatus::Placing {
+        match game.shown.take() {
+            Some((shown, flipped))
+                if shown.width == choss.board.width
+                    && shown.height == choss.board.height
+                    && flipped == choss.flipped() =>
+            {
+                let changes = SpriteChanges::new(&shown, &choss.board);
+                // take every leaving sprite out first so moves can't overwrite each other
+                let mut moving = Vec::new();
+                for (from, to) in changes.moved {
+                    if let Some(ent) = piece_ents.remove(&from) {
+                        moving.push((ent, to));
+                    }
+                }
+                for pos in changes.despawned {
+                    if let Some(ent) = piece_ents.remove(&pos) {
+                        commands.entity(ent).despawn();
+                    }
+                }
+                for (ent, to) in moving {
+                    commands
+                        .entity(ent)
+                        .insert(MovingTo(choss.board_to_world(to)));
+                    piece_ents.insert(to, ent);
+                }
+                for pos in changes.spawned {
+                    if let Some(Some((color, piece))) = choss.board.get(pos) {
+                        let ent = spawn_piece(
+                            &mut commands,
+                            &server,
+                            &choss,
+                            &piece_set.0,
+                            pos,
+                            *color,
+                            *piece,
+                        );
+                        piece_ents.insert(pos, ent);
+                    }
+                }
+            }
+            _ => {
+                clean_up_pieces(&mut commands, &mut piece_ents);
+                for (pos, color, piece) in choss.board.pieces() {
+                    let ent = spawn_piece(
+                        &mut commands,
+                        &server,
+                        &choss,
+                        &piece_set.0,
+                        pos,
+                        color,
+                        piece,
+                    );
+                    piece_ents.insert(pos, ent);
+                }
+            }
+        }
+        game.shown = Some((choss.board.clone(), choss.flipped()));
+        game.status = GameStatus::Playing;
+    }
+}
+
+fn pgn_result(status: &GameStatus, player: PieceColor) -> &'static str {
+    let winner = match status {
+        GameStatus::Win => player,
+        GameStatus::Loss => player.next(),
+        GameStatus::Draw => return "1/2-1/2",
+        _ => return "*",
+    };
+    if winner == PieceColor::White {
+        "1-0"
+    } else {
+        "0-1"
+    }
+}
+
+fn end_game(
+    mut commands: Commands,
+    mut game: ResMut<Game>,
+    mut pgn: ResMut<PgnGame>,
+    mut last_pgn: ResMut<LastGamePgn>,
+    choss: Res<ChossGame>,
+    lang: Res<Lang>,
+    mut events: GameEvents,
+) {
+    if game.puzzle.is_some() {
+        // puzzles end when they're solved, not when the game is over
+        return;
+    }
+    if game.status == GameStatus::Win
+        || game.status == GameStatus::Loss
+        || game.status == GameStatus::Draw
+    {
+        pgn.result = pgn_result(&game.status, choss.player).to_string();
+        // keep it around, the next match will start recording over it
+        last_pgn.0 = Some(pgn.to_pgn());
+        #[cfg(not(target_arch = "wasm32"))]
+        save_game(&pgn.to_pgn());
+        // there's nothing left to recover
+        #[cfg(not(target_arch = "wasm32"))]
+        clear_recovery();
+        events.send_game_over(match game.status {
+            GameStatus::Win => Some(choss.player),
+            GameStatus::Loss => Some(choss.player.next()),
+            _ => None,
+        });
+        if game.opponent == 0 {
+            // end the alice game
+            if game.status == GameStatus::Win {
+                commands
+                    .entity(game.opponent())
+                    .insert(Say::new("happy", lang.get("alice.win")));
+            } else if game.status == GameStatus::Loss {
+                commands
+                    .entity(game.opponent())
+                    .insert(Say::new("happy", lang.get("alice.loss")));
+            } else {
+                commands
+                    .entity(game.opponent())
+                    .insert(Say::new("happy", lang.get("alice.draw")));
+            }
+            game.opponent = 1;
+            game.status = GameStatus::Preparing;
+        } else {
+            // end the carl game
+            if game.status == GameStatus::Win {
+                commands
+                    .entity(game.opponent())
+                    .insert(Say::new("exhausted", lang.get("carl.win")));
+                game.undo_unlocked = true;
+                game.status = GameStatus::Ending;
+            } else if game.status == GameStatus::Loss {
+                commands
+                    .entity(game.opponent())
+                    .insert(Say::new("smug", lang.get("carl.loss")));
+                game.status = GameStatus::Preparing;
+            } else {
+                commands
+                    .entity(game.opponent())
+                    .insert(Say::new("neutral", lang.get("carl.draw")));
+                game.status = GameStatus::Preparing;
+            }
+        }
+    }
+}
+
+// the PGN of the last finished game
+pub struct LastGamePgn(Option<String>);
+
+fn copy_pgn(
+    mut commands: Commands,
+    controls: Controls,
+    game: Res<Game>,
+    last_pgn: Res<LastGamePgn>,
+    lang: Res<Lang>,
+    query_say: Query<(), With<Say>>,
+) {
+    if controls.just_pressed(Control::CopyPgn) && query_say.is_empty() {
+        if let Some(pgn) = &last_pgn.0 {
+            let line = match copy_to_clipboard(pgn.clone()) {
+                Ok(()) => lang.get("game_copied"),
+                Err(err) => {
+                    println!("couldn't copy the game: {}", err);
+                    println!("{}", pgn);
+                    lang.get("copy_failed")
+                }
+            };
+            commands
+                .entity(game.opponent())
+                .insert(Say::new("neutral", line));
+        }
+    }
+}
+
+fn copy_fen(
+    mut commands: Commands,
+    controls: Controls,
+    game: Res<Game>,
+    choss: Res<ChossGame>,
+    lang: Res<Lang>,
+    query_say: Query<(), With<Say>>,
+) {
+    if controls.just_pressed(Control::CopyFen) && query_say.is_empty() {
+        let fen = choss.to_fen();
+        let line = match copy_to_clipboard(fen.clone()) {
+            Ok(()) => lang.get("position_copied"),
+            Err(err) => {
+                println!("couldn't copy the position: {}", err);
+                println!("{}", fen);
+                lang.get("position_copy_failed")
+            }
+        };
+        commands
+            .entity(game.opponent())
+            .insert(Say::new("neutral", line));
+    }
+}
+
+fn import_fen(
+    mut commands: Commands,
+    controls: Controls,
+    mut game: ResMut<Game>,
+    mut choss: ResMut<ChossGame>,
+    (mut last_move, mut pgn): (ResMut<LastMove>, ResMut<PgnGame>),
+    lang: Res<Lang>,
+    query_say: Query<(), With<Say>>,
+    query_undo: Query<(), With<UndoingComp>>,
+    query_moving: Query<(), With<MovingTo>>,
+) {
+    // only when nothing is moving, so no animation ends on the wrong board
+    if controls.just_pressed(Control::ImportFen)
+        && game.status == GameStatus::Playing
+        && game.to_play.is_none()
+        && game.puzzle.is_none()
+        && query_say.is_empty()
+        && query_undo.is_empty()
+        && query_moving.is_empty()
+    {
+        let position = paste_from_clipboard().and_then(|fen| {
+            ChossGame::from_fen(fen.trim(), choss.player).map_err(|err| err.to_string())
+        });
+        let say = match position {
+            Ok(new_choss) => {
+                // same players, new game
+                let players = (pgn.white.clone(), pgn.black.clone());
+                *pgn = PgnGame::new(new_choss.board.clone(), new_choss.turn_color());
+                (pgn.white, pgn.black) = players;
+                *choss = new_choss;
+                last_move.0 = None;
+                game.new_match();
+                game.status = GameStatus::Placing;
+                Say::new("neutral", lang.get("position_imported"))
             }
-        }
+            Err(err) => {
+                println!("couldn't import the position: {}", err);
+                Say::new("neutral", lang.format("position_invalid", &[&err]))
+            }
+        };
+        commands.entity(game.opponent()).insert(say);
     }
 }
 
-fn clean_up_pieces(commands: &mut Commands, piece_ents: &mut HashMap<Pos, Entity>) {
-    for entity in piece_ents.values() {
-        commands.entity(*entity).despawn();
-    }
-    piece_ents.clear();
+pub struct Puzzles(Vec<Puzzle>);
+
+fn load_puzzle(
+    index: usize,
+    puzzles: &Puzzles,
+    game: &mut Game,
+    choss: &mut ChossGame,
+    pgn: &mut PgnGame,
+    last_move: &mut LastMove,
+    lang: &Lang,
+) -> Say {
+    // the bundled puzzles are checked by the tests
+    let mut new_choss = ChossGame::from_fen(&puzzles.0[index].fen, PieceColor::White).unwrap();
+    new_choss.player = new_choss.turn_color();
+    *pgn = PgnGame::new(new_choss.board.clone(), new_choss.turn_color());
+    *choss = new_choss;
+    last_move.0 = None;
+    game.new_match();
+    game.puzzle = Some(PuzzleRun {
+        index,
+        step: 0,
+        accepted: None,
+        solved: false,
+    });
+    game.status = GameStatus::Placing;
+    Say::new(
+        "neutral",
+        lang.format(
+            "puzzle.start",
+            &[&(index + 1), &lang.get(side_key(choss.player))],
+        ),
+    )
 }
 
-fn start_game(
-    query_say: Query<(), With<Say>>,
+fn toggle_puzzles(
     mut commands: Commands,
+    controls: Controls,
     mut game: ResMut<Game>,
     mut choss: ResMut<ChossGame>,
+    (mut last_move, mut pgn): (ResMut<LastMove>, ResMut<PgnGame>),
+    (puzzles, lang): (Res<Puzzles>, Res<Lang>),
+    query_say: Query<(), With<Say>>,
+    query_undo: Query<(), With<UndoingComp>>,
+    query_moving: Query<(), With<MovingTo>>,
 ) {
-    if query_say.is_empty() && game.status == GameStatus::Preparing {
-        if game.opponent == 0 {
-            // start the alice game
-            commands.entity(game.opponent()).insert(Say::new(
-                "happy",
-                "Welcome to the Choss club !\n\
-                 It's your first game right ?\n\
-                 Well you win if you capture my King,\n\
-                 the piece with a cross on its head.\n\
-                 Select a white piece to make a move.",
-            ));
-        } else {
-            // start the carl game
-            commands.entity(game.opponent()).insert(Say::new(
-                "smug",
-                "My name's Carl Brok.\nI've never lost a game here,\nso I don't expect much from you\nbut let's see what you got.",
-            ));
+    if controls.just_pressed(Control::Puzzles)
+        && game.status == GameStatus::Playing
+        && game.to_play.is_none()
+        && query_say.is_empty()
+        && query_undo.is_empty()
+        && query_moving.is_empty()
+    {
+        if game.puzzle.is_some() {
+            // back to a regular match
+            reset_game(&mut game);
+        } else if !puzzles.0.is_empty() {
+            let say = load_puzzle(
+                0,
+                &puzzles,
+                &mut game,
+                &mut choss,
+                &mut pgn,
+                &mut last_move,
+                &lang,
+            );
+            commands.entity(game.opponent()).insert(say);
         }
-        // setup the board
-        *choss = ChossGame::new(PieceColor::White);
-        game.last_eval = Some(0.);
-        game.cached_moves = Vec::new();
-        game.status = GameStatus::Placing;
     }
 }
 
-fn place_pieces(
+fn check_puzzle_move(
     mut commands: Commands,
-    mut piece_ents: ResMut<HashMap<Pos, Entity>>,
     mut game: ResMut<Game>,
+    puzzles: Res<Puzzles>,
     choss: Res<ChossGame>,
-    server: Res<AssetServer>,
+    lang: Res<Lang>,
 ) {
-    if game.status == GameStatus::Placing {
-        clean_up_pieces(&mut commands, &mut piece_ents);
-        for (i, square) in choss.board.squares.iter().enumerate() {
-            if let Some((color, piece)) = square {
-                let handle = server
-                    .load(format!("choss_pieces/{}.png", piece_tex_name(piece, color)).as_str());
-                let pos = choss.board.pos(i);
-                piece_ents.insert(
-                    pos,
-                    commands
-                        .spawn_bundle(SpriteBundle {
-                            sprite: Sprite {
-                                custom_size: Some(Vec2::new(SIZE as f32 * 0.8, SIZE as f32 * 0.8)),
-                                ..Default::default()
-                            },
-                            texture: handle,
-                            transform: choss.board_to_world(pos),
-                            ..Default::default()
-                        })
-                        .id(),
-                );
-            }
+    // the player's moves are checked before they're played
+    let game = &mut *game;
+    if let (Some(run), Some((pos, actions))) = (&mut game.puzzle, &game.to_play) {
+        if choss.turn_color() != choss.player || run.accepted == game.to_play {
+            return;
+        }
+        let solution = &puzzles.0[run.index].solution;
+        if run.step < solution.len() && is_expected(&choss, *pos, actions, &solution[run.step]) {
+            run.step += 1;
+            run.accepted = game.to_play.clone();
+        } else {
+            game.to_play = None;
+            commands
+                .entity(game.opponents[game.opponent])
+                .insert(Say::new("neutral", lang.get("puzzle.wrong")));
         }
-        game.status = GameStatus::Playing;
     }
 }
 
-fn end_game(mut commands: Commands, mut game: ResMut<Game>) {
-    if game.status == GameStatus::Win
-        || game.status == GameStatus::Loss
-        || game.status == GameStatus::Draw
-    {
-        if game.opponent == 0 {
-            // end the alice game
-            if game.status == GameStatus::Win {
-                commands.entity(game.opponent()).insert(Say::new(
-                    "happy",
-                    "Wow you actually won ! Amazing !\nWell, your next opponent won't be as easy.\nHe's kinda annoying but really strong.",
-                ));
-            } else if game.status == GameStatus::Loss {
-                commands.entity(game.opponent()).insert(Say::new(
-                    "happy",
-                    "Chockmate ! I won but it's okay,\nit was your first game after all.\nAll this reflexion got me tired though,\nI'm going to relax and leave you with Carl,\nhe's strong so you'll learn a lot !",
-                ));
-            } else {
-                commands.entity(game.opponent()).insert(Say::new(
-                    "happy",
-                    "Uh, it's a draw then ! Not bad !\nAll this reflexion got me tired though,\nI'm going to relax and leave you with Carl,\nhe's strong so you'll learn a lot !",
-                ));
-            }
-            game.opponent = 1;
-            game.status = GameStatus::Preparing;
+fn puzzle(
+    mut commands: Commands,
+    mut game: ResMut<Game>,
+    mut choss: ResMut<ChossGame>,
+    (mut last_move, mut pgn): (ResMut<LastMove>, ResMut<PgnGame>),
+    puzzles: Res<Puzzles>,
+    lang: Res<Lang>,
+    query_say: Query<(), With<Say>>,
+    query_moving: Query<(), With<MovingTo>>,
+) {
+    if !query_say.is_empty() || !query_moving.is_empty() || game.to_play.is_some() {
+        return;
+    }
+    let opponent = game.opponent();
+    let (index, step, solved) = match &game.puzzle {
+        Some(run) => (run.index, run.step, run.solved),
+        None => return,
+    };
+    let solution = &puzzles.0[index].solution;
+    if solved {
+        if index + 1 < puzzles.0.len() {
+            let say = load_puzzle(
+                index + 1,
+                &puzzles,
+                &mut game,
+                &mut choss,
+                &mut pgn,
+                &mut last_move,
+                &lang,
+            );
+            commands.entity(opponent).insert(say);
         } else {
-            // end the carl game
-            if game.status == GameStatus::Win {
-                commands.entity(game.opponent()).insert(Say::new(
-                    "exhausted",
-                    "I - I actually lost...\n\
-                    I'm starting to realise now .\n\
-                    Even since I started using it,\n\
-                    I stopped improving...\n\
-                    Was this ability my undoing ? . . . . .",
-                ));
-                game.status = GameStatus::Ending;
-            } else if game.status == GameStatus::Loss {
-                commands.entity(game.opponent()).insert(Say::new(
-                    "smug",
-                    "Chockmate. I won as expected.\nStay if you want to play me again !",
-                ));
-                game.status = GameStatus::Preparing;
+            commands
+                .entity(opponent)
+                .insert(Say::new("happy", lang.get("puzzle.last")));
+            reset_game(&mut game);
+        }
+    } else if choss.turn_color() != choss.player {
+        let to_play = solution
+            .get(step)
+            .and_then(|notation| parse_move(&choss, notation));
+        if let Some(run) = &mut game.puzzle {
+            if to_play.is_some() {
+                // the opponent answers with the solution's move
+                run.step += 1;
+                run.accepted = None;
             } else {
-                commands.entity(game.opponent()).insert(Say::new(
-                    "neutral",
-                    "Eh, I let you draw on purpose.\nStay if you want to play me again !",
-                ));
-                game.status = GameStatus::Preparing;
+                run.solved = true;
+                commands
+                    .entity(opponent)
+                    .insert(Say::new("happy", lang.get("puzzle.correct")));
             }
         }
+        game.to_play = to_play;
     }
 }
 
@@ -662,16 +2691,18 @@ fn display_end(
     mut game: ResMut<Game>,
     query_title: Query<Entity, With<Title>>,
     server: Res<AssetServer>,
-    keys: Res<Input<KeyCode>>,
+    controls: Controls,
     query_say: Query<(), With<Say>>,
     mut query_text: Query<&mut Text, With<DialogueText>>,
     mut query_face: Query<&mut Handle<Image>, With<DialogueFace>>,
     audio: Res<Audio>,
+    choss: Res<ChossGame>,
+    lang: Res<Lang>,
+    move_times: Res<MoveTimes>,
 ) {
     if game.status == GameStatus::Ending && query_say.is_empty() {
         if let Ok(entity) = query_title.get_single() {
-            if keys.just_pressed(KeyCode::R) {
-                // R was pressed
+            if controls.just_pressed(Control::Restart) {
                 commands.entity(entity).despawn();
                 game.opponent = 0;
                 game.status = GameStatus::Preparing;
@@ -679,8 +2710,15 @@ fn display_end(
         } else {
             // clean the pieces
             clean_up_pieces(&mut commands, &mut piece_ents);
+            game.shown = None;
             if let Ok(mut text) = query_text.get_single_mut() {
-                text.sections[0].value = "".to_string();
+                // a look back at the match while the title is up
+                let summary = GameSummary::new(&game.eval_log).text(choss.player, &lang)
+                    + &move_times.summary(choss.player, &lang);
+                text.sections[0].value = match game.end_reason {
+                    Some(reason) => lang.format("end.reason", &[&lang.get(reason.key()), &summary]),
+                    None => summary,
+                };
             }
             if let Ok(mut face) = query_face.get_single_mut() {
                 *face = server.load("empty.png");
@@ -701,34 +2739,1025 @@ fn display_end(
     }
 }
 
-pub struct SelectedSquare(Option<Pos>);
+fn claim_draw(
+    controls: Controls,
+    settings: Res<Settings>,
+    choss: Res<ChossGame>,
+    mut game: ResMut<Game>,
+) {
+    if controls.just_pressed(Control::ClaimDraw)
+        && settings.claim_draws
+        && game.status == GameStatus::Playing
+        && game.puzzle.is_none()
+        && choss.draw_claimable()
+    {
+        game.status = GameStatus::Draw;
+        game.end_reason = Some(EndReason::Claimed);
+    }
+}
+
+#[derive(Component)]
+struct DrawPrompt;
+
+fn setup_draw_prompt(mut commands: Commands, server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: server.load("fonts/RobotoMono-Regular.ttf"),
+        font_size: 14.0,
+        color: Color::rgb(0.8, 0.8, 0.8),
+    };
+    let text_alignment = TextAlignment {
+        vertical: VerticalAlign::Top,
+        horizontal: HorizontalAlign::Left,
+    };
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::with_section("", text_style, text_alignment),
+            ..Default::default()
+        })
+        .insert(DrawPrompt);
+}
+
+fn display_draw_prompt(
+    mut query_text: Query<(&mut Text, &mut Transform), With<DrawPrompt>>,
+    settings: Res<Settings>,
+    choss: Res<ChossGame>,
+    game: Res<Game>,
+    lang: Res<Lang>,
+) {
+    if !(settings.is_changed() || choss.is_changed() || game.is_changed() || lang.is_changed()) {
+        return;
+    }
+    if let Ok((mut text, mut transform)) = query_text.get_single_mut() {
+        text.sections[0].value = if settings.claim_draws
+            && game.status == GameStatus::Playing
+            && game.puzzle.is_none()
+            && choss.draw_claimable()
+        {
+            lang.format(
+                "claim_draw",
+                &[&key_name(settings.key_bindings.key(Control::ClaimDraw))],
+            )
+        } else {
+            "".to_string()
+        };
+        // under the board and its file labels
+        transform.translation = Vec3::new(
+            -HSIZE * choss.board.width as f32,
+            -HSIZE * choss.board.height as f32 - settings.board_margin,
+            0.,
+        );
+    }
+}
+
+#[derive(Component)]
+struct UndoCounter;
+
+fn setup_undo_counter(mut commands: Commands, server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: server.load("fonts/RobotoMono-Regular.ttf"),
+        font_size: 14.0,
+        color: Color::rgb(0.8, 0.8, 0.8),
+    };
+    let text_alignment = TextAlignment {
+        vertical: VerticalAlign::Bottom,
+        horizontal: HorizontalAlign::Left,
+    };
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::with_section("", text_style, text_alignment),
+            ..Default::default()
+        })
+        .insert(UndoCounter);
+}
+
+fn display_undo_counter(
+    mut query_text: Query<(&mut Text, &mut Transform), With<UndoCounter>>,
+    choss: Res<ChossGame>,
+    game: Res<Game>,
+    lang: Res<Lang>,
+) {
+    if !(choss.is_changed() || game.is_changed() || lang.is_changed()) {
+        return;
+    }
+    if let Ok((mut text, mut transform)) = query_text.get_single_mut() {
+        text.sections[0].value = match game.undos_left() {
+            Some(left) if game.status == GameStatus::Playing && game.puzzle.is_none() => {
+                lang.format("undos_left", &[&left])
+            }
+            _ => "".to_string(),
+        };
+        // right above the board
+        transform.translation = Vec3::new(
+            -HSIZE * choss.board.width as f32,
+            HSIZE * choss.board.height as f32 + 8.,
+            0.,
+        );
+    }
+}
+
+// the rules used for the next game
+pub struct Variant {
+    setup: Setup,
+    win_condition: WinCondition,
+    // the side the player takes, the AI opens when it's Black
+    side: Side,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Side {
+    White,
+    Black,
+    // a coin flip at the start of each match
+    Random,
+}
+
+impl Side {
+    fn next(self) -> Self {
+        match self {
+            Side::White => Side::Black,
+            Side::Black => Side::Random,
+            Side::Random => Side::White,
+        }
+    }
+
+    fn pick(self, rng: &mut impl Rng) -> PieceColor {
+        match self {
+            Side::White => PieceColor::White,
+            Side::Black => PieceColor::Black,
+            Side::Random => {
+                if rng.gen() {
+                    PieceColor::White
+                } else {
+                    PieceColor::Black
+                }
+            }
+        }
+    }
+}
+
+fn flip_board(
+    controls: Controls,
+    mut orientation: ResMut<BoardOrientation>,
+    mut choss: ResMut<ChossGame>,
+) {
+    if controls.just_pressed(Control::FlipBoard) {
+        orientation.turned = !orientation.turned;
+    }
+    // undos and new games bring back a game from before the last flip
+    if choss.turned != orientation.turned {
+        choss.turned = orientation.turned;
+    }
+}
+
+fn variant_text(variant: &Variant, lang: &Lang) -> String {
+    let setup = match variant.setup {
+        Setup::Halved => "setup.halved",
+        Setup::Cylinder => "setup.cylinder",
+        Setup::Standard => "setup.standard",
+        Setup::Horde => "setup.horde",
+        Setup::LightBrigade => "setup.light_brigade",
+    };
+    let win_condition = match variant.win_condition {
+        WinCondition::Checkmate => "win.checkmate",
+        WinCondition::ThreeCheck => "win.three_check",
+        WinCondition::KingOfTheHill => "win.king_of_the_hill",
+    };
+    let side = match variant.side {
+        Side::White => side_key(PieceColor::White),
+        Side::Black => side_key(PieceColor::Black),
+        Side::Random => "side.random",
+    };
+    lang.format(
+        "notice.variant",
+        &[&lang.get(setup), &lang.get(win_condition), &lang.get(side)],
+    )
+}
+
+fn select_variant(
+    controls: Controls,
+    mut variant: ResMut<Variant>,
+    mut notice: ResMut<Notice>,
+    lang: Res<Lang>,
+) {
+    let before = (variant.setup, variant.win_condition, variant.side);
+    if controls.just_pressed(Control::NextSetup) {
+        variant.setup = variant.setup.next();
+    }
+    if controls.just_pressed(Control::NextWinCondition) {
+        variant.win_condition = variant.win_condition.next();
+    }
+    if controls.just_pressed(Control::NextSide) {
+        variant.side = variant.side.next();
+    }
+    if (variant.setup, variant.win_condition, variant.side) != before {
+        notice.show(variant_text(&variant, &lang));
+    }
+}
+
+pub struct SelectedSquare(pub Option<Pos>);
+
+pub struct HoveredSquare(pub Option<Pos>);
+
+pub struct DraggedSquare(Option<Pos>);
+
+pub struct CursorSquare(Option<Pos>);
+
+// practice mode where the pieces are hidden but still played normally
+#[derive(Default)]
+pub struct Blindfold {
+    enabled: bool,
+    reveal_until: f64,
+}
+
+pub struct PreMove(Option<(Pos, Pos)>);
+
+pub struct LastMove(Option<(Pos, Pos)>);
 
-pub struct HoveredSquare(Option<Pos>);
+pub struct PendingMove(Option<(Pos, Pos)>);
+
+#[derive(Default)]
+pub struct Arrows {
+    start: Option<Pos>,
+    arrows: Vec<(Pos, Pos)>,
+}
 
 pub struct Undoing;
 
 impl Plugin for Undoing {
     fn build(&self, app: &mut App) {
-        app.insert_resource(ChossGame::new(PieceColor::White))
+        let choss = ChossGame::new(PieceColor::White);
+        app.insert_resource(PgnGame::new(choss.board.clone(), choss.turn_color()))
+            .insert_resource(LastGamePgn(None))
+            .insert_resource(choss)
+            .add_plugin(EventsPlugin)
             .add_plugin(CharacterPlugin)
+            .add_plugin(SettingsPlugin)
+            .add_plugin(LangPlugin)
+            .add_plugin(SoundsPlugin)
+            .add_plugin(TutorialPlugin)
+            .add_plugin(AnalysisPlugin)
+            .add_plugin(DebugPlugin)
+            .add_plugin(PacePlugin)
+            .add_plugin(PieceSetPlugin)
+            .add_plugin(FrameRatePlugin)
+            .add_plugin(EvalGraphPlugin)
+            .add_plugin(HelpPlugin)
+            .add_plugin(KeyBindingsPlugin)
+            .add_plugin(MoveLogPlugin)
+            .add_plugin(NoticePlugin)
             .insert_resource(Game::new())
+            .insert_resource(AITable::default())
+            .insert_resource(Puzzles(parse_puzzles(include_str!(
+                "../assets/puzzles.txt"
+            ))))
             .insert_resource(HashMap::<Pos, Entity>::new())
             .insert_resource(SelectedSquare(None))
             .insert_resource(HoveredSquare(None))
+            .insert_resource(DraggedSquare(None))
+            .insert_resource(CursorSquare(None))
+            .insert_resource(Blindfold::default())
+            .insert_resource(Arrows::default())
+            .insert_resource(PreMove(None))
+            .insert_resource(LastMove(None))
+            .insert_resource(PendingMove(None))
+            .insert_resource(BoardOrientation::default())
+            .insert_resource(Variant {
+                setup: Setup::Halved,
+                win_condition: WinCondition::Checkmate,
+                side: Side::White,
+            })
             .add_startup_system(create_opponents)
+            .add_startup_system(setup_rng)
             .add_startup_system(draw_choss)
+            .add_startup_system(setup_draw_prompt)
+            .add_startup_system(setup_undo_counter)
+            .add_system(redraw_choss)
+            .add_system(draw_coordinates)
             .add_system(play_move.label("play"))
-            .add_system(mouse_button_input)
+            .add_system(record_moves.label("record").after("play"))
+            .add_system(mouse_button_input.label("input"))
+            .add_system(drag_piece.after("input"))
+            .add_system(hover_square)
+            .add_system(keyboard_input.before("play"))
+            .add_system(display_cursor)
             .add_system(display_moves)
+            .add_system(display_last_move)
+            .add_system(display_check)
+            .add_system(display_illegal_move)
+            .add_system(display_pins)
+            .add_system(display_threats)
+            .add_system(display_arrows)
+            .add_system(play_premove.before("play"))
+            .add_system(display_premove)
+            .add_system(confirm_pending_move.before("play"))
+            .add_system(display_pending_move)
             .add_system(move_to)
+            .add_system(blindfold)
+            .add_system(select_variant)
+            .add_system(flip_board.before("input"))
+            .add_system(restart_match.before("input"))
+            .add_system(takeback)
+            .add_system(undo_the_undo.before("play"))
+            .add_system(undo_move.before("play"))
+            .add_system(copy_pgn)
+            .add_system(copy_fen)
+            .add_system(claim_draw.before("play"))
+            .add_system(display_draw_prompt)
+            .add_system(display_undo_counter)
+            .add_system(import_fen.before("input"))
+            .add_system(toggle_puzzles.before("input"))
+            .add_system(check_puzzle_move.after("input").before("play"))
+            .add_system(puzzle.after("play"))
             .add_system(die)
             .add_system(promote)
-            .add_system(start_ai_turn.after("play"))
+            .add_system(change_piece_set)
+            .add_system(start_ai_turn.after("record"))
             // ensure dialogue gets instanciated before the next play_move call
             .add_system(start_game.label("start"))
-            .add_system(end_game.after("start"))
+            .add_system(end_game.after("start").after("record"))
             .add_system(place_pieces)
-            .add_system(undo)
-            .add_system(display_end.before("start"));
+            .add_system(undo.before("record"))
+            .add_system(display_end.before("start"))
+            .add_system(update_eval_graph.after("play"));
+        // there are no threads to search on in the browser
+        #[cfg(not(target_arch = "wasm32"))]
+        app.add_system(ponder);
+        // nor files to save snapshots to
+        #[cfg(not(target_arch = "wasm32"))]
+        app.add_plugin(crate::snapshot::SnapshotPlugin);
+        // and the assets are fetched one by one over the network
+        #[cfg(not(target_arch = "wasm32"))]
+        app.add_plugin(crate::assets::AssetsPlugin);
+        // the match in progress is kept on disk to be resumed after a crash
+        #[cfg(not(target_arch = "wasm32"))]
+        app.insert_resource(PendingRecovery(
+            load_recovery().and_then(|content| from_recovery(&content)),
+        ))
+        .add_startup_system(setup_resume_prompt)
+        .add_system(resume_game.before("input"))
+        .add_system(autosave.after("play"))
+        .add_system(display_resume_prompt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::{negamax, QUIESCENCE_DEPTH};
+    use bevy::app::Events;
+    use bevy::tasks::TaskPool;
+
+    #[test]
+    fn no_move_delay() {
+        let mut game = Game::new();
+        game.last_move_time = 5.;
+        assert!(game.move_ready(5., 0.));
+        assert!(!game.move_ready(5.5, 1.));
+        assert!(game.move_ready(6., 1.));
+    }
+
+    fn carl_undo_line(game: &mut Game) -> Option<String> {
+        // Carl blunders a piece and wants to undo it
+        game.cached_moves = vec![(0., Pos(0, 0), Vec::new())];
+        game.last_eval = Some(3.);
+        game.get_dialogue(-3., REFERENCE_VALUE)
+            .map(|(_, key)| Lang::english().get(&key))
+    }
+
+    #[test]
+    fn carl_lines_every_match() {
+        let mut game = Game::new();
+        game.opponent = 1;
+        game.new_match();
+        let first = carl_undo_line(&mut game);
+        assert_eq!(first, Some("Oh... that won't do.".to_string()));
+        assert_eq!(
+            carl_undo_line(&mut game),
+            Some("Mh, that doesn't work.".to_string())
+        );
+        // the second match starts the escalation over
+        game.new_match();
+        assert_eq!(carl_undo_line(&mut game), first);
+    }
+
+    #[test]
+    fn thresholds_follow_material() {
+        let mut game = Game::new();
+        game.opponent = 1;
+        game.new_match();
+        game.cached_moves = vec![(0., Pos(0, 0), Vec::new())];
+        game.last_eval = Some(0.);
+        // losing a pawn and a half is fine with a full army but a blunder in an endgame
+        assert!(!game.should_undo(-1.5, REFERENCE_VALUE));
+        assert!(game.should_undo(-1.5, 5.));
+        // and losing a piece is not that bad with several queens around
+        assert!(game.should_undo(-3., REFERENCE_VALUE));
+        assert!(!game.should_undo(-3., 50.));
+        // Alice comments the same way
+        game.opponent = 0;
+        assert_eq!(game.get_dialogue(1.5, REFERENCE_VALUE), None);
+        assert!(game.get_dialogue(1.5, 5.).is_some());
+    }
+
+    #[test]
+    fn undo_counter() {
+        let mut game = Game::new();
+        game.new_match();
+        assert_eq!(game.undos_left(), None);
+        game.opponent = 1;
+        game.new_match();
+        assert_eq!(game.undos_left(), Some(MAX_UNDOS));
+        // the alternatives Carl kept don't change how many undos he has
+        game.update_cached_moves(vec![(0., Pos(0, 0), Vec::new()); 3], 1);
+        assert_eq!(game.undos_left(), Some(MAX_UNDOS));
+        assert!(game.should_undo(-3., REFERENCE_VALUE));
+        // once they are spent he has to live with his blunders until the next match
+        game.undos = 0;
+        assert_eq!(game.undos_left(), Some(0));
+        assert!(!game.should_undo(-3., REFERENCE_VALUE));
+        game.new_match();
+        assert_eq!(game.undos_left(), Some(MAX_UNDOS));
+    }
+
+    #[test]
+    fn undo_skip() {
+        let rewound = |skip| {
+            let mut game = Game::new();
+            let mut choss = ChossGame::new(PieceColor::White);
+            let mut pgn = PgnGame::new(choss.board.clone(), choss.turn_color());
+            let mut last_move = LastMove(None);
+            game.last_state = Some(choss.clone());
+            pgn.push(Pos(1, 6), &[Action::Go(Pos(1, 5))]);
+            choss.play(Pos(1, 6), &vec![Action::Go(Pos(1, 5))]);
+            game.status = GameStatus::Playing;
+            let mut undoing = UndoingComp::new();
+            let mut zeniths = 0;
+            let mut frames = 0;
+            loop {
+                frames += 1;
+                let (zenith, over) = undoing.advance(1. / 60., skip);
+                if zenith {
+                    zeniths += 1;
+                    rewind(&mut game, &mut choss, &mut last_move, &mut pgn);
+                }
+                if over {
+                    break;
+                }
+                assert!(frames < 1000);
+            }
+            // the board is replaced exactly once, either way
+            assert_eq!(zeniths, 1);
+            let halfmove = choss.halfmove();
+            (frames, (game.status, halfmove, choss.board, pgn.to_pgn()))
+        };
+        let (animated_frames, animated) = rewound(false);
+        let (skipped_frames, skipped) = rewound(true);
+        assert!(animated_frames > 1);
+        assert_eq!(skipped_frames, 1);
+        assert_eq!(skipped, animated);
+        assert_eq!((skipped.0, skipped.1), (GameStatus::Placing, 0));
+    }
+
+    #[test]
+    fn undoing_carls_undo() {
+        let mut game = Game::new();
+        let mut choss = ChossGame::new(PieceColor::White);
+        let mut pgn = PgnGame::new(choss.board.clone(), choss.turn_color());
+        let mut last_move = LastMove(None);
+        game.opponent = 1;
+        game.last_state = Some(choss.clone());
+        let play_two = |choss: &mut ChossGame, pgn: &mut PgnGame| {
+            for (pos, to) in [(Pos(1, 6), Pos(1, 5)), (Pos(1, 1), Pos(1, 2))] {
+                pgn.push(pos, &[Action::Go(to)]);
+                choss.play(pos, &vec![Action::Go(to)]);
+            }
+        };
+        // not before Carl has been beaten
+        play_two(&mut choss, &mut pgn);
+        rewind(&mut game, &mut choss, &mut last_move, &mut pgn);
+        // what record_moves does with the Undo event
+        pgn.truncate(choss.moves_played() as usize);
+        assert!(!redo(&mut game, &mut choss, &mut last_move, &mut pgn));
+        game.undo_unlocked = true;
+        game.cached_moves = vec![(0., Pos(0, 1), vec![Action::Go(Pos(0, 2))])];
+        play_two(&mut choss, &mut pgn);
+        let escaped = (choss.board.squares.clone(), choss.halfmove(), pgn.to_pgn());
+        rewind(&mut game, &mut choss, &mut last_move, &mut pgn);
+        assert_eq!(choss.halfmove(), 0);
+        assert!(redo(&mut game, &mut choss, &mut last_move, &mut pgn));
+        assert_eq!(
+            (choss.board.squares.clone(), choss.halfmove(), pgn.to_pgn()),
+            escaped
+        );
+        // Carl has no alternatives left to undo into
+        assert!(game.cached_moves.is_empty());
+        assert_eq!(game.status, GameStatus::Placing);
+        assert!(!redo(&mut game, &mut choss, &mut last_move, &mut pgn));
+    }
+
+    #[test]
+    fn undoing_the_players_move() {
+        let mut game = Game::new();
+        game.new_match();
+        let mut choss = ChossGame::new(PieceColor::Black);
+        let mut last_move = LastMove(None);
+        // the AI's opening move alone isn't the player's to undo
+        choss.play(Pos(1, 6), &vec![Action::Go(Pos(1, 5))]);
+        let opened = choss.board.clone();
+        assert!(!rewind_player_move(&mut game, &mut choss, &mut last_move));
+        assert_eq!(choss.halfmove(), 1);
+        choss.play(Pos(1, 1), &vec![Action::Go(Pos(1, 2))]);
+        choss.play(Pos(2, 6), &vec![Action::Go(Pos(2, 5))]);
+        game.cached_moves = vec![(0., Pos(0, 1), vec![Action::Go(Pos(0, 2))])];
+        // the AI's answer goes back along with the player's move
+        assert!(rewind_player_move(&mut game, &mut choss, &mut last_move));
+        assert_eq!(choss.halfmove(), 1);
+        assert_eq!(choss.turn_color(), choss.player);
+        assert_eq!(choss.board, opened);
+        assert!(game.cached_moves.is_empty());
+        assert_eq!(game.status, GameStatus::Placing);
+        assert_eq!(game.takebacks, MAX_TAKEBACKS - 1);
+    }
+
+    #[test]
+    fn carl_refuses_undoing_the_players_move() {
+        let mut game = Game::new();
+        game.opponent = 1;
+        game.new_match();
+        let mut choss = ChossGame::new(PieceColor::White);
+        let mut last_move = LastMove(None);
+        choss.play(Pos(1, 6), &vec![Action::Go(Pos(1, 5))]);
+        choss.play(Pos(1, 1), &vec![Action::Go(Pos(1, 2))]);
+        let played = choss.board.clone();
+        assert!(!rewind_player_move(&mut game, &mut choss, &mut last_move));
+        assert_eq!(choss.halfmove(), 2);
+        assert_eq!(choss.board, played);
+        // and Alice stops agreeing once the takebacks are spent
+        game.opponent = 0;
+        game.new_match();
+        game.takebacks = 0;
+        assert!(!rewind_player_move(&mut game, &mut choss, &mut last_move));
+        assert_eq!(choss.board, played);
+    }
+
+    #[test]
+    fn moves_are_recorded_from_events() {
+        let mut world = World::new();
+        let choss = ChossGame::new(PieceColor::White);
+        world.insert_resource(PgnGame::new(choss.board.clone(), choss.turn_color()));
+        world.insert_resource(choss);
+        world.insert_resource(Game::new());
+        world.insert_resource(Events::<MovePlayed>::default());
+        world.insert_resource(Events::<Undo>::default());
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(record_moves);
+        let mut moves = world.get_resource_mut::<Events<MovePlayed>>().unwrap();
+        for (color, pos, to) in [
+            (PieceColor::White, Pos(1, 6), Pos(1, 5)),
+            (PieceColor::Black, Pos(1, 1), Pos(1, 2)),
+            (PieceColor::White, Pos(2, 6), Pos(2, 5)),
+        ] {
+            moves.send(MovePlayed {
+                color,
+                pos,
+                actions: vec![Action::Go(to)],
+            });
+        }
+        world.get_resource_mut::<Game>().unwrap().eval_log =
+            vec![EvalEntry::new(0, PieceColor::White, 0., 0., 0., 0.); 3]
+                .into_iter()
+                .enumerate()
+                .map(|(halfmove, entry)| EvalEntry { halfmove, ..entry })
+                .collect();
+        stage.run(&mut world);
+        let recorded = |world: &World| world.get_resource::<PgnGame>().unwrap().numbered_moves();
+        assert_eq!(recorded(&world), vec!["1. b3 b6", "2. c3"]);
+        // going back forgets the moves and the judgements after the position
+        world
+            .get_resource_mut::<Events<Undo>>()
+            .unwrap()
+            .send(Undo { halfmove: 1 });
+        stage.run(&mut world);
+        assert_eq!(recorded(&world), vec!["1. b3"]);
+        assert_eq!(world.get_resource::<Game>().unwrap().eval_log.len(), 1);
+    }
+
+    #[test]
+    fn saved_games_resume() {
+        let mut game = Game::new();
+        game.opponent = 1;
+        game.new_match();
+        game.status = GameStatus::Playing;
+        let mut choss = ChossGame::new(PieceColor::Black);
+        let mut pgn = PgnGame::new(choss.board.clone(), choss.turn_color());
+        for (pos, to) in [(Pos(1, 6), Pos(1, 5)), (Pos(1, 1), Pos(1, 2))] {
+            pgn.push(pos, &[Action::Go(to)]);
+            choss.play(pos, &vec![Action::Go(to)]);
+        }
+        pgn.annotate_last("?!");
+        game.eval_log.push(EvalEntry::new(
+            1,
+            PieceColor::Black,
+            0.,
+            1.5,
+            -1.5,
+            REFERENCE_VALUE,
+        ));
+        game.last_state = Some(choss.clone());
+        game.cached_moves = vec![(0.5, Pos(0, 6), vec![Action::Go(Pos(0, 5))])];
+        game.undo_unlocked = true;
+        let content =
+            to_recovery(&SavedGame::new(&game, &choss, &pgn, &MoveTimes::default())).unwrap();
+
+        let mut resumed = Game::new();
+        let mut resumed_choss = ChossGame::new(PieceColor::White);
+        let mut resumed_pgn = PgnGame::new(Board::new(5, 8), PieceColor::White);
+        let saved: SavedGame = from_recovery(&content).unwrap();
+        saved.resume(
+            &mut resumed,
+            &mut resumed_choss,
+            &mut resumed_pgn,
+            &mut MoveTimes::default(),
+        );
+        assert_eq!(resumed.status, GameStatus::Placing);
+        assert_eq!(resumed.opponent, 1);
+        assert!(resumed.undo_unlocked);
+        assert_eq!(resumed.cached_moves, game.cached_moves);
+        assert_eq!(resumed.carl_lines, game.carl_lines);
+        assert_eq!(
+            resumed
+                .eval_log
+                .iter()
+                .map(|entry| (entry.halfmove, entry.color, entry.loss, entry.annotation))
+                .collect::<Vec<_>>(),
+            vec![(1, PieceColor::Black, 1.5, "?!")]
+        );
+        assert_eq!(resumed_choss.player, PieceColor::Black);
+        assert_eq!(resumed_choss.board.squares, choss.board.squares);
+        assert_eq!(resumed_choss.halfmove(), 2);
+        assert_eq!(resumed_choss.history, choss.history);
+        assert_eq!(resumed_pgn.to_pgn(), pgn.to_pgn());
+        // and the match goes on from there
+        let (pos, actions) = resumed_choss.board.moves(PieceColor::White, true).remove(0);
+        resumed_choss.play(pos, &actions);
+        assert_eq!(resumed_choss.halfmove(), 3);
+    }
+
+    #[test]
+    fn takebacks_run_out() {
+        let mut game = Game::new();
+        game.new_match();
+        // nothing to take back yet
+        assert!(!game.request_takeback());
+        for left in (0..MAX_TAKEBACKS).rev() {
+            game.takeback_state = Some(ChossGame::new(PieceColor::White));
+            assert!(game.request_takeback());
+            assert_eq!(game.takebacks, left);
+            assert!(game.last_state.is_some());
+        }
+        game.takeback_state = Some(ChossGame::new(PieceColor::White));
+        assert!(!game.request_takeback());
+        // Carl never agrees
+        game.opponent = 1;
+        game.new_match();
+        game.takeback_state = Some(ChossGame::new(PieceColor::White));
+        assert!(!game.request_takeback());
+    }
+
+    #[test]
+    fn reset_clears_the_match() {
+        let mut game = Game::new();
+        game.opponent = 1;
+        game.new_match();
+        game.cached_moves = vec![(0., Pos(0, 0), Vec::new())];
+        game.last_eval = Some(3.);
+        game.status = GameStatus::Playing;
+        // one of Carl's undo lines, then a regular line
+        game.get_dialogue(-3., REFERENCE_VALUE);
+        game.get_dialogue(9., REFERENCE_VALUE);
+        assert!(!game.lines_sent.is_empty());
+        assert_ne!(game.carl_lines, carl_lines());
+        reset_game(&mut game);
+        assert!(game.lines_sent.is_empty());
+        assert_eq!(game.carl_lines, carl_lines());
+        assert!(game.cached_moves.is_empty());
+        assert_eq!(game.last_eval, None);
+        assert!(game.status == GameStatus::Preparing);
+        // still against the same opponent
+        assert_eq!(game.opponent, 1);
+    }
+
+    #[test]
+    fn summary_counts_bad_moves() {
+        let eval_log = vec![
+            EvalEntry::new(0, PieceColor::White, 0., 0.5, 0., REFERENCE_VALUE),
+            EvalEntry::new(1, PieceColor::Black, 0.5, 0.5, 0., REFERENCE_VALUE),
+            EvalEntry::new(2, PieceColor::White, 0.5, 3., 0., REFERENCE_VALUE),
+            EvalEntry::new(3, PieceColor::Black, 3., 4., 0., REFERENCE_VALUE),
+            EvalEntry::new(4, PieceColor::White, 4., f32::INFINITY, 0., REFERENCE_VALUE),
+            EvalEntry::new(5, PieceColor::Black, 20., 18., 0., REFERENCE_VALUE),
+        ];
+        let summary = GameSummary::new(&eval_log);
+        assert_eq!(summary.blunders, [1, 0]);
+        assert_eq!(summary.mistakes, [1, 0]);
+        assert_eq!(summary.biggest_swing, Some((4, PieceColor::White, 16.)));
+        assert_eq!(summary.average_loss, [(50. + 250. + 1600.) / 3., 100. / 3.]);
+        assert_eq!(GameSummary::new(&[]).biggest_swing, None);
+    }
+
+    #[test]
+    fn draw_by_repetition() {
+        let mut choss = ChossGame::new(PieceColor::White);
+        for _ in 0..2 {
+            for (from, to) in [
+                (Pos(1, 7), Pos(2, 5)),
+                (Pos(1, 0), Pos(2, 2)),
+                (Pos(2, 5), Pos(1, 7)),
+                (Pos(2, 2), Pos(1, 0)),
+            ] {
+                assert!(!automatic_draw(&choss, false));
+                choss.play(from, &vec![Action::Go(to)]);
+            }
+        }
+        // the game ends by itself, or goes on until someone claims it
+        assert!(automatic_draw(&choss, false));
+        assert!(!automatic_draw(&choss, true));
+        let mut game = Game::new();
+        game.last_eval = Some(-1.);
+        assert!(game.ai_claims_draw(&choss));
+        game.last_eval = Some(2.);
+        assert!(!game.ai_claims_draw(&choss));
+        // nothing to claim on a fresh board
+        game.last_eval = Some(-1.);
+        assert!(!game.ai_claims_draw(&ChossGame::new(PieceColor::White)));
+    }
+
+    #[test]
+    fn undo_only_touches_changed_squares() {
+        let before = Board::from_fen("4k/5/1p3/5/2N2/5/5/K4 w").unwrap();
+        let after = before.play(PieceColor::White, Pos(2, 4), &[Action::Go(Pos(1, 2))]);
+        // undoing the capture slides the knight back and brings the pawn back to life
+        let changes = SpriteChanges::new(&after, &before);
+        assert_eq!(changes.moved, vec![(Pos(1, 2), Pos(2, 4))]);
+        assert!(changes.despawned.is_empty());
+        assert_eq!(changes.spawned, vec![Pos(1, 2)]);
+
+        // applying the changes to the shown sprites gives back the board's pieces
+        let mut shown: HashSet<Pos> = after.pieces().map(|(pos, _, _)| pos).collect();
+        for (from, _) in &changes.moved {
+            shown.remove(from);
+        }
+        for pos in &changes.despawned {
+            shown.remove(pos);
+        }
+        shown.extend(changes.moved.iter().map(|(_, to)| *to));
+        shown.extend(changes.spawned);
+        let expected: HashSet<Pos> = before.pieces().map(|(pos, _, _)| pos).collect();
+        assert_eq!(shown, expected);
+
+        // a pawn that only changed status keeps its sprite
+        let leaped = before.play(PieceColor::Black, Pos(1, 2), &[Action::Go(Pos(1, 3))]);
+        let changes = SpriteChanges::new(
+            &leaped,
+            &leaped.play(PieceColor::White, Pos(0, 7), &[Action::Go(Pos(0, 6))]),
+        );
+        assert_eq!(changes.moved, vec![(Pos(0, 7), Pos(0, 6))]);
+        assert!(changes.despawned.is_empty() && changes.spawned.is_empty());
+    }
+
+    #[test]
+    fn same_seed_same_game() {
+        let self_play = |seed| {
+            let mut rng = GameRng::new(Some(seed));
+            let mut choss = ChossGame::new(PieceColor::White);
+            let mut played = Vec::new();
+            for _ in 0..6 {
+                let moves = negamax(
+                    &choss.board,
+                    choss.turn_color(),
+                    1,
+                    choss.win_condition,
+                    choss.checks,
+                    0.5,
+                    &PieceValues::classical(),
+                    QUIESCENCE_DEPTH,
+                );
+                let (_, pos, actions) = candidate_moves(moves, &mut rng.0).pop().unwrap();
+                choss.play(pos, &actions);
+                played.push((pos, actions));
+            }
+            played
+        };
+        assert_eq!(self_play(7), self_play(7));
+    }
+
+    #[test]
+    fn illegal_clicks() {
+        let choss = ChossGame::new(PieceColor::White);
+        let settings = Settings::default();
+        let mut game = Game::new();
+        let (mut premove, mut pending) = (PreMove(None), PendingMove(None));
+        let mut click = |pos, selected: &mut SelectedSquare| {
+            click_square(
+                pos,
+                &choss,
+                &settings,
+                &mut game,
+                selected,
+                &mut premove,
+                &mut pending,
+            )
+        };
+        // the knight can't go there
+        let mut selected = SelectedSquare(Some(Pos(1, 7)));
+        assert!(click(Pos(1, 4), &mut selected));
+        // switching to another piece is fine
+        let mut selected = SelectedSquare(Some(Pos(1, 7)));
+        assert!(!click(Pos(0, 6), &mut selected));
+        assert_eq!(selected.0, Some(Pos(0, 6)));
+        // so is clicking around with nothing selected
+        let mut selected = SelectedSquare(None);
+        assert!(!click(Pos(1, 4), &mut selected));
+        assert!(!click(Pos(2, 4), &mut selected));
+        // a legal move isn't flagged
+        let mut selected = SelectedSquare(Some(Pos(1, 7)));
+        assert!(!click(Pos(2, 5), &mut selected));
+    }
+
+    fn ai_world(choss: ChossGame) -> World {
+        // a game under way against the AI, with what its turn needs
+        let mut world = World::new();
+        let opponent = world.spawn().id();
+        let mut game = Game::new();
+        game.opponents = vec![opponent, opponent];
+        game.status = GameStatus::Playing;
+        world.insert_resource(PgnGame::new(choss.board.clone(), choss.turn_color()));
+        world.insert_resource(choss);
+        world.insert_resource(game);
+        world.insert_resource(Settings::default());
+        world.insert_resource(Lang::english());
+        world.insert_resource(MoveTimes::default());
+        world.insert_resource(GameRng::new(Some(0)));
+        world.insert_resource(AITable::default());
+        world
+    }
+
+    #[test]
+    fn ai_opens_against_black() {
+        let ai_first_move = |player| {
+            let mut world = ai_world(ChossGame::new(player));
+            let mut stage = SystemStage::single_threaded();
+            stage.add_system(start_ai_turn);
+            stage.run(&mut world);
+            let choss = world.get_resource::<ChossGame>().unwrap();
+            world
+                .get_resource::<Game>()
+                .unwrap()
+                .to_play
+                .as_ref()
+                .map(|(pos, _)| *choss.board.get(*pos).unwrap())
+        };
+        // the AI picks one of White's pieces to move
+        assert!(matches!(
+            ai_first_move(PieceColor::Black),
+            Some(Some((PieceColor::White, _)))
+        ));
+        // and waits for the player otherwise
+        assert_eq!(ai_first_move(PieceColor::White), None);
+    }
+
+    #[test]
+    fn ai_without_moves_ends_the_game() {
+        // Black is mated in the imported position and it's the AI's turn
+        let choss = ChossGame::from_fen("k4/1Q3/2K2/5/5/5/5/5 b", PieceColor::White).unwrap();
+        let mut world = ai_world(choss);
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(start_ai_turn);
+        stage.run(&mut world);
+        let game = world.get_resource::<Game>().unwrap();
+        assert_eq!(game.status, GameStatus::Win);
+        assert_eq!(game.end_reason, Some(EndReason::Checkmate));
+        assert!(game.to_play.is_none());
+    }
+
+    #[test]
+    fn coin_flip_for_the_side() {
+        let mut rng = GameRng::new(Some(3));
+        let sides: Vec<_> = (0..4).map(|_| Side::Random.pick(&mut rng.0)).collect();
+        assert_eq!(
+            sides,
+            vec![
+                PieceColor::White,
+                PieceColor::Black,
+                PieceColor::White,
+                PieceColor::Black
+            ]
+        );
+        // the chosen sides don't need the coin
+        assert_eq!(Side::Black.pick(&mut rng.0), PieceColor::Black);
+        assert_eq!(Side::White.pick(&mut rng.0), PieceColor::White);
+    }
+
+    #[test]
+    fn next_variant_is_shown() {
+        let variant = Variant {
+            setup: Setup::Horde,
+            win_condition: WinCondition::ThreeCheck,
+            side: Side::Random,
+        };
+        assert_eq!(
+            variant_text(&variant, &Lang::english()),
+            "Next game: the horde, three checks win, side: random"
+        );
+    }
+
+    #[test]
+    fn pondering() {
+        let ponders = |world: &mut World| world.query::<&Ponder>().iter(world).count();
+        let setup = || {
+            let mut world = ai_world(ChossGame::new(PieceColor::White));
+            world.get_resource_mut::<Game>().unwrap().predicted =
+                Some((0, Pos(1, 7), vec![Action::Go(Pos(2, 5))]));
+            world.insert_resource(AsyncComputeTaskPool(TaskPool::new()));
+            let mut stage = SystemStage::single_threaded();
+            stage.add_system(ponder.before("ai"));
+            stage.add_system(start_ai_turn.label("ai"));
+            // the search starts while the player thinks
+            stage.run(&mut world);
+            assert_eq!(ponders(&mut world), 1);
+            stage.run(&mut world);
+            assert_eq!(ponders(&mut world), 1);
+            (world, stage)
+        };
+
+        let to_play = |world: &World| world.get_resource::<Game>().unwrap().to_play.is_some();
+
+        // the search is dropped when the player plays something else,
+        // what it found is kept in the table for the AI's own search
+        let (mut world, mut stage) = setup();
+        let mut choss = world.get_resource_mut::<ChossGame>().unwrap();
+        choss.play(Pos(0, 6), &vec![Action::Go(Pos(0, 5))]);
+        stage.run(&mut world);
+        assert_eq!(ponders(&mut world), 0);
+        assert!(to_play(&world));
+
+        // the expected move is answered with the pondered search, the game goes on until it's done
+        let (mut world, mut stage) = setup();
+        let mut choss = world.get_resource_mut::<ChossGame>().unwrap();
+        choss.play(Pos(1, 7), &vec![Action::Go(Pos(2, 5))]);
+        while !to_play(&world) {
+            assert_eq!(ponders(&mut world), 1);
+            stage.run(&mut world);
+        }
+        assert_eq!(ponders(&mut world), 0);
+    }
+
+    #[test]
+    fn bare_kings_after_a_capture() {
+        let mut choss = ChossGame::from_fen("k4/5/5/5/5/5/1r3/K4 w", PieceColor::White).unwrap();
+        assert_eq!(
+            game_over(&choss, PieceColor::Black, &Settings::default()),
+            None
+        );
+        // the king takes the last rook
+        choss.play(Pos(0, 7), &vec![Action::Go(Pos(1, 6))]);
+        assert_eq!(
+            game_over(&choss, PieceColor::White, &Settings::default()),
+            Some((GameStatus::Draw, EndReason::InsufficientMaterial))
+        );
+    }
+
+    #[test]
+    fn move_limit() {
+        let settings = Settings {
+            move_limit: Some(2),
+            ..Default::default()
+        };
+        let mut choss = ChossGame::new(PieceColor::White);
+        let knight_moves = [
+            (Pos(1, 7), Pos(2, 5)),
+            (Pos(1, 0), Pos(2, 2)),
+            (Pos(2, 5), Pos(1, 7)),
+            (Pos(2, 2), Pos(1, 0)),
+        ];
+        for (i, (from, to)) in knight_moves.into_iter().enumerate() {
+            assert_eq!(
+                game_over(&choss, choss.turn_color().next(), &settings),
+                None
+            );
+            assert_eq!(
+                game_over(&choss, choss.turn_color().next(), &Settings::default()),
+                None
+            );
+            let color = choss.turn_color();
+            choss.play(from, &vec![Action::Go(to)]);
+            if i == 3 {
+                assert_eq!(
+                    game_over(&choss, color, &settings),
+                    Some((GameStatus::Draw, EndReason::MoveLimit))
+                );
+                assert_eq!(game_over(&choss, color, &Settings::default()), None);
+            }
+        }
     }
 }
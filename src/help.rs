@@ -0,0 +1,230 @@
+use crate::{
+    keybindings::{key_name, Control, Controls, KeyBindings, RebindError, CONTROLS},
+    lang::Lang,
+    settings::Settings,
+};
+use bevy::prelude::*;
+
+const COLUMN_WIDTH: f32 = 350.;
+
+#[derive(Component)]
+struct HelpBackground;
+
+// the controls are split in two columns so they fit in the window
+#[derive(Component)]
+struct HelpColumn(usize);
+
+// the help is also where the keys are changed
+#[derive(Default)]
+pub struct Help {
+    pub shown: bool,
+    // the line of the control to change
+    selected: usize,
+    // the control waiting for its new key
+    pub rebinding: Option<Control>,
+    // what the last key change did, under the title
+    message: String,
+}
+
+fn help_lines(help: &Help, bindings: &KeyBindings, lang: &Lang) -> Vec<String> {
+    CONTROLS
+        .iter()
+        .enumerate()
+        .map(|(i, control)| {
+            let key = if help.rebinding == Some(*control) {
+                "...".to_string()
+            } else {
+                key_name(bindings.key(*control))
+            };
+            let marker = if i == help.selected { ">" } else { " " };
+            format!("{}{:>9}  {}", marker, key, lang.get(&control.lang_key()))
+        })
+        .collect()
+}
+
+fn rebind_message(
+    lang: &Lang,
+    bindings: &KeyBindings,
+    control: Control,
+    key: KeyCode,
+    result: Result<Option<Control>, RebindError>,
+) -> String {
+    match result {
+        Ok(None) => lang.format(
+            "help.rebound",
+            &[&lang.get(&control.lang_key()), &key_name(key)],
+        ),
+        Ok(Some(other)) => lang.format(
+            "help.swapped",
+            &[
+                &lang.get(&other.lang_key()),
+                &key_name(key),
+                &key_name(bindings.key(other)),
+            ],
+        ),
+        Err(RebindError::Reserved) => lang.format("help.reserved", &[&key_name(key)]),
+        Err(RebindError::Unbindable) => lang.format("help.unbindable", &[&key_name(key)]),
+    }
+}
+
+fn setup(mut commands: Commands, server: Res<AssetServer>) {
+    // over the board and the pieces
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgba(0.1, 0.1, 0.15, 0.95),
+                custom_size: Some(Vec2::new(2. * COLUMN_WIDTH + 20., 400.)),
+                ..Default::default()
+            },
+            transform: Transform::from_xyz(0., 0., 5.),
+            visibility: Visibility { is_visible: false },
+            ..Default::default()
+        })
+        .insert(HelpBackground);
+    let text_style = TextStyle {
+        font: server.load("fonts/RobotoMono-Regular.ttf"),
+        font_size: 12.0,
+        color: Color::rgb(0.8, 0.8, 0.8),
+    };
+    let text_alignment = TextAlignment {
+        vertical: VerticalAlign::Top,
+        horizontal: HorizontalAlign::Left,
+    };
+    for column in 0..2 {
+        commands
+            .spawn_bundle(Text2dBundle {
+                text: Text::with_section("", text_style.clone(), text_alignment),
+                transform: Transform::from_xyz((column as f32 - 1.) * COLUMN_WIDTH, 190., 6.),
+                visibility: Visibility { is_visible: false },
+                ..Default::default()
+            })
+            .insert(HelpColumn(column));
+    }
+}
+
+fn toggle_help(controls: Controls, mut help: ResMut<Help>) {
+    if controls.just_pressed(Control::Help) {
+        help.shown = !help.shown;
+        help.message.clear();
+    }
+}
+
+// the menu is driven by the cursor controls, whatever their keys are
+fn edit_bindings(
+    keys: Res<Input<KeyCode>>,
+    mut help: ResMut<Help>,
+    mut settings: ResMut<Settings>,
+    lang: Res<Lang>,
+) {
+    if !help.shown {
+        return;
+    }
+    let bindings = &settings.key_bindings;
+    if let Some(control) = help.rebinding {
+        if keys.just_pressed(bindings.key(Control::Cancel)) {
+            help.rebinding = None;
+        } else if let Some(key) = keys.get_just_pressed().next() {
+            let result = settings.key_bindings.rebind(control, *key);
+            help.message = rebind_message(&lang, &settings.key_bindings, control, *key, result);
+            help.rebinding = None;
+        }
+    } else if keys.just_pressed(bindings.key(Control::CursorUp)) {
+        help.selected = (help.selected + CONTROLS.len() - 1) % CONTROLS.len();
+    } else if keys.just_pressed(bindings.key(Control::CursorDown)) {
+        help.selected = (help.selected + 1) % CONTROLS.len();
+    } else if keys.just_pressed(bindings.key(Control::Play)) {
+        help.rebinding = Some(CONTROLS[help.selected]);
+        help.message.clear();
+    } else if keys.just_pressed(bindings.key(Control::Cancel)) {
+        help.shown = false;
+    }
+}
+
+fn display_help(
+    mut query_background: Query<&mut Visibility, (With<HelpBackground>, Without<HelpColumn>)>,
+    mut query_text: Query<(&mut Text, &mut Visibility, &HelpColumn)>,
+    help: Res<Help>,
+    bindings: Res<KeyBindings>,
+    lang: Res<Lang>,
+) {
+    if !(help.is_changed() || bindings.is_changed() || lang.is_changed()) {
+        return;
+    }
+    if let Ok(mut visibility) = query_background.get_single_mut() {
+        visibility.is_visible = help.shown;
+    }
+    let lines = help_lines(&help, &bindings, &lang);
+    let half = lines.len() - lines.len() / 2;
+    for (mut text, mut visibility, column) in query_text.iter_mut() {
+        visibility.is_visible = help.shown;
+        // the title and the message are over the first column, the second one starts on the same line
+        let title = if column.0 == 0 {
+            format!("{}\n{}", lang.get("help.title"), help.message)
+        } else {
+            "\n".to_string()
+        };
+        let content = lines
+            .iter()
+            .skip(column.0 * half)
+            .take(half)
+            .cloned()
+            .collect::<Vec<String>>()
+            .join("\n");
+        text.sections[0].value = format!("{}\n\n{}", title, content);
+    }
+}
+
+pub struct HelpPlugin;
+
+impl Plugin for HelpPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Help::default())
+            .add_startup_system(setup)
+            .add_system(toggle_help)
+            .add_system(edit_bindings.before("settings"))
+            .add_system(display_help.after("settings"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn help_lists_the_controls() {
+        let mut help = Help::default();
+        let lines = help_lines(&help, &KeyBindings::default(), &Lang::english());
+        assert_eq!(lines.len(), CONTROLS.len());
+        assert_eq!(lines[0], ">       F1  show or hide this help");
+        assert!(lines.contains(&" Backspace  take back your last move".to_string()));
+        help.rebinding = Some(Control::Takeback);
+        let lines = help_lines(&help, &KeyBindings::default(), &Lang::english());
+        assert!(lines.contains(&"       ...  take back your last move".to_string()));
+    }
+
+    #[test]
+    fn rebinding_messages() {
+        let lang = Lang::english();
+        let mut bindings = KeyBindings::default();
+        let mut rebind = |control, key| {
+            let result = bindings.rebind(control, key);
+            rebind_message(&lang, &bindings, control, key, result)
+        };
+        assert_eq!(
+            rebind(Control::Takeback, KeyCode::Key1),
+            "\"take back your last move\" is now on Key1"
+        );
+        assert_eq!(
+            rebind(Control::Takeback, KeyCode::A),
+            "\"show the analysis\" was on A, it's now on Key1"
+        );
+        assert_eq!(
+            rebind(Control::Takeback, KeyCode::Escape),
+            "Esc can't be changed or taken"
+        );
+        assert_eq!(
+            rebind(Control::Takeback, KeyCode::LShift),
+            "LShift can't be bound"
+        );
+    }
+}
@@ -0,0 +1,478 @@
+use crate::help::Help;
+use crate::settings::Settings;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+// everything the keyboard can do, the mouse only plays moves
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Control {
+    Help,
+    CursorUp,
+    CursorDown,
+    CursorLeft,
+    CursorRight,
+    Play,
+    Cancel,
+    Takeback,
+    UndoMove,
+    UndoUndo,
+    NewMatch,
+    Restart,
+    Resume,
+    ClaimDraw,
+    CopyPgn,
+    CopyFen,
+    ImportFen,
+    Puzzles,
+    FlipBoard,
+    Blindfold,
+    Peek,
+    Analysis,
+    EvalGraph,
+    DialogueLog,
+    LogUp,
+    LogDown,
+    NextSetup,
+    NextWinCondition,
+    NextSide,
+    ConfirmMoves,
+    Tutorial,
+    HighContrast,
+    ClaimDraws,
+    SkipUndoAnimation,
+    Threats,
+    PieceValues,
+    Handicap,
+    Language,
+    FasterMoves,
+    SlowerMoves,
+    PieceSet,
+    Vsync,
+    Debug,
+    Snapshot,
+}
+
+// in the order of the help overlay
+pub const CONTROLS: [Control; 44] = [
+    Control::Help,
+    Control::CursorUp,
+    Control::CursorDown,
+    Control::CursorLeft,
+    Control::CursorRight,
+    Control::Play,
+    Control::Cancel,
+    Control::Takeback,
+    Control::UndoMove,
+    Control::UndoUndo,
+    Control::NewMatch,
+    Control::Restart,
+    Control::Resume,
+    Control::ClaimDraw,
+    Control::CopyPgn,
+    Control::CopyFen,
+    Control::ImportFen,
+    Control::Puzzles,
+    Control::FlipBoard,
+    Control::Blindfold,
+    Control::Peek,
+    Control::Analysis,
+    Control::EvalGraph,
+    Control::DialogueLog,
+    Control::LogUp,
+    Control::LogDown,
+    Control::NextSetup,
+    Control::NextWinCondition,
+    Control::NextSide,
+    Control::ConfirmMoves,
+    Control::Tutorial,
+    Control::HighContrast,
+    Control::ClaimDraws,
+    Control::SkipUndoAnimation,
+    Control::Threats,
+    Control::PieceValues,
+    Control::Handicap,
+    Control::Language,
+    Control::FasterMoves,
+    Control::SlowerMoves,
+    Control::PieceSet,
+    Control::Vsync,
+    Control::Debug,
+    Control::Snapshot,
+];
+
+impl Control {
+    pub fn default_key(self) -> KeyCode {
+        match self {
+            Control::Help => KeyCode::F1,
+            Control::CursorUp => KeyCode::Up,
+            Control::CursorDown => KeyCode::Down,
+            Control::CursorLeft => KeyCode::Left,
+            Control::CursorRight => KeyCode::Right,
+            Control::Play => KeyCode::Return,
+            Control::Cancel => KeyCode::Escape,
+            Control::Takeback => KeyCode::Back,
+            Control::UndoMove => KeyCode::Delete,
+            Control::UndoUndo => KeyCode::U,
+            Control::NewMatch => KeyCode::N,
+            Control::Restart => KeyCode::R,
+            Control::Resume => KeyCode::O,
+            Control::ClaimDraw => KeyCode::Y,
+            Control::CopyPgn => KeyCode::P,
+            Control::CopyFen => KeyCode::F5,
+            Control::ImportFen => KeyCode::I,
+            Control::Puzzles => KeyCode::Z,
+            Control::FlipBoard => KeyCode::F,
+            Control::Blindfold => KeyCode::B,
+            Control::Peek => KeyCode::V,
+            Control::Analysis => KeyCode::A,
+            Control::EvalGraph => KeyCode::J,
+            Control::DialogueLog => KeyCode::L,
+            Control::LogUp => KeyCode::PageUp,
+            Control::LogDown => KeyCode::PageDown,
+            Control::NextSetup => KeyCode::S,
+            Control::NextWinCondition => KeyCode::W,
+            Control::NextSide => KeyCode::X,
+            Control::ConfirmMoves => KeyCode::C,
+            Control::Tutorial => KeyCode::T,
+            Control::HighContrast => KeyCode::H,
+            Control::ClaimDraws => KeyCode::D,
+            Control::SkipUndoAnimation => KeyCode::Q,
+            Control::Threats => KeyCode::E,
+            Control::PieceValues => KeyCode::M,
+            Control::Handicap => KeyCode::G,
+            Control::Language => KeyCode::F2,
+            Control::FasterMoves => KeyCode::Minus,
+            Control::SlowerMoves => KeyCode::Equals,
+            Control::PieceSet => KeyCode::K,
+            Control::Vsync => KeyCode::F4,
+            Control::Debug => KeyCode::F3,
+            Control::Snapshot => KeyCode::F12,
+        }
+    }
+
+    // in the settings file
+    pub fn name(self) -> &'static str {
+        match self {
+            Control::Help => "help",
+            Control::CursorUp => "cursor_up",
+            Control::CursorDown => "cursor_down",
+            Control::CursorLeft => "cursor_left",
+            Control::CursorRight => "cursor_right",
+            Control::Play => "play",
+            Control::Cancel => "cancel",
+            Control::Takeback => "takeback",
+            Control::UndoMove => "undo_move",
+            Control::UndoUndo => "undo_undo",
+            Control::NewMatch => "new_match",
+            Control::Restart => "restart",
+            Control::Resume => "resume",
+            Control::ClaimDraw => "claim_draw",
+            Control::CopyPgn => "copy_pgn",
+            Control::CopyFen => "copy_fen",
+            Control::ImportFen => "import_fen",
+            Control::Puzzles => "puzzles",
+            Control::FlipBoard => "flip_board",
+            Control::Blindfold => "blindfold",
+            Control::Peek => "peek",
+            Control::Analysis => "analysis",
+            Control::EvalGraph => "eval_graph",
+            Control::DialogueLog => "dialogue_log",
+            Control::LogUp => "log_up",
+            Control::LogDown => "log_down",
+            Control::NextSetup => "next_setup",
+            Control::NextWinCondition => "next_win_condition",
+            Control::NextSide => "next_side",
+            Control::ConfirmMoves => "confirm_moves",
+            Control::Tutorial => "tutorial",
+            Control::HighContrast => "high_contrast",
+            Control::ClaimDraws => "claim_draws",
+            Control::SkipUndoAnimation => "skip_undo_animation",
+            Control::Threats => "threats",
+            Control::PieceValues => "piece_values",
+            Control::Handicap => "handicap",
+            Control::Language => "language",
+            Control::FasterMoves => "faster_moves",
+            Control::SlowerMoves => "slower_moves",
+            Control::PieceSet => "piece_set",
+            Control::Vsync => "vsync",
+            Control::Debug => "debug",
+            Control::Snapshot => "snapshot",
+        }
+    }
+
+    pub fn lang_key(self) -> String {
+        format!("controls.{}", self.name())
+    }
+}
+
+// these keep their key, so the help can always be opened and a rebinding cancelled
+const FIXED_CONTROLS: [Control; 2] = [Control::Help, Control::Cancel];
+
+// the keys a control can be bound to, the modifiers and media keys are left out
+const BINDABLE_KEYS: [KeyCode; 73] = [
+    KeyCode::A,
+    KeyCode::B,
+    KeyCode::C,
+    KeyCode::D,
+    KeyCode::E,
+    KeyCode::F,
+    KeyCode::G,
+    KeyCode::H,
+    KeyCode::I,
+    KeyCode::J,
+    KeyCode::K,
+    KeyCode::L,
+    KeyCode::M,
+    KeyCode::N,
+    KeyCode::O,
+    KeyCode::P,
+    KeyCode::Q,
+    KeyCode::R,
+    KeyCode::S,
+    KeyCode::T,
+    KeyCode::U,
+    KeyCode::V,
+    KeyCode::W,
+    KeyCode::X,
+    KeyCode::Y,
+    KeyCode::Z,
+    KeyCode::Key0,
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+    KeyCode::F1,
+    KeyCode::F2,
+    KeyCode::F3,
+    KeyCode::F4,
+    KeyCode::F5,
+    KeyCode::F6,
+    KeyCode::F7,
+    KeyCode::F8,
+    KeyCode::F9,
+    KeyCode::F10,
+    KeyCode::F11,
+    KeyCode::F12,
+    KeyCode::Up,
+    KeyCode::Down,
+    KeyCode::Left,
+    KeyCode::Right,
+    KeyCode::Return,
+    KeyCode::Escape,
+    KeyCode::Back,
+    KeyCode::Space,
+    KeyCode::Tab,
+    KeyCode::Insert,
+    KeyCode::Delete,
+    KeyCode::Home,
+    KeyCode::End,
+    KeyCode::PageUp,
+    KeyCode::PageDown,
+    KeyCode::Minus,
+    KeyCode::Equals,
+    KeyCode::Comma,
+    KeyCode::Period,
+    KeyCode::Slash,
+    KeyCode::Backslash,
+    KeyCode::Semicolon,
+    KeyCode::Apostrophe,
+    KeyCode::LBracket,
+    KeyCode::RBracket,
+];
+
+#[derive(Debug, PartialEq)]
+pub enum RebindError {
+    // the control or the key belongs to one of the fixed controls
+    Reserved,
+    // the mouse buttons always play moves, and a few keys can't be bound at all
+    Unbindable,
+}
+
+// the key of each control, the same key can't do two things
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyBindings(HashMap<Control, KeyCode>);
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings(
+            CONTROLS
+                .iter()
+                .map(|control| (*control, control.default_key()))
+                .collect(),
+        )
+    }
+}
+
+impl KeyBindings {
+    pub fn key(&self, control: Control) -> KeyCode {
+        self.0[&control]
+    }
+
+    pub fn control(&self, key: KeyCode) -> Option<Control> {
+        CONTROLS
+            .iter()
+            .find(|control| self.key(**control) == key)
+            .copied()
+    }
+
+    pub fn rebind(
+        &mut self,
+        control: Control,
+        key: KeyCode,
+    ) -> Result<Option<Control>, RebindError> {
+        if FIXED_CONTROLS
+            .iter()
+            .any(|fixed| *fixed == control || self.key(*fixed) == key)
+        {
+            return Err(RebindError::Reserved);
+        }
+        if !BINDABLE_KEYS.contains(&key) {
+            return Err(RebindError::Unbindable);
+        }
+        // the control that had the key gets the old one, so no key is left doing two things
+        let other = self.control(key).filter(|other| *other != control);
+        if let Some(other) = other {
+            self.0.insert(other, self.key(control));
+        }
+        self.0.insert(control, key);
+        Ok(other)
+    }
+
+    pub fn parse(&mut self, name: &str, key_name: &str) {
+        // an unknown control or key is ignored, like the other settings
+        let control = CONTROLS.iter().find(|control| control.name() == name);
+        let key = BINDABLE_KEYS
+            .iter()
+            .find(|key| format!("{:?}", key) == key_name);
+        if let (Some(control), Some(key)) = (control, key) {
+            self.rebind(*control, *key).ok();
+        }
+    }
+
+    pub fn serialize(&self) -> String {
+        // only the changed keys, so a new default reaches the ones who kept the old one
+        CONTROLS
+            .iter()
+            .filter(|control| self.key(**control) != control.default_key())
+            .map(|control| format!("key.{}={:?}\n", control.name(), self.key(*control)))
+            .collect()
+    }
+}
+
+// the keyboard as the systems see it, by what the keys do
+#[derive(SystemParam)]
+pub struct Controls<'w, 's> {
+    keys: Res<'w, Input<KeyCode>>,
+    bindings: Res<'w, KeyBindings>,
+    help: Res<'w, Help>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+impl<'w, 's> Controls<'w, 's> {
+    pub fn just_pressed(&self, control: Control) -> bool {
+        // the help takes the keyboard while it's open, except to close it
+        let free = !self.help.shown || (control == Control::Help && self.help.rebinding.is_none());
+        free && self.keys.just_pressed(self.bindings.key(control))
+    }
+}
+
+pub fn key_name(key: KeyCode) -> String {
+    match key {
+        KeyCode::Return => "Enter".to_string(),
+        KeyCode::Back => "Backspace".to_string(),
+        KeyCode::Minus => "-".to_string(),
+        KeyCode::Equals => "=".to_string(),
+        KeyCode::PageUp => "Page Up".to_string(),
+        KeyCode::PageDown => "Page Down".to_string(),
+        KeyCode::Escape => "Esc".to_string(),
+        key => format!("{:?}", key),
+    }
+}
+
+pub struct KeyBindingsPlugin;
+
+fn apply_bindings(settings: Res<Settings>, mut bindings: ResMut<KeyBindings>) {
+    if settings.is_changed() && *bindings != settings.key_bindings {
+        *bindings = settings.key_bindings.clone();
+    }
+}
+
+impl Plugin for KeyBindingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(KeyBindings::default())
+            .add_system(apply_bindings.after("settings"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang::Lang;
+
+    #[test]
+    fn controls_are_described() {
+        let bindings = KeyBindings::default();
+        assert_eq!(bindings.key(Control::Takeback), KeyCode::Back);
+        assert_eq!(bindings.key(Control::Restart), KeyCode::R);
+        assert_eq!(
+            Control::SkipUndoAnimation.lang_key(),
+            "controls.skip_undo_animation"
+        );
+        assert_eq!(bindings.control(KeyCode::R), Some(Control::Restart));
+        assert_eq!(bindings.control(KeyCode::Space), None);
+        let lang = Lang::english();
+        for control in CONTROLS {
+            // an unknown lang key is shown as is
+            assert_ne!(lang.get(&control.lang_key()), control.lang_key());
+            // no key does two things
+            assert_eq!(
+                CONTROLS
+                    .iter()
+                    .filter(|c| c.default_key() == control.default_key())
+                    .count(),
+                1
+            );
+        }
+        assert_eq!(key_name(KeyCode::Back), "Backspace");
+        assert_eq!(key_name(KeyCode::F1), "F1");
+    }
+
+    #[test]
+    fn rebinding_keeps_keys_apart() {
+        let mut bindings = KeyBindings::default();
+        assert_eq!(bindings.rebind(Control::Analysis, KeyCode::Key1), Ok(None));
+        assert_eq!(bindings.key(Control::Analysis), KeyCode::Key1);
+        // taking another control's key gives it the old one
+        assert_eq!(
+            bindings.rebind(Control::Analysis, KeyCode::J),
+            Ok(Some(Control::EvalGraph))
+        );
+        assert_eq!(bindings.key(Control::EvalGraph), KeyCode::Key1);
+        assert_eq!(
+            bindings.rebind(Control::Help, KeyCode::F5),
+            Err(RebindError::Reserved)
+        );
+        assert_eq!(
+            bindings.rebind(Control::Takeback, KeyCode::Escape),
+            Err(RebindError::Reserved)
+        );
+        assert_eq!(
+            bindings.rebind(Control::Takeback, KeyCode::LShift),
+            Err(RebindError::Unbindable)
+        );
+        let mut loaded = KeyBindings::default();
+        for line in bindings.serialize().lines() {
+            let (name, key) = line.trim_start_matches("key.").split_once('=').unwrap();
+            loaded.parse(name, key);
+        }
+        assert_eq!(loaded, bindings);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::settings::Settings;
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+// bundled like the puzzles so they're also there in the browser, english comes first
+pub const LANGUAGES: [(&str, &str); 2] = [
+    ("en", include_str!("../assets/lang/en.txt")),
+    ("fr", include_str!("../assets/lang/fr.txt")),
+];
+
+pub struct Lang {
+    pub code: String,
+    strings: HashMap<String, String>,
+}
+
+impl Lang {
+    fn parse(content: &str) -> HashMap<String, String> {
+        // "key = text" lines, \n is a line break and # starts a comment
+        content
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, text)| (key.trim().to_string(), text.trim().replace("\\n", "\n")))
+            .collect()
+    }
+
+    pub fn new(code: &str) -> Self {
+        // the strings a language doesn't have yet are taken from english
+        let mut strings = Lang::parse(LANGUAGES[0].1);
+        if let Some((_, content)) = LANGUAGES.iter().find(|(lang_code, _)| *lang_code == code) {
+            strings.extend(Lang::parse(content));
+        }
+        Lang {
+            code: code.to_string(),
+            strings,
+        }
+    }
+
+    pub fn english() -> Self {
+        Lang::new(LANGUAGES[0].0)
+    }
+
+    pub fn get(&self, key: &str) -> String {
+        // an unknown key is shown as is, so it's easy to spot
+        self.strings
+            .get(key)
+            .cloned()
+            .unwrap_or_else(|| key.to_string())
+    }
+
+    pub fn format(&self, key: &str, args: &[&dyn Display]) -> String {
+        // each {} is replaced by the next argument, in order
+        let text = self.get(key);
+        let mut parts = text.split("{}");
+        let mut res = parts.next().unwrap_or_default().to_string();
+        let mut args = args.iter();
+        for part in parts {
+            if let Some(arg) = args.next() {
+                res += &arg.to_string();
+            }
+            res += part;
+        }
+        res
+    }
+}
+
+fn update_lang(settings: Res<Settings>, mut lang: ResMut<Lang>) {
+    if settings.is_changed() && lang.code != settings.lang {
+        *lang = Lang::new(&settings.lang);
+    }
+}
+
+pub struct LangPlugin;
+
+impl Plugin for LangPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Lang::english())
+            .add_system(update_lang.after("settings"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn languages_fall_back_to_english() {
+        let english = Lang::english();
+        assert_eq!(english.get("puzzle.wrong"), "Not quite, try again.");
+        assert_eq!(
+            english.format("undos_left", &[&3]),
+            "Undos left: 3".to_string()
+        );
+        assert_eq!(
+            english.format("coin_flip", &[&english.get("side.black")]),
+            "The coin says you are Black.\n"
+        );
+        assert_eq!(english.get("no.such.key"), "no.such.key");
+        let french = Lang::new("fr");
+        assert_eq!(french.get("puzzle.wrong"), "Pas tout à fait, réessaie.");
+        assert_eq!(french.get("alice.win"), english.get("alice.win"));
+        // an unknown language is english
+        assert_eq!(Lang::new("xx").get("alice.win"), english.get("alice.win"));
+        // every string has its english version
+        for (code, content) in LANGUAGES {
+            for key in Lang::parse(content).keys() {
+                assert!(english.strings.contains_key(key), "{} in {}", key, code);
+            }
+        }
+    }
+}
@@ -1,11 +1,37 @@
 mod ai;
+mod analysis;
+#[cfg(not(target_arch = "wasm32"))]
+mod assets;
 mod board;
 mod character;
 mod choss;
+mod debug;
+mod eval_graph;
+mod events;
+mod fen;
+mod frame_rate;
 mod game;
+mod help;
+mod keybindings;
+mod lang;
 mod make_board;
+mod move_log;
+mod notice;
+mod pace;
+mod pgn;
 mod piece;
+mod piece_set;
 mod pos;
+mod puzzle;
+#[cfg(not(target_arch = "wasm32"))]
+mod recovery;
+mod settings;
+#[cfg(not(target_arch = "wasm32"))]
+mod snapshot;
+mod sounds;
+#[cfg(test)]
+mod tactics;
+mod tutorial;
 mod utils;
 use bevy::prelude::*;
 use game::Undoing;
@@ -15,6 +41,7 @@ fn main() {
         .insert_resource(ClearColor(Color::rgb(0.15, 0.15, 0.2)))
         .insert_resource(WindowDescriptor {
             width: 720.,
+            vsync: settings::Settings::load().vsync,
             ..Default::default()
         })
         .add_plugins(DefaultPlugins)
@@ -43,16 +43,19 @@ fn from_backrank(pieces: Vec<Piece>) -> Board {
 }
 
 pub fn standard_board() -> Board {
-    from_backrank(vec![
-        Piece::Rook,
-        Piece::Knight,
-        Piece::Bishop,
-        Piece::Queen,
-        Piece::King,
-        Piece::Bishop,
-        Piece::Knight,
-        Piece::Rook,
-    ])
+    Board {
+        castling: true,
+        ..from_backrank(vec![
+            Piece::Rook,
+            Piece::Knight,
+            Piece::Bishop,
+            Piece::Queen,
+            Piece::King,
+            Piece::Bishop,
+            Piece::Knight,
+            Piece::Rook,
+        ])
+    }
 }
 
 pub fn halved_board() -> Board {
@@ -64,3 +67,212 @@ pub fn halved_board() -> Board {
         Piece::Queen,
     ])
 }
+
+pub fn cylinder_board() -> Board {
+    // the a and e files are neighbours
+    Board {
+        cylinder: true,
+        ..halved_board()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Setup {
+    Halved,
+    Cylinder,
+    Standard,
+    Horde,
+    LightBrigade,
+}
+
+impl Setup {
+    pub fn next(self) -> Self {
+        match self {
+            Setup::Halved => Setup::Cylinder,
+            Setup::Cylinder => Setup::Standard,
+            Setup::Standard => Setup::Horde,
+            Setup::Horde => Setup::LightBrigade,
+            Setup::LightBrigade => Setup::Halved,
+        }
+    }
+
+    pub fn board(self) -> Board {
+        match self {
+            Setup::Halved => halved_board(),
+            Setup::Cylinder => cylinder_board(),
+            Setup::Standard => standard_board(),
+            Setup::Horde => horde_board(),
+            Setup::LightBrigade => light_brigade_board(),
+        }
+    }
+}
+
+// the AI gives up its knights first, then its bishops and its rooks
+const HANDICAP_ORDER: [Piece; 3] = [Piece::Knight, Piece::Bishop, Piece::Rook];
+pub const MAX_HANDICAP: u32 = 3;
+
+pub fn with_handicap(mut board: Board, color: Color, handicap: u32) -> Board {
+    // removes that many pieces from the color's army, the king and queens are always kept
+    let mut removed = Vec::new();
+    for handicap_piece in HANDICAP_ORDER {
+        removed.extend(
+            board
+                .pieces_of(color)
+                .filter(|(_, piece)| *piece == handicap_piece)
+                .map(|(pos, _)| pos),
+        );
+    }
+    for pos in removed.into_iter().take(handicap as usize) {
+        board.set(pos, None);
+    }
+    board
+}
+
+fn pawn(color: Color, status: PawnStatus) -> Piece {
+    Piece::Pawn {
+        orientation: if color == Color::White {
+            Pos(0, -1)
+        } else {
+            Pos(0, 1)
+        },
+        status,
+    }
+}
+
+pub fn horde_board() -> Board {
+    // white gives up its pieces for pawns filling its whole half of the board
+    let mut board = standard_board();
+    for y in 4..8 {
+        // like in the standard game, only the pawns on the first 2 ranks can leap
+        let status = if y >= 6 {
+            PawnStatus::CanLeap
+        } else {
+            PawnStatus::CannotLeap
+        };
+        for x in 0..8 {
+            board.set(Pos(x, y), Some((Color::White, pawn(Color::White, status))));
+        }
+    }
+    board.set(Pos(4, 7), Some((Color::White, Piece::King)));
+    board
+}
+
+pub fn light_brigade_board() -> Board {
+    // 3 queens against 7 knights
+    let mut board = from_backrank(vec![
+        Piece::Knight,
+        Piece::Knight,
+        Piece::Knight,
+        Piece::Knight,
+        Piece::King,
+        Piece::Knight,
+        Piece::Knight,
+        Piece::Knight,
+    ]);
+    for x in 0..8 {
+        board.set(Pos(x, 7), None);
+    }
+    for x in [1, 3, 6] {
+        board.set(Pos(x, 7), Some((Color::White, Piece::Queen)));
+    }
+    board.set(Pos(4, 7), Some((Color::White, Piece::King)));
+    board
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::PieceValues;
+
+    fn count(board: &Board, color: Color, piece_name: &str) -> usize {
+        board
+            .squares
+            .iter()
+            .flatten()
+            .filter(|(p_color, piece)| *p_color == color && piece.name() == piece_name)
+            .count()
+    }
+
+    #[test]
+    fn horde_is_all_pawns_and_a_king() {
+        let board = horde_board();
+        assert_eq!(count(&board, Color::White, "pawn"), 31);
+        assert_eq!(count(&board, Color::White, "king"), 1);
+        assert_eq!(
+            board
+                .squares
+                .iter()
+                .flatten()
+                .filter(|(color, _)| *color == Color::White)
+                .count(),
+            32
+        );
+        assert_eq!(count(&board, Color::Black, "pawn"), 8);
+        // the horde can move, and so can the other side
+        assert!(!board.moves(Color::White, true).is_empty());
+        assert!(!board.moves(Color::Black, true).is_empty());
+    }
+
+    #[test]
+    fn handicap_removes_material() {
+        let value = |board: &Board, color| -> f32 {
+            board
+                .pieces_of(color)
+                .map(|(_, piece)| PieceValues::classical().value(piece))
+                .sum()
+        };
+        let board = halved_board();
+        let handicapped = with_handicap(board.clone(), Color::Black, 1);
+        assert_eq!(count(&handicapped, Color::Black, "knight"), 0);
+        assert_eq!(
+            value(&handicapped, Color::Black),
+            value(&board, Color::Black) - PieceValues::classical().knight
+        );
+        // the player's army is untouched
+        assert_eq!(
+            value(&handicapped, Color::White),
+            value(&board, Color::White)
+        );
+
+        // the standard board has 2 knights to give before the bishops
+        let handicapped = with_handicap(standard_board(), Color::White, 3);
+        assert_eq!(count(&handicapped, Color::White, "knight"), 0);
+        assert_eq!(count(&handicapped, Color::White, "bishop"), 1);
+        assert_eq!(count(&handicapped, Color::White, "queen"), 1);
+        assert!(!handicapped.moves(Color::White, true).is_empty());
+
+        // the light brigade queens are never removed
+        let handicapped = with_handicap(light_brigade_board(), Color::White, MAX_HANDICAP);
+        assert_eq!(count(&handicapped, Color::White, "queen"), 3);
+        assert_eq!(with_handicap(board.clone(), Color::Black, 0), board);
+    }
+
+    #[test]
+    fn setups_are_playable() {
+        let mut setup = Setup::Halved;
+        loop {
+            let board = setup.board();
+            for color in [Color::White, Color::Black] {
+                assert_eq!(count(&board, color, "king"), 1, "{:?}", setup);
+                assert!(!board.is_checked(color), "{:?}", setup);
+            }
+            // pawns must walk toward the other side
+            for (i, square) in board.squares.iter().enumerate() {
+                if let Some((color, Piece::Pawn { orientation, .. })) = square {
+                    let forward = if *color == Color::White { -1 } else { 1 };
+                    assert_eq!(
+                        *orientation,
+                        Pos(0, forward),
+                        "{:?} {:?}",
+                        setup,
+                        board.pos(i)
+                    );
+                }
+            }
+            setup = setup.next();
+            if setup == Setup::Halved {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{
+    choss::{ChossGame, HSIZE},
+    pgn::PgnGame,
+    settings::Settings,
+};
+use bevy::prelude::*;
+
+// the older moves leave the panel, the whole game is in the PGN
+const SHOWN_MOVES: usize = 8;
+
+#[derive(Component)]
+struct MoveLogText;
+
+// the moves played so far in algebraic notation, one full move per entry
+#[derive(Default)]
+pub struct MoveLog(pub Vec<String>);
+
+impl MoveLog {
+    fn text(&self) -> String {
+        self.0[self.0.len().saturating_sub(SHOWN_MOVES)..].join("\n")
+    }
+}
+
+fn setup(mut commands: Commands, server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: server.load("fonts/RobotoMono-Regular.ttf"),
+        font_size: 14.0,
+        color: Color::rgb(0.8, 0.8, 0.8),
+    };
+    let text_alignment = TextAlignment {
+        vertical: VerticalAlign::Center,
+        horizontal: HorizontalAlign::Right,
+    };
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::with_section("", text_style, text_alignment),
+            ..Default::default()
+        })
+        .insert(MoveLogText);
+}
+
+fn update_move_log(pgn: Res<PgnGame>, mut move_log: ResMut<MoveLog>) {
+    // the game record already forgets the undone moves and starts over with each game
+    if pgn.is_changed() {
+        let moves = pgn.numbered_moves();
+        if move_log.0 != moves {
+            move_log.0 = moves;
+        }
+    }
+}
+
+fn display_move_log(
+    mut query_text: Query<(&mut Text, &mut Transform), With<MoveLogText>>,
+    move_log: Res<MoveLog>,
+    choss: Res<ChossGame>,
+    settings: Res<Settings>,
+) {
+    if !(move_log.is_changed() || choss.is_changed() || settings.is_changed()) {
+        return;
+    }
+    if let Ok((mut text, mut transform)) = query_text.get_single_mut() {
+        let content = move_log.text();
+        if text.sections[0].value != content {
+            text.sections[0].value = content;
+        }
+        // left of the board, between the dialogue log and the pace
+        transform.translation = Vec3::new(
+            -HSIZE * choss.board.width as f32 - settings.board_margin,
+            0.,
+            0.,
+        );
+    }
+}
+
+pub struct MoveLogPlugin;
+
+impl Plugin for MoveLogPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(MoveLog::default())
+            .add_startup_system(setup)
+            .add_system(update_move_log.after("play"))
+            .add_system(display_move_log);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::{Action, Color as PieceColor};
+    use crate::pos::Pos;
+
+    #[test]
+    fn shows_the_last_moves() {
+        let mut pgn = PgnGame::new(crate::make_board::halved_board(), PieceColor::White);
+        pgn.push(Pos(2, 6), &[Action::Go(Pos(2, 4))]);
+        pgn.push(Pos(2, 1), &[Action::Go(Pos(2, 3))]);
+        pgn.push(Pos(1, 7), &[Action::Go(Pos(2, 5))]);
+        let mut move_log = MoveLog(pgn.numbered_moves());
+        assert_eq!(move_log.text(), "1. c4 c5\n2. Nc3");
+        // an undo takes the move back out
+        pgn.truncate(2);
+        move_log.0 = pgn.numbered_moves();
+        assert_eq!(move_log.text(), "1. c4 c5");
+        move_log.0 = (1..=20).map(|i| format!("{}. a", i)).collect();
+        assert!(move_log.text().starts_with("13. a\n"));
+        assert!(move_log.text().ends_with("20. a"));
+    }
+}
@@ -0,0 +1,70 @@
+use crate::choss::{ChossGame, HSIZE};
+use bevy::prelude::*;
+
+// in seconds, how long a notice stays above the board
+const NOTICE_TIME: f64 = 3.;
+
+// what the last key press changed, for the keys that have nothing else to show for it
+#[derive(Default)]
+pub struct Notice(pub String);
+
+impl Notice {
+    pub fn show(&mut self, text: impl ToString) {
+        self.0 = text.to_string();
+    }
+}
+
+#[derive(Component)]
+struct NoticeText;
+
+fn setup(mut commands: Commands, server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: server.load("fonts/RobotoMono-Regular.ttf"),
+        font_size: 14.0,
+        color: Color::rgb(0.8, 0.8, 0.8),
+    };
+    let text_alignment = TextAlignment {
+        vertical: VerticalAlign::Bottom,
+        horizontal: HorizontalAlign::Right,
+    };
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::with_section("", text_style, text_alignment),
+            ..Default::default()
+        })
+        .insert(NoticeText);
+}
+
+fn display_notice(
+    mut query_text: Query<(&mut Text, &mut Transform), With<NoticeText>>,
+    notice: Res<Notice>,
+    choss: Res<ChossGame>,
+    time: Res<Time>,
+    mut shown_at: Local<f64>,
+) {
+    let now = time.seconds_since_startup();
+    if let Ok((mut text, mut transform)) = query_text.get_single_mut() {
+        if notice.is_changed() && !notice.is_added() {
+            text.sections[0].value = notice.0.clone();
+            *shown_at = now;
+        } else if !text.sections[0].value.is_empty() && now - *shown_at >= NOTICE_TIME {
+            text.sections[0].value = "".to_string();
+        }
+        // right above the board, across from the undo counter
+        transform.translation = Vec3::new(
+            HSIZE * choss.board.width as f32,
+            HSIZE * choss.board.height as f32 + 8.,
+            0.,
+        );
+    }
+}
+
+pub struct NoticePlugin;
+
+impl Plugin for NoticePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Notice::default())
+            .add_startup_system(setup)
+            .add_system(display_notice);
+    }
+}
@@ -0,0 +1,191 @@
+use crate::{
+    choss::{ChossGame, HSIZE},
+    events::MovePlayed,
+    game::side_key,
+    lang::Lang,
+    piece::Color as PieceColor,
+    settings::Settings,
+};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Component)]
+struct PaceText;
+
+// how long each side takes to move, it's not a clock, nobody loses on time
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct MoveTimes {
+    // the side and seconds of every half move played since the game was set up, in order
+    times: Vec<(PieceColor, f64)>,
+    // the half move being played and when it started
+    #[serde(skip)]
+    turn_start: Option<(u32, f64)>,
+    // the AI's time is how long it searched, not how long it waited to play
+    #[serde(skip)]
+    searched: Option<f64>,
+}
+
+impl MoveTimes {
+    pub fn searched(&mut self, seconds: f64) {
+        self.searched = Some(seconds);
+    }
+
+    fn record(&mut self, halfmove: usize, color: PieceColor, seconds: f64) {
+        // an undone move is forgotten along with its time
+        self.times.truncate(halfmove);
+        self.times.push((color, seconds));
+    }
+
+    fn last(&self, color: PieceColor) -> Option<f64> {
+        self.times
+            .iter()
+            .rev()
+            .find(|(side, _)| *side == color)
+            .map(|(_, seconds)| *seconds)
+    }
+
+    fn average(&self, color: PieceColor) -> Option<f64> {
+        let times: Vec<f64> = self
+            .times
+            .iter()
+            .filter(|(side, _)| *side == color)
+            .map(|(_, seconds)| *seconds)
+            .collect();
+        (!times.is_empty()).then(|| times.iter().sum::<f64>() / times.len() as f64)
+    }
+
+    fn text(&self, lang: &Lang) -> String {
+        [PieceColor::White, PieceColor::Black]
+            .into_iter()
+            .filter_map(|color| {
+                let (last, average) = (self.last(color)?, self.average(color)?);
+                Some(lang.format(
+                    "pace.side",
+                    &[
+                        &lang.get(side_key(color)),
+                        &format!("{:.1}", last),
+                        &format!("{:.1}", average),
+                    ],
+                ))
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    pub fn summary(&self, player: PieceColor, lang: &Lang) -> String {
+        match (self.average(player), self.average(player.next())) {
+            (Some(you), Some(them)) => lang.format(
+                "summary.pace",
+                &[&format!("{:.1}", you), &format!("{:.1}", them)],
+            ),
+            _ => "".to_string(),
+        }
+    }
+}
+
+fn setup(mut commands: Commands, server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: server.load("fonts/RobotoMono-Regular.ttf"),
+        font_size: 14.0,
+        color: Color::rgb(0.8, 0.8, 0.8),
+    };
+    let text_alignment = TextAlignment {
+        vertical: VerticalAlign::Bottom,
+        horizontal: HorizontalAlign::Right,
+    };
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::with_section("", text_style, text_alignment),
+            ..Default::default()
+        })
+        .insert(PaceText);
+}
+
+fn time_moves(
+    mut moves: EventReader<MovePlayed>,
+    mut move_times: ResMut<MoveTimes>,
+    choss: Res<ChossGame>,
+    time: Res<Time>,
+) {
+    let now = time.seconds_since_startup();
+    for played in moves.iter() {
+        if let Some((halfmove, start)) = move_times.turn_start {
+            let seconds = match move_times.searched.take() {
+                Some(searched) if played.color != choss.player => searched,
+                _ => now - start,
+            };
+            move_times.record(halfmove as usize, played.color, seconds);
+        }
+    }
+    // the next turn starts once the move is played, or when going back to an earlier one
+    let halfmove = choss.moves_played();
+    if !matches!(move_times.turn_start, Some((start_halfmove, _)) if start_halfmove == halfmove) {
+        move_times.times.truncate(halfmove as usize);
+        move_times.turn_start = Some((halfmove, now));
+    }
+}
+
+fn display_pace(
+    mut query_text: Query<(&mut Text, &mut Transform), With<PaceText>>,
+    move_times: Res<MoveTimes>,
+    choss: Res<ChossGame>,
+    settings: Res<Settings>,
+    lang: Res<Lang>,
+) {
+    if !(move_times.is_changed() || choss.is_changed() || settings.is_changed()) {
+        return;
+    }
+    if let Ok((mut text, mut transform)) = query_text.get_single_mut() {
+        let content = move_times.text(&lang);
+        if text.sections[0].value != content {
+            text.sections[0].value = content;
+        }
+        // left of the board, under the dialogue log
+        transform.translation = Vec3::new(
+            -HSIZE * choss.board.width as f32 - settings.board_margin,
+            -HSIZE * choss.board.height as f32,
+            0.,
+        );
+    }
+}
+
+pub struct PacePlugin;
+
+impl Plugin for PacePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(MoveTimes::default())
+            .add_startup_system(setup)
+            .add_system(time_moves.after("play"))
+            .add_system(display_pace);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn move_times_per_side() {
+        let mut move_times = MoveTimes::default();
+        assert_eq!(move_times.average(PieceColor::White), None);
+        move_times.record(0, PieceColor::White, 3.);
+        move_times.record(1, PieceColor::Black, 0.5);
+        move_times.record(2, PieceColor::White, 5.);
+        assert_eq!(move_times.last(PieceColor::White), Some(5.));
+        assert_eq!(move_times.average(PieceColor::White), Some(4.));
+        assert_eq!(move_times.average(PieceColor::Black), Some(0.5));
+        let lang = Lang::english();
+        assert_eq!(
+            move_times.text(&lang),
+            "White: 5.0s last, 4.0s on average\nBlack: 0.5s last, 0.5s on average"
+        );
+        assert_eq!(
+            move_times.summary(PieceColor::Black, &lang),
+            "\nTime per move: 0.5s for you, 4.0s for them."
+        );
+        // playing again from an earlier move replaces the times after it
+        move_times.record(1, PieceColor::Black, 2.);
+        assert_eq!(move_times.last(PieceColor::White), Some(3.));
+        assert_eq!(move_times.average(PieceColor::Black), Some(2.));
+    }
+}
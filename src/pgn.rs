@@ -1,33 +1,419 @@
-use crate::piece::{Action, Piece};
+use crate::board::Board;
+use crate::piece::{Action, Color, Piece};
 use crate::pos::Pos;
+use serde::{Deserialize, Deserializer, Serialize};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{fs::OpenOptions, io::Write};
 
-fn piece2pgn(piece: Piece) -> &'static str {
-    match piece {
-        Piece::Pawn {
-            orientation: _,
-            status: _,
-        } => "p",
-        Piece::Knight => "N",
-        Piece::Bishop => "B",
-        Piece::Rook => "R",
-        Piece::Queen => "Q",
-        Piece::King => "K",
-    }
+// every finished game is added at the end
+#[cfg(not(target_arch = "wasm32"))]
+const GAMES_PATH: &str = "games.pgn";
+
+pub fn pos2pgn(pos: Pos, height: usize) -> String {
+    // files go past h on wider boards
+    let file = (b'a' + pos.0 as u8) as char;
+    // white starts at the bottom of the board, on rank 1
+    format!("{}{}", file, height as i32 - pos.1)
 }
 
-fn pos2pgn(pos: Pos) -> String {
-    let letters = ["a", "b", "c", "d", "e", "f", "g", "h"];
-    format!("{}{}", letters[pos.0 as usize], pos.1)
+fn check_suffix(board: &Board, pos: Pos, actions: &[Action]) -> &'static str {
+    // look at the board after the move to know if it gives check or mate
+    let color = match board.get(pos) {
+        Some(Some((color, _))) => *color,
+        _ => return "",
+    };
+    let after = board.play(color, pos, actions);
+    let opponent = color.next();
+    // lines from the search can capture the king, there's nothing to annotate then
+    if after.king_pos(opponent).is_none() || !after.is_checked(opponent) {
+        ""
+    } else if after.moves(opponent, true).is_empty() {
+        "#"
+    } else {
+        "+"
+    }
 }
 
-pub fn move2pgn(pos: Pos, actions: &Vec<Action>) -> String {
+pub fn move2pgn(board: &Board, pos: Pos, actions: &[Action]) -> String {
     let mut res = String::new();
     for action in actions {
         if let Action::Go(go_pos) = action {
-            res += format!("{}{}", pos2pgn(pos), pos2pgn(*go_pos)).as_str();
+            res += format!(
+                "{}{}",
+                pos2pgn(pos, board.height),
+                pos2pgn(*go_pos, board.height)
+            )
+            .as_str();
         } else if let Action::Promotion(piece) = action {
-            res += format!("={}", piece2pgn(*piece)).as_str();
+            res += format!("={}", piece.letter()).as_str();
         }
     }
-    res
+    res + check_suffix(board, pos, actions)
+}
+
+fn destination(pos: Pos, actions: &[Action]) -> Pos {
+    actions.iter().fold(pos, |last_pos, action| match action {
+        Action::Go(go_pos) => *go_pos,
+        _ => last_pos,
+    })
+}
+
+fn disambiguation(board: &Board, color: Color, pos: Pos, piece: Piece, target: Pos) -> String {
+    // the other pieces of the same kind that could go to the same square
+    let others: Vec<Pos> = board
+        .moves(color, true)
+        .into_iter()
+        .filter(|(other_pos, actions)| {
+            *other_pos != pos
+                && destination(*other_pos, actions) == target
+                && matches!(board.get(*other_pos), Some(Some((_, other))) if other.letter() == piece.letter())
+        })
+        .map(|(other_pos, _)| other_pos)
+        .collect();
+    let square = pos2pgn(pos, board.height);
+    let (file, rank) = square.split_at(1);
+    if others.is_empty() {
+        "".to_string()
+    } else if others.iter().all(|other| other.0 != pos.0) {
+        file.to_string()
+    } else if others.iter().all(|other| other.1 != pos.1) {
+        rank.to_string()
+    } else {
+        square
+    }
+}
+
+pub fn move2san(board: &Board, pos: Pos, actions: &[Action]) -> String {
+    let (color, piece) = match board.get(pos) {
+        Some(Some((color, piece))) => (*color, *piece),
+        _ => return move2pgn(board, pos, actions),
+    };
+    let castle = actions.iter().find_map(|action| match action {
+        Action::Castle(rook_pos, _) => Some(*rook_pos),
+        _ => None,
+    });
+    if let Some(rook_pos) = castle {
+        let side = if rook_pos.0 > pos.0 { "O-O" } else { "O-O-O" };
+        return side.to_string() + check_suffix(board, pos, actions);
+    }
+    let target = destination(pos, actions);
+    let capture = if board.is_capture(color, actions) {
+        "x"
+    } else {
+        ""
+    };
+    let mut res = match piece {
+        // a pawn only names its file when it takes
+        Piece::Pawn { .. } if capture.is_empty() => "".to_string(),
+        Piece::Pawn { .. } => pos2pgn(pos, board.height)[..1].to_string(),
+        _ => format!(
+            "{}{}",
+            piece.letter(),
+            disambiguation(board, color, pos, piece, target)
+        ),
+    };
+    res += capture;
+    res += &pos2pgn(target, board.height);
+    for action in actions {
+        if let Action::Promotion(promoted) = action {
+            res += &format!("={}", promoted.letter());
+        }
+    }
+    res + check_suffix(board, pos, actions)
+}
+
+// every annotation a move can get
+const ANNOTATIONS: [&str; 4] = ["??", "?", "?!", "!"];
+
+// named so serde doesn't expect to borrow the annotation from the text it's read from,
+// it's one of the known ones instead
+pub type Annotation = &'static str;
+
+pub fn annotation(eval_loss: f32) -> &'static str {
+    // how much the move lost compared to the engine's expectation, in pawns
+    if eval_loss >= 4. {
+        "??"
+    } else if eval_loss >= 2. {
+        "?"
+    } else if eval_loss >= 1. {
+        "?!"
+    } else if eval_loss <= -2. {
+        // the move was better than what the engine expected
+        "!"
+    } else {
+        ""
+    }
+}
+
+fn known_annotation(text: &str) -> &'static str {
+    ANNOTATIONS
+        .into_iter()
+        .find(|annotation| *annotation == text)
+        .unwrap_or("")
+}
+
+pub fn deserialize_annotation<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Annotation, D::Error> {
+    // the annotations are saved as text, they're read back as the ones the game knows
+    Ok(known_annotation(&String::deserialize(deserializer)?))
+}
+
+fn deserialize_annotations<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<&'static str>, D::Error> {
+    Ok(Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|text| known_annotation(text))
+        .collect())
+}
+
+// a whole game, recorded move by move as it's played
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PgnGame {
+    pub white: String,
+    pub black: String,
+    // "1-0", "0-1", "1/2-1/2", or "*" while the game goes on
+    pub result: String,
+    start: Board,
+    // the side that plays the first recorded move
+    first: Color,
+    moves: Vec<(Pos, Vec<Action>)>,
+    // one per move, "!", "?", ... or empty
+    #[serde(deserialize_with = "deserialize_annotations")]
+    annotations: Vec<&'static str>,
+}
+
+impl PgnGame {
+    pub fn new(start: Board, first: Color) -> Self {
+        PgnGame {
+            white: "?".to_string(),
+            black: "?".to_string(),
+            result: "*".to_string(),
+            start,
+            first,
+            moves: Vec::new(),
+            annotations: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, pos: Pos, actions: &[Action]) {
+        self.moves.push((pos, actions.to_vec()));
+        self.annotations.push("");
+    }
+
+    pub fn annotate_last(&mut self, annotation: &'static str) {
+        if let Some(last) = self.annotations.last_mut() {
+            *last = annotation;
+        }
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        // forget the moves that were undone
+        self.moves.truncate(len);
+        self.annotations.truncate(len);
+    }
+
+    pub fn numbered_moves(&self) -> Vec<String> {
+        // one entry per full move, like "1. c4 c5"
+        let mut res: Vec<String> = Vec::new();
+        let mut board = self.start.clone();
+        let mut color = self.first;
+        if color == Color::Black && !self.moves.is_empty() {
+            res.push("1...".to_string());
+        }
+        // count in half moves as if white had played first
+        let offset = if self.first == Color::White { 0 } else { 1 };
+        for (i, (pos, actions)) in self.moves.iter().enumerate() {
+            let san = move2san(&board, *pos, actions) + self.annotations[i];
+            match res.last_mut() {
+                Some(last) if (i + offset) % 2 == 1 => *last += &format!(" {}", san),
+                _ => res.push(format!("{}. {}", (i + offset) / 2 + 1, san)),
+            }
+            board = board.play(color, *pos, actions);
+            color = color.next();
+        }
+        res
+    }
+
+    pub fn to_pgn(&self) -> String {
+        // no game starts from the usual position, the FEN tells other tools where this one did,
+        // the move numbers start over from it
+        let headers = format!(
+            "[Event \"Choss club\"]\n[White \"{}\"]\n[Black \"{}\"]\n[Result \"{}\"]\n\
+             [SetUp \"1\"]\n[FEN \"{} 0 1\"]\n\n",
+            self.white,
+            self.black,
+            self.result,
+            self.start.to_fen(self.first)
+        );
+        let mut moves = self.numbered_moves();
+        moves.push(self.result.clone());
+        headers + &moves.join(" ") + "\n"
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_game(pgn: &str) {
+    let res = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(GAMES_PATH)
+        // a blank line between the games
+        .and_then(|mut file| writeln!(file, "{}", pgn));
+    if let Err(err) = res {
+        println!("couldn't save the game to {}: {}", GAMES_PATH, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::PawnStatus;
+
+    #[test]
+    fn files_past_h() {
+        assert_eq!(pos2pgn(Pos(0, 3), 8), "a5");
+        assert_eq!(pos2pgn(Pos(11, 3), 8), "l5");
+    }
+
+    #[test]
+    fn ranks_from_the_bottom() {
+        let board = crate::make_board::standard_board();
+        // the white back rank, then the black one
+        assert_eq!(pos2pgn(Pos(4, 7), board.height), "e1");
+        assert_eq!(pos2pgn(Pos(4, 0), board.height), "e8");
+    }
+
+    #[test]
+    fn check_and_mate_suffixes() {
+        let mut board = Board::new(5, 8);
+        board.set(Pos(0, 0), Some((Color::Black, Piece::King)));
+        board.set(Pos(4, 7), Some((Color::White, Piece::King)));
+        board.set(Pos(4, 2), Some((Color::White, Piece::Rook)));
+        board.set(Pos(2, 3), Some((Color::White, Piece::Rook)));
+        // a quiet move
+        assert_eq!(
+            move2pgn(&board, Pos(2, 3), &[Action::Go(Pos(2, 4))]),
+            "c5c4"
+        );
+        // the king can still run to the second row
+        assert_eq!(
+            move2pgn(&board, Pos(2, 3), &[Action::Go(Pos(2, 0))]),
+            "c5c8+"
+        );
+        // the other rook covers the escape row
+        assert_eq!(
+            move2pgn(&board, Pos(4, 2), &[Action::Go(Pos(4, 1))]),
+            "e6e7"
+        );
+        let board = board.play(Color::White, Pos(4, 2), &[Action::Go(Pos(4, 1))]);
+        assert_eq!(
+            move2pgn(&board, Pos(2, 3), &[Action::Go(Pos(2, 0))]),
+            "c5c8#"
+        );
+    }
+
+    #[test]
+    fn queen_mate() {
+        let mut board = Board::new(8, 8);
+        board.set(Pos(0, 0), Some((Color::Black, Piece::King)));
+        board.set(Pos(2, 2), Some((Color::White, Piece::King)));
+        board.set(Pos(1, 5), Some((Color::White, Piece::Queen)));
+        // the king guards the queen
+        assert_eq!(
+            move2san(&board, Pos(1, 5), &[Action::Go(Pos(1, 1))]),
+            "Qb7#"
+        );
+        assert_eq!(move2san(&board, Pos(1, 5), &[Action::Go(Pos(1, 2))]), "Qb6");
+    }
+
+    #[test]
+    fn san_captures_and_promotions() {
+        let mut board = Board::new(8, 8);
+        board.set(Pos(0, 6), Some((Color::Black, Piece::King)));
+        board.set(Pos(7, 7), Some((Color::White, Piece::King)));
+        board.set(Pos(1, 5), Some((Color::White, Piece::Knight)));
+        board.set(Pos(2, 3), Some((Color::Black, Piece::Rook)));
+        assert_eq!(
+            move2san(&board, Pos(1, 5), &[Action::Go(Pos(2, 3))]),
+            "Nxc5"
+        );
+        // a second knight that could take too
+        board.set(Pos(3, 5), Some((Color::White, Piece::Knight)));
+        assert_eq!(
+            move2san(&board, Pos(1, 5), &[Action::Go(Pos(2, 3))]),
+            "Nbxc5"
+        );
+        board.set(
+            Pos(6, 1),
+            Some((
+                Color::White,
+                Piece::Pawn {
+                    orientation: Pos(0, -1),
+                    status: PawnStatus::CannotLeap,
+                },
+            )),
+        );
+        assert_eq!(
+            move2san(
+                &board,
+                Pos(6, 1),
+                &[Action::Go(Pos(6, 0)), Action::Promotion(Piece::Queen)]
+            ),
+            "g8=Q"
+        );
+        assert_eq!(
+            move2san(
+                &board,
+                Pos(6, 1),
+                &[Action::Go(Pos(6, 0)), Action::Promotion(Piece::Knight)]
+            ),
+            "g8=N"
+        );
+    }
+
+    #[test]
+    fn numbered_moves() {
+        let mut pgn = PgnGame::new(crate::make_board::halved_board(), Color::White);
+        pgn.white = "Player".to_string();
+        pgn.black = "Alice".to_string();
+        pgn.push(Pos(2, 6), &[Action::Go(Pos(2, 4))]);
+        pgn.push(Pos(2, 1), &[Action::Go(Pos(2, 3))]);
+        pgn.push(Pos(1, 7), &[Action::Go(Pos(2, 5))]);
+        pgn.annotate_last("?!");
+        pgn.push(Pos(0, 1), &[Action::Go(Pos(0, 2))]);
+        pgn.annotate_last("??");
+        pgn.truncate(3);
+        pgn.result = "1-0".to_string();
+        assert_eq!(
+            pgn.to_pgn(),
+            "[Event \"Choss club\"]\n[White \"Player\"]\n[Black \"Alice\"]\n[Result \"1-0\"]\n\
+             [SetUp \"1\"]\n[FEN \"rnbkq/ppppp/5/5/5/5/PPPPP/RNBKQ w - - 0 1\"]\n\n\
+             1. c4 c5 2. Nc3?! 1-0\n"
+        );
+    }
+
+    #[test]
+    fn black_plays_first() {
+        let board = Board::from_fen("4k/5/5/5/5/5/5/K4 b").unwrap();
+        let mut pgn = PgnGame::new(board, Color::Black);
+        pgn.push(Pos(4, 0), &[Action::Go(Pos(3, 0))]);
+        pgn.push(Pos(0, 7), &[Action::Go(Pos(1, 7))]);
+        let pgn = pgn.to_pgn();
+        assert!(pgn.contains("[FEN \"4k/5/5/5/5/5/5/K4 b - - 0 1\"]\n"));
+        assert!(pgn.ends_with("1... Kd8 2. Kb1 *\n"));
+    }
+
+    #[test]
+    fn annotations_from_eval_loss() {
+        assert_eq!(annotation(0.), "");
+        assert_eq!(annotation(0.5), "");
+        assert_eq!(annotation(1.2), "?!");
+        assert_eq!(annotation(2.), "?");
+        assert_eq!(annotation(3.5), "?");
+        assert_eq!(annotation(9.), "??");
+        assert_eq!(annotation(-1.), "");
+        assert_eq!(annotation(-3.), "!");
+    }
 }
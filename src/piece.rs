@@ -1,9 +1,10 @@
 use crate::board::Board;
 use crate::pos::{Pos, DIAGS, LINES, LOS};
 use itertools::iproduct;
+use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Color {
     White,
     Black,
@@ -18,20 +19,22 @@ impl Color {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum Action {
     Go(Pos),
     Take(Pos),
     Promotion(Piece),
+    // the rook's square and where it lands, the king goes with a Go
+    Castle(Pos, Pos),
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum PawnStatus {
     CanLeap,
     JustLeaped,
     CannotLeap,
 }
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum Piece {
     Pawn {
         orientation: Pos,
@@ -44,16 +47,50 @@ pub enum Piece {
     King,
 }
 
-impl Display for Piece {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let str = match self {
+impl Color {
+    pub fn letter(self) -> char {
+        match self {
+            Color::White => 'w',
+            Color::Black => 'b',
+        }
+    }
+}
+
+impl Piece {
+    // the single source of truth for piece naming,
+    // asset files are named after `name` and notation uses `letter`
+    pub fn name(self) -> &'static str {
+        match self {
             Piece::Pawn {
                 orientation: _,
                 status: _,
-            } => "pawn".to_string(),
-            piece => format!("{:?}", piece).to_lowercase(),
-        };
-        f.write_str(&str)
+            } => "pawn",
+            Piece::Knight => "knight",
+            Piece::Bishop => "bishop",
+            Piece::Rook => "rook",
+            Piece::Queen => "queen",
+            Piece::King => "king",
+        }
+    }
+
+    pub fn letter(self) -> char {
+        match self {
+            Piece::Pawn {
+                orientation: _,
+                status: _,
+            } => 'P',
+            Piece::Knight => 'N',
+            Piece::Bishop => 'B',
+            Piece::Rook => 'R',
+            Piece::Queen => 'Q',
+            Piece::King => 'K',
+        }
+    }
+}
+
+impl Display for Piece {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
     }
 }
 
@@ -73,12 +110,12 @@ fn pawn_promotion(
             }
         }
         if board.get(last_pos + orientation).is_none() {
-            let mut action_q = actions.clone();
-            action_q.push(Action::Promotion(Piece::Queen));
-            let mut action_n = actions.clone();
-            action_n.push(Action::Promotion(Piece::Knight));
-            res_prom.push(action_q);
-            res_prom.push(action_n);
+            // the queen first, the under-promotions only matter in a few positions
+            for piece in [Piece::Queen, Piece::Knight, Piece::Rook, Piece::Bishop] {
+                let mut promotion = actions.clone();
+                promotion.push(Action::Promotion(piece));
+                res_prom.push(promotion);
+            }
         } else {
             res_prom.push(actions.clone())
         }
@@ -90,7 +127,7 @@ fn pawn_takes(board: &Board, pos: Pos, color: Color, orientation: Pos) -> Vec<Ve
     let mut res = Vec::new();
     // Taking moves
     for diag_dir in orientation.neighbors() {
-        let diag_pos = diag_dir + pos;
+        let diag_pos = board.wrap(diag_dir + pos);
         let diag = board.get(diag_pos);
         // if it's a square
         if let Some(square) = diag {
@@ -102,7 +139,7 @@ fn pawn_takes(board: &Board, pos: Pos, color: Color, orientation: Pos) -> Vec<Ve
                 }
             } else {
                 // the square is empty
-                let en_passant_pos = diag_pos + orientation * -1;
+                let en_passant_pos = board.wrap(diag_pos + orientation * -1);
                 // if there's a piece in en passant pos
                 if let Some(Some((other_color, piece))) = board.get(en_passant_pos) {
                     // if it's an opponent
@@ -135,8 +172,8 @@ fn pawn_moves(
 ) -> Vec<Vec<Action>> {
     let mut moves = Vec::new();
     // Non-Taking moves
-    let forward_pos = orientation + pos;
-    let leap_pos = orientation * 2 + pos;
+    let forward_pos = board.wrap(orientation + pos);
+    let leap_pos = board.wrap(orientation * 2 + pos);
     // if there is a free cell forward
     if let Some(None) = board.get(forward_pos) {
         moves.push(vec![Action::Go(forward_pos)]);
@@ -156,6 +193,7 @@ fn pawn_moves(
 fn knight_takes(board: &Board, pos: Pos, color: Color) -> Vec<Vec<Action>> {
     iproduct!([-2, 2], [-1, 1])
         .flat_map(|(long, short)| [Pos(long, short) + pos, Pos(short, long) + pos])
+        .map(|take_pos| board.wrap(take_pos))
         .filter(|take_pos| {
             if let Some(Some((other_color, _))) = board.get(*take_pos) {
                 if color != *other_color {
@@ -171,6 +209,7 @@ fn knight_takes(board: &Board, pos: Pos, color: Color) -> Vec<Vec<Action>> {
 fn knight_moves(board: &Board, pos: Pos, color: Color) -> Vec<Vec<Action>> {
     iproduct!([-2, 2], [-1, 1])
         .flat_map(|(long, short)| [Pos(long, short) + pos, Pos(short, long) + pos])
+        .map(|take_pos| board.wrap(take_pos))
         .filter(|take_pos| {
             if let Some(square) = board.get(*take_pos) {
                 if let Some((other_color, _)) = square {
@@ -191,7 +230,11 @@ fn los_takes(board: &Board, pos: Pos, color: Color, dirs: &[Pos]) -> Vec<Vec<Act
     for dir in dirs {
         let mut curr_pos = pos;
         loop {
-            curr_pos = curr_pos + *dir;
+            curr_pos = board.wrap(curr_pos + *dir);
+            if curr_pos == pos {
+                // on a cylinder board the ray came all the way around
+                break;
+            }
             let line = board.get(curr_pos);
             if let Some(square) = line {
                 if let Some((other_color, _)) = square {
@@ -216,7 +259,11 @@ fn los_moves(board: &Board, pos: Pos, color: Color, dirs: &[Pos]) -> Vec<Vec<Act
     for dir in dirs {
         let mut curr_pos = pos;
         loop {
-            curr_pos = curr_pos + *dir;
+            curr_pos = board.wrap(curr_pos + *dir);
+            if curr_pos == pos {
+                // on a cylinder board the ray came all the way around
+                break;
+            }
             let line = board.get(curr_pos);
             if let Some(square) = line {
                 if let Some((other_color, _)) = square {
@@ -239,9 +286,33 @@ fn los_moves(board: &Board, pos: Pos, color: Color, dirs: &[Pos]) -> Vec<Vec<Act
     moves
 }
 
+fn los_attacks(board: &Board, pos: Pos, dirs: &[Pos]) -> Vec<Pos> {
+    // like los_takes, but the ray attacks the first piece it meets whatever its color
+    let mut attacks = Vec::new();
+    for dir in dirs {
+        let mut curr_pos = pos;
+        loop {
+            curr_pos = board.wrap(curr_pos + *dir);
+            if curr_pos == pos {
+                break;
+            }
+            match board.get(curr_pos) {
+                Some(square) => {
+                    attacks.push(curr_pos);
+                    if square.is_some() {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+    attacks
+}
+
 fn king_takes(board: &Board, pos: Pos, color: Color) -> Vec<Vec<Action>> {
     LOS.iter()
-        .map(|los_dir| *los_dir + pos)
+        .map(|los_dir| board.wrap(*los_dir + pos))
         .filter(|take_pos| {
             if let Some(Some((other_color, _))) = board.get(*take_pos) {
                 if color != *other_color {
@@ -254,11 +325,45 @@ fn king_takes(board: &Board, pos: Pos, color: Color) -> Vec<Vec<Action>> {
         .collect()
 }
 
+fn castle_moves(board: &Board, pos: Pos, color: Color) -> Vec<Vec<Action>> {
+    // the king goes two squares towards a rook of its rank, which lands on the square it crossed,
+    // neither of them can have moved and the king can't be in check or cross an attacked square
+    let mut res = Vec::new();
+    if !board.castling || board.move_count(pos) > 0 {
+        return res;
+    }
+    let attacked = board.attacked_by(color.next());
+    if attacked.contains(&pos) {
+        return res;
+    }
+    for (rook_pos, piece) in board.pieces_of(color) {
+        let distance = rook_pos.0 - pos.0;
+        if piece != Piece::Rook
+            || rook_pos.1 != pos.1
+            || distance.abs() < 3
+            || board.move_count(rook_pos) > 0
+        {
+            continue;
+        }
+        let dir = Pos(distance.signum(), 0);
+        let empty = (1..distance.abs()).all(|i| board.get(pos + dir * i) == Some(&None));
+        let safe = (1..=2).all(|i| !attacked.contains(&(pos + dir * i)));
+        if empty && safe {
+            res.push(vec![
+                Action::Go(pos + dir * 2),
+                Action::Castle(rook_pos, pos + dir),
+            ]);
+        }
+    }
+    res
+}
+
 fn king_moves(board: &Board, pos: Pos, color: Color) -> Vec<Vec<Action>> {
-    // NOTE: we don't do castling because in the game you place your pieces at the start of the match
-    // so it's both useless and inapplicable in our case (also a pain to implement)
-    LOS.iter()
-        .map(|los_dir| *los_dir + pos)
+    // castling is only on for the boards where the pieces start in their classical places,
+    // in the other setups you place your pieces at the start of the match
+    let mut moves: Vec<Vec<Action>> = LOS
+        .iter()
+        .map(|los_dir| board.wrap(*los_dir + pos))
         .filter(|take_pos| {
             if let Some(square) = board.get(*take_pos) {
                 if let Some((other_color, _)) = square {
@@ -271,7 +376,9 @@ fn king_moves(board: &Board, pos: Pos, color: Color) -> Vec<Vec<Action>> {
             return false;
         })
         .map(|take_pos| vec![Action::Go(take_pos)])
-        .collect()
+        .collect();
+    moves.extend(castle_moves(board, pos, color));
+    moves
 }
 
 impl Piece {
@@ -330,6 +437,28 @@ impl Piece {
         }
     }
 
+    pub fn attacks(self, board: &Board, pos: Pos) -> Vec<Pos> {
+        // the squares this piece could take on if an opponent stood there
+        let neighbors: Vec<Pos> = match self {
+            Piece::Pawn {
+                orientation,
+                status: _,
+            } => orientation.neighbors().to_vec(),
+            Piece::Knight => iproduct!([-2, 2], [-1, 1])
+                .flat_map(|(long, short)| [Pos(long, short), Pos(short, long)])
+                .collect(),
+            Piece::Bishop => return los_attacks(board, pos, &DIAGS),
+            Piece::Rook => return los_attacks(board, pos, &LINES),
+            Piece::Queen => return los_attacks(board, pos, &LOS),
+            Piece::King => LOS.to_vec(),
+        };
+        neighbors
+            .into_iter()
+            .map(|dir| board.wrap(dir + pos))
+            .filter(|attacked| board.get(*attacked).is_some())
+            .collect()
+    }
+
     pub fn takes(self, board: &Board, pos: Pos, color: Color) -> Vec<Vec<Action>> {
         match self {
             Piece::Pawn {
@@ -344,3 +473,133 @@ impl Piece {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_pieces() -> Vec<Piece> {
+        vec![
+            Piece::Pawn {
+                orientation: Pos(0, -1),
+                status: PawnStatus::CanLeap,
+            },
+            Piece::Knight,
+            Piece::Bishop,
+            Piece::Rook,
+            Piece::Queen,
+            Piece::King,
+        ]
+    }
+
+    #[test]
+    fn piece_names_and_letters() {
+        let expected = [
+            ("pawn", 'P'),
+            ("knight", 'N'),
+            ("bishop", 'B'),
+            ("rook", 'R'),
+            ("queen", 'Q'),
+            ("king", 'K'),
+        ];
+        for (piece, (name, letter)) in all_pieces().into_iter().zip(expected) {
+            assert_eq!(piece.name(), name);
+            assert_eq!(piece.to_string(), name);
+            assert_eq!(piece.letter(), letter);
+        }
+    }
+
+    fn move_count(piece: Piece, pos: Pos, width: usize, height: usize) -> usize {
+        let mut board = Board::new(width, height);
+        board.set(pos, Some((Color::White, piece)));
+        piece.moves(&board, pos, Color::White).len()
+    }
+
+    #[test]
+    fn moves_on_a_big_board() {
+        // nothing in move generation should assume an 8x8 board
+        assert_eq!(move_count(Piece::Knight, Pos(6, 6), 12, 12), 8);
+        assert_eq!(move_count(Piece::Knight, Pos(11, 11), 12, 12), 2);
+        assert_eq!(move_count(Piece::King, Pos(6, 6), 12, 12), 8);
+        assert_eq!(move_count(Piece::King, Pos(11, 0), 12, 12), 3);
+        assert_eq!(move_count(Piece::Rook, Pos(0, 0), 12, 12), 22);
+        assert_eq!(move_count(Piece::Bishop, Pos(0, 0), 12, 12), 11);
+        assert_eq!(move_count(Piece::Queen, Pos(11, 11), 12, 12), 33);
+        // non square boards too
+        assert_eq!(move_count(Piece::Rook, Pos(11, 0), 12, 5), 15);
+    }
+
+    #[test]
+    fn pawn_promotes_on_a_big_board() {
+        let pawn = Piece::Pawn {
+            orientation: Pos(0, -1),
+            status: PawnStatus::CannotLeap,
+        };
+        let mut board = Board::new(12, 12);
+        board.set(Pos(10, 1), Some((Color::White, pawn)));
+        let moves = pawn.moves(&board, Pos(10, 1), Color::White);
+        assert!(moves
+            .iter()
+            .all(|actions| matches!(actions.last(), Some(Action::Promotion(_)))));
+        // one move per piece the pawn can become
+        assert_eq!(moves.len(), 4);
+        for piece in [Piece::Queen, Piece::Knight, Piece::Rook, Piece::Bishop] {
+            assert!(moves.contains(&vec![Action::Go(Pos(10, 0)), Action::Promotion(piece)]));
+        }
+    }
+
+    #[test]
+    fn kingside_castling() {
+        let mut board = crate::make_board::standard_board();
+        board.set(Pos(5, 7), None);
+        board.set(Pos(6, 7), None);
+        let castle = vec![Action::Go(Pos(6, 7)), Action::Castle(Pos(7, 7), Pos(5, 7))];
+        assert!(Piece::King
+            .moves(&board, Pos(4, 7), Color::White)
+            .contains(&castle));
+        let castled = board.play(Color::White, Pos(4, 7), &castle);
+        assert_eq!(
+            castled.get(Pos(6, 7)),
+            Some(&Some((Color::White, Piece::King)))
+        );
+        assert_eq!(
+            castled.get(Pos(5, 7)),
+            Some(&Some((Color::White, Piece::Rook)))
+        );
+        assert_eq!(castled.get(Pos(7, 7)), Some(&None));
+        // the rook has moved, it can't castle again
+        assert_eq!(castled.move_count(Pos(5, 7)), 1);
+        // not through an attacked square
+        let mut attacked = board.clone();
+        attacked.set(Pos(5, 6), None);
+        attacked.set(Pos(5, 3), Some((Color::Black, Piece::Rook)));
+        assert!(!Piece::King
+            .moves(&attacked, Pos(4, 7), Color::White)
+            .contains(&castle));
+        // nor once the king has moved
+        let moved = board
+            .play(Color::White, Pos(4, 7), &[Action::Go(Pos(5, 7))])
+            .play(Color::White, Pos(5, 7), &[Action::Go(Pos(4, 7))]);
+        assert!(!Piece::King
+            .moves(&moved, Pos(4, 7), Color::White)
+            .contains(&castle));
+        // and only on the boards that play with it
+        assert_eq!(crate::pgn::move2san(&board, Pos(4, 7), &castle), "O-O");
+        let no_castling = Board {
+            castling: false,
+            ..board
+        };
+        assert!(!Piece::King
+            .moves(&no_castling, Pos(4, 7), Color::White)
+            .contains(&castle));
+    }
+
+    #[test]
+    fn pawn_name_ignores_state() {
+        let pawn = Piece::Pawn {
+            orientation: Pos(0, 1),
+            status: PawnStatus::JustLeaped,
+        };
+        assert_eq!(pawn.name(), "pawn");
+    }
+}
@@ -0,0 +1,172 @@
+use crate::choss::piece_tex_name;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::keybindings::{Control, Controls};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::lang::Lang;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::notice::Notice;
+use crate::piece::{Color, PawnStatus, Piece};
+use crate::pos::Pos;
+use crate::settings::Settings;
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::asset::FileAssetIo;
+use bevy::prelude::*;
+#[cfg(not(target_arch = "wasm32"))]
+use std::{fs, path::Path};
+
+// the textures straight in choss_pieces/, the other sets are its subfolders
+pub const DEFAULT_SET: &str = "";
+
+// the set the pieces are drawn with, the one in the settings unless it's incomplete
+pub struct PieceSet(pub String);
+
+pub fn is_set_name(name: &str) -> bool {
+    // a single folder name, so a settings file can't point outside of the pieces folder
+    name.chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+pub fn piece_tex_path(set: &str, piece: &Piece, color: &Color) -> String {
+    let folder = if set == DEFAULT_SET {
+        "choss_pieces".to_string()
+    } else {
+        format!("choss_pieces/{}", set)
+    };
+    format!("{}/{}.png", folder, piece_tex_name(piece, color))
+}
+
+pub fn set_textures(set: &str) -> Vec<String> {
+    // every piece that can be drawn, the orientation of pawns doesn't change their look
+    let pawn = Piece::Pawn {
+        orientation: Pos(0, 1),
+        status: PawnStatus::CanLeap,
+    };
+    let mut res = Vec::new();
+    for piece in [
+        pawn,
+        Piece::Knight,
+        Piece::Bishop,
+        Piece::Rook,
+        Piece::Queen,
+        Piece::King,
+    ] {
+        for color in [Color::White, Color::Black] {
+            res.push(piece_tex_path(set, &piece, &color));
+        }
+    }
+    res
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn missing_textures(assets: &Path, set: &str) -> Vec<String> {
+    set_textures(set)
+        .into_iter()
+        .filter(|path| !assets.join(path).is_file())
+        .collect()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn available_sets(assets: &Path) -> Vec<String> {
+    let mut sets = vec![DEFAULT_SET.to_string()];
+    if let Ok(entries) = fs::read_dir(assets.join("choss_pieces")) {
+        let mut folders: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| is_set_name(name))
+            .collect();
+        folders.sort();
+        sets.extend(folders);
+    }
+    sets
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn select_piece_set(settings: Res<Settings>, mut piece_set: ResMut<PieceSet>) {
+    if !settings.is_changed() || piece_set.0 == settings.piece_set {
+        return;
+    }
+    let missing = missing_textures(
+        &FileAssetIo::get_root_path().join("assets"),
+        &settings.piece_set,
+    );
+    if missing.is_empty() {
+        piece_set.0 = settings.piece_set.clone();
+    } else {
+        println!(
+            "the piece set {} is missing {}, using the default one",
+            settings.piece_set,
+            missing.join(", ")
+        );
+        if piece_set.0 != DEFAULT_SET {
+            piece_set.0 = DEFAULT_SET.to_string();
+        }
+    }
+}
+
+// the assets can't be listed in the browser, the set is trusted to be complete
+#[cfg(target_arch = "wasm32")]
+fn select_piece_set(settings: Res<Settings>, mut piece_set: ResMut<PieceSet>) {
+    if settings.is_changed() && piece_set.0 != settings.piece_set {
+        piece_set.0 = settings.piece_set.clone();
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn cycle_piece_set(
+    controls: Controls,
+    mut settings: ResMut<Settings>,
+    mut notice: ResMut<Notice>,
+    lang: Res<Lang>,
+) {
+    if controls.just_pressed(Control::PieceSet) {
+        let sets = available_sets(&FileAssetIo::get_root_path().join("assets"));
+        let i = sets
+            .iter()
+            .position(|set| *set == settings.piece_set)
+            .map_or(0, |i| i + 1);
+        settings.piece_set = sets[i % sets.len()].clone();
+        let name = if settings.piece_set == DEFAULT_SET {
+            lang.get("notice.default_set")
+        } else {
+            settings.piece_set.clone()
+        };
+        notice.show(lang.format("notice.piece_set", &[&name]));
+    }
+}
+
+pub struct PieceSetPlugin;
+
+impl Plugin for PieceSetPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(PieceSet(DEFAULT_SET.to_string()))
+            .add_system(select_piece_set.after("settings"));
+        #[cfg(not(target_arch = "wasm32"))]
+        app.add_system(cycle_piece_set.before("settings"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn piece_set_paths() {
+        assert_eq!(
+            piece_tex_path(DEFAULT_SET, &Piece::Queen, &Color::Black),
+            "choss_pieces/queen_b.png"
+        );
+        assert_eq!(
+            piece_tex_path("alt", &Piece::Queen, &Color::Black),
+            "choss_pieces/alt/queen_b.png"
+        );
+        assert!(set_textures("alt").contains(&"choss_pieces/alt/pawn_w.png".to_string()));
+        let assets = Path::new("assets");
+        assert_eq!(missing_textures(assets, DEFAULT_SET), Vec::<String>::new());
+        // a set that isn't there misses everything, so the default one is used
+        assert_eq!(missing_textures(assets, "no_such_set").len(), 12);
+        assert_eq!(available_sets(assets)[0], DEFAULT_SET);
+        assert!(is_set_name("alt_2"));
+        assert!(!is_set_name("../fonts"));
+    }
+}
@@ -0,0 +1,105 @@
+use crate::choss::ChossGame;
+use crate::fen::parse_square;
+use crate::piece::Action;
+use crate::pos::Pos;
+
+pub struct Puzzle {
+    pub fen: String,
+    // alternates the player's moves and the opponent's answers
+    pub solution: Vec<String>,
+}
+
+pub fn parse_puzzles(content: &str) -> Vec<Puzzle> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('|'))
+        .map(|(fen, solution)| Puzzle {
+            fen: fen.trim().to_string(),
+            solution: solution.split_whitespace().map(str::to_string).collect(),
+        })
+        .collect()
+}
+
+pub fn parse_move(choss: &ChossGame, notation: &str) -> Option<(Pos, Vec<Action>)> {
+    // coordinates like "d7d8", with "=N" to pick the promotion
+    let (squares, promotion) = match notation.split_once('=') {
+        Some((squares, promotion)) => (squares, promotion.chars().next()),
+        None => (notation, None),
+    };
+    let split = squares
+        .char_indices()
+        .skip(1)
+        .find(|(_, c)| c.is_ascii_lowercase())?
+        .0;
+    let from = parse_square(&choss.board, &squares[..split])?;
+    let to = parse_square(&choss.board, &squares[split..])?;
+    choss
+        .board
+        .moves(choss.turn_color(), true)
+        .into_iter()
+        .find(|(pos, actions)| {
+            *pos == from
+                && actions.contains(&Action::Go(to))
+                && actions.iter().all(|action| match action {
+                    Action::Promotion(piece) => Some(piece.letter()) == promotion,
+                    _ => true,
+                })
+        })
+}
+
+pub fn is_expected(choss: &ChossGame, pos: Pos, actions: &[Action], expected: &str) -> bool {
+    // any move reaching the same position is fine
+    let color = choss.turn_color();
+    match parse_move(choss, expected) {
+        Some((expected_pos, expected_actions)) => {
+            choss.board.play(color, pos, actions).squares
+                == choss
+                    .board
+                    .play(color, expected_pos, &expected_actions)
+                    .squares
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::{Color, Piece};
+
+    fn bundled() -> Vec<Puzzle> {
+        parse_puzzles(include_str!("../assets/puzzles.txt"))
+    }
+
+    #[test]
+    fn bundled_puzzles_are_playable() {
+        let puzzles = bundled();
+        assert!(!puzzles.is_empty());
+        for puzzle in puzzles {
+            let mut choss = ChossGame::from_fen(&puzzle.fen, Color::White).unwrap();
+            for notation in puzzle.solution.iter() {
+                let (pos, actions) = parse_move(&choss, notation)
+                    .unwrap_or_else(|| panic!("{} is illegal in {}", notation, puzzle.fen));
+                choss.play(pos, &actions);
+            }
+        }
+    }
+
+    #[test]
+    fn checks_the_solution() {
+        let puzzle = &bundled()[2];
+        let choss = ChossGame::from_fen(&puzzle.fen, Color::White).unwrap();
+        let knight = vec![Action::Go(Pos(3, 0)), Action::Promotion(Piece::Knight)];
+        let queen = vec![Action::Go(Pos(3, 0)), Action::Promotion(Piece::Queen)];
+        assert!(is_expected(&choss, Pos(3, 1), &knight, &puzzle.solution[0]));
+        assert!(!is_expected(&choss, Pos(3, 1), &queen, &puzzle.solution[0]));
+        assert!(!is_expected(
+            &choss,
+            Pos(0, 7),
+            &[Action::Go(Pos(0, 6))],
+            &puzzle.solution[0]
+        ));
+    }
+}
@@ -0,0 +1,89 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fs;
+
+const RECOVERY_PATH: &str = "recovery.ron";
+// to bump whenever what's saved changes, older files are then ignored
+const RECOVERY_VERSION: u32 = 3;
+
+#[derive(Serialize)]
+struct Recovery<'a, T> {
+    version: u32,
+    game: &'a T,
+}
+
+#[derive(Deserialize)]
+struct RecoveryVersion {
+    version: u32,
+}
+
+#[derive(Deserialize)]
+struct Recovered<T> {
+    game: T,
+}
+
+pub fn to_recovery<T: Serialize>(game: &T) -> Option<String> {
+    let recovery = Recovery {
+        version: RECOVERY_VERSION,
+        game,
+    };
+    ron::to_string(&recovery)
+        .map_err(|err| println!("couldn't save the game: {}", err))
+        .ok()
+}
+
+pub fn from_recovery<T: DeserializeOwned>(content: &str) -> Option<T> {
+    // the version is read first so a file from another version is told apart from a broken one
+    match ron::from_str::<RecoveryVersion>(content) {
+        Ok(RecoveryVersion { version }) if version == RECOVERY_VERSION => {
+            ron::from_str::<Recovered<T>>(content)
+                .map(|recovered| recovered.game)
+                .map_err(|err| println!("ignoring the saved game, it couldn't be read: {}", err))
+                .ok()
+        }
+        Ok(RecoveryVersion { version }) => {
+            println!(
+                "ignoring the saved game, it's from version {} and this is version {}",
+                version, RECOVERY_VERSION
+            );
+            None
+        }
+        Err(err) => {
+            println!("ignoring the saved game, it couldn't be read: {}", err);
+            None
+        }
+    }
+}
+
+pub fn save_recovery(content: &str) {
+    if let Err(err) = fs::write(RECOVERY_PATH, content) {
+        println!("couldn't save the game: {}", err);
+    }
+}
+
+pub fn load_recovery() -> Option<String> {
+    // no file is the usual case, the last game ended properly
+    fs::read_to_string(RECOVERY_PATH).ok()
+}
+
+pub fn clear_recovery() {
+    // there may be nothing to remove
+    let _ = fs::remove_file(RECOVERY_PATH);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovery_versions() {
+        let saved = to_recovery(&vec![1, 2, 3]).unwrap();
+        assert_eq!(from_recovery::<Vec<u32>>(&saved), Some(vec![1, 2, 3]));
+        let old = saved.replace(
+            &format!("version:{}", RECOVERY_VERSION),
+            &format!("version:{}", RECOVERY_VERSION + 1),
+        );
+        assert_ne!(old, saved);
+        assert_eq!(from_recovery::<Vec<u32>>(&old), None);
+        assert_eq!(from_recovery::<Vec<u32>>("garbage"), None);
+    }
+}
@@ -0,0 +1,503 @@
+use crate::ai::{PieceValues, QUIESCENCE_DEPTH};
+use crate::keybindings::{Control, Controls, KeyBindings};
+use crate::lang::{Lang, LANGUAGES};
+use crate::make_board::MAX_HANDICAP;
+use crate::notice::Notice;
+use crate::piece_set::{is_set_name, DEFAULT_SET};
+use bevy::prelude::*;
+use std::fs;
+
+const SETTINGS_PATH: &str = "settings.cfg";
+
+pub struct Palette {
+    pub light_square: Color,
+    pub dark_square: Color,
+    pub move_marker: Color,
+    pub capture_marker: Color,
+    pub promotion_marker: Color,
+    pub last_move: Color,
+    pub check: Color,
+    pub pinned: Color,
+    pub threatened: Color,
+    pub illegal: Color,
+    pub premove: Color,
+    pub pending_move: Color,
+    pub cursor: Color,
+}
+
+impl Palette {
+    pub fn standard() -> Self {
+        Palette {
+            light_square: Color::rgb_u8(200, 200, 200),
+            dark_square: Color::rgb_u8(100, 100, 200),
+            move_marker: Color::rgba(0., 0., 0., 0.5),
+            capture_marker: Color::rgba(0., 0., 0., 0.5),
+            promotion_marker: Color::rgba(0.9, 0.7, 0., 0.7),
+            last_move: Color::rgba(1., 1., 0.3, 0.35),
+            check: Color::rgba(1., 0.1, 0.1, 0.6),
+            pinned: Color::rgba(1., 0.5, 0.5, 0.25),
+            threatened: Color::rgba(1., 0.4, 0., 0.35),
+            illegal: Color::rgba(0.9, 0., 0., 0.5),
+            premove: Color::rgba(0.6, 0.2, 0.8, 0.4),
+            pending_move: Color::rgba(0.1, 0.8, 0.1, 0.4),
+            cursor: Color::rgba(1., 1., 1., 0.4),
+        }
+    }
+
+    pub fn high_contrast() -> Self {
+        // based on the Okabe-Ito palette, which stays distinguishable with the common color blindnesses
+        Palette {
+            light_square: Color::rgb_u8(235, 235, 235),
+            dark_square: Color::rgb_u8(110, 110, 110),
+            move_marker: Color::rgba_u8(0, 114, 178, 230),
+            capture_marker: Color::rgba_u8(213, 94, 0, 230),
+            promotion_marker: Color::rgba_u8(204, 121, 167, 230),
+            last_move: Color::rgba_u8(86, 180, 233, 130),
+            check: Color::rgba_u8(230, 159, 0, 200),
+            pinned: Color::rgba_u8(230, 159, 0, 90),
+            threatened: Color::rgba_u8(213, 94, 0, 110),
+            illegal: Color::rgba_u8(213, 94, 0, 160),
+            premove: Color::rgba_u8(204, 121, 167, 130),
+            pending_move: Color::rgba_u8(0, 158, 115, 130),
+            cursor: Color::rgba_u8(240, 228, 66, 150),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Settings {
+    // a move must be clicked twice (or confirmed with Enter) before being played
+    pub confirm_moves: bool,
+    // hovering a piece explains how it moves
+    pub tutorial: bool,
+    // colorblind friendly colors for the board and highlights
+    pub high_contrast: bool,
+    // seconds to wait after a move or a line of dialogue before the game goes on
+    pub move_delay: f64,
+    // in pawns, how much worse than an equal position the AI considers a draw
+    pub contempt: f32,
+    // repetitions and the fifty moves rule only end the game if a player claims the draw
+    pub claim_draws: bool,
+    // Carl's undos restore the board right away instead of spinning the camera
+    pub skip_undo_animation: bool,
+    // the same seed makes the AI pick the same moves again, a new one is drawn each run if unset
+    pub seed: Option<u64>,
+    // the game is drawn after that many full moves, there's no limit if unset
+    pub move_limit: Option<u32>,
+    // highlights the player's pieces the opponent can take
+    pub show_threats: bool,
+    // how much the AI thinks each piece is worth
+    pub piece_values: PieceValues,
+    // how many captures deep the AI follows exchanges past its search depth
+    pub quiescence_depth: u32,
+    // in milliseconds, the AI searches deeper and deeper for that long instead of
+    // to a depth picked from the material left, if set
+    pub think_time: Option<u64>,
+    // how many pieces the AI gives up at the start of a game, for a gentler match
+    pub handicap: u32,
+    // the language code of the dialogues and texts
+    pub lang: String,
+    // in pixels, the space kept between the board and the dialogue around it
+    pub board_margin: f32,
+    // the subfolder of choss_pieces/ the piece textures are taken from, empty for the default ones
+    pub piece_set: String,
+    // waits for the screen between frames, easier on laptop batteries
+    pub vsync: bool,
+    // frames per second the game doesn't go over, on top of vsync, there's no cap if unset
+    pub fps_cap: Option<u32>,
+    // the key of each control, changed from the help overlay
+    pub key_bindings: KeyBindings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            confirm_moves: false,
+            tutorial: true,
+            high_contrast: false,
+            move_delay: 1.,
+            contempt: 0.5,
+            claim_draws: false,
+            skip_undo_animation: false,
+            seed: None,
+            move_limit: None,
+            show_threats: false,
+            piece_values: PieceValues::classical(),
+            quiescence_depth: QUIESCENCE_DEPTH,
+            think_time: None,
+            handicap: 0,
+            lang: "en".to_string(),
+            board_margin: 16.,
+            piece_set: DEFAULT_SET.to_string(),
+            vsync: true,
+            fps_cap: None,
+            key_bindings: KeyBindings::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn palette(&self) -> Palette {
+        if self.high_contrast {
+            Palette::high_contrast()
+        } else {
+            Palette::standard()
+        }
+    }
+
+    pub fn parse(content: &str) -> Self {
+        // unknown keys and invalid values are ignored so old settings files keep working
+        let mut settings = Settings::default();
+        for line in content.lines() {
+            if let Some((key, value)) = line.split_once('=') {
+                let value = value.trim();
+                match key.trim() {
+                    "confirm_moves" => {
+                        settings.confirm_moves = value.parse().unwrap_or(settings.confirm_moves)
+                    }
+                    "tutorial" => settings.tutorial = value.parse().unwrap_or(settings.tutorial),
+                    "high_contrast" => {
+                        settings.high_contrast = value.parse().unwrap_or(settings.high_contrast)
+                    }
+                    "move_delay" => {
+                        settings.move_delay = value
+                            .parse()
+                            .ok()
+                            .filter(|delay: &f64| *delay >= 0.)
+                            .unwrap_or(settings.move_delay)
+                    }
+                    "contempt" => {
+                        settings.contempt = value
+                            .parse()
+                            .ok()
+                            .filter(|contempt: &f32| contempt.is_finite())
+                            .unwrap_or(settings.contempt)
+                    }
+                    "claim_draws" => {
+                        settings.claim_draws = value.parse().unwrap_or(settings.claim_draws)
+                    }
+                    "skip_undo_animation" => {
+                        settings.skip_undo_animation =
+                            value.parse().unwrap_or(settings.skip_undo_animation)
+                    }
+                    "seed" => settings.seed = value.parse().ok(),
+                    "move_limit" => {
+                        settings.move_limit = value.parse().ok().filter(|limit: &u32| *limit > 0)
+                    }
+                    "show_threats" => {
+                        settings.show_threats = value.parse().unwrap_or(settings.show_threats)
+                    }
+                    "piece_values" => {
+                        settings.piece_values =
+                            parse_piece_values(value).unwrap_or(settings.piece_values)
+                    }
+                    "quiescence_depth" => {
+                        settings.quiescence_depth =
+                            value.parse().unwrap_or(settings.quiescence_depth)
+                    }
+                    "think_time" => {
+                        settings.think_time = value.parse().ok().filter(|millis: &u64| *millis > 0)
+                    }
+                    "handicap" => {
+                        settings.handicap = value
+                            .parse()
+                            .ok()
+                            .filter(|handicap: &u32| *handicap <= MAX_HANDICAP)
+                            .unwrap_or(settings.handicap)
+                    }
+                    "board_margin" => {
+                        settings.board_margin = value
+                            .parse()
+                            .ok()
+                            .filter(|margin: &f32| margin.is_finite() && *margin >= 0.)
+                            .unwrap_or(settings.board_margin)
+                    }
+                    "vsync" => settings.vsync = value.parse().unwrap_or(settings.vsync),
+                    "fps_cap" => settings.fps_cap = value.parse().ok().filter(|cap: &u32| *cap > 0),
+                    "piece_set" if is_set_name(value) => settings.piece_set = value.to_string(),
+                    "lang" if LANGUAGES.iter().any(|(code, _)| *code == value) => {
+                        settings.lang = value.to_string()
+                    }
+                    key if key.starts_with("key.") => settings
+                        .key_bindings
+                        .parse(key.trim_start_matches("key."), value),
+                    _ => {}
+                }
+            }
+        }
+        settings
+    }
+
+    pub fn serialize(&self) -> String {
+        format!(
+            "confirm_moves={}\ntutorial={}\nhigh_contrast={}\nmove_delay={}\ncontempt={}\nclaim_draws={}\nskip_undo_animation={}\nseed={}\nmove_limit={}\nshow_threats={}\npiece_values={}\nquiescence_depth={}\nthink_time={}\nhandicap={}\nlang={}\nboard_margin={}\npiece_set={}\nvsync={}\nfps_cap={}\n",
+            self.confirm_moves,
+            self.tutorial,
+            self.high_contrast,
+            self.move_delay,
+            self.contempt,
+            self.claim_draws,
+            self.skip_undo_animation,
+            // left empty when unset
+            self.seed.map(|seed| seed.to_string()).unwrap_or_default(),
+            self.move_limit
+                .map(|limit| limit.to_string())
+                .unwrap_or_default(),
+            self.show_threats,
+            serialize_piece_values(&self.piece_values),
+            self.quiescence_depth,
+            self.think_time
+                .map(|millis| millis.to_string())
+                .unwrap_or_default(),
+            self.handicap,
+            self.lang,
+            self.board_margin,
+            self.piece_set,
+            self.vsync,
+            self.fps_cap.map(|cap| cap.to_string()).unwrap_or_default()
+        ) + &self.key_bindings.serialize()
+    }
+
+    pub fn load() -> Self {
+        fs::read_to_string(SETTINGS_PATH)
+            .map(|content| Settings::parse(&content))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        // this can't work in the browser, the settings will just last for the session
+        if let Err(err) = fs::write(SETTINGS_PATH, self.serialize()) {
+            println!("couldn't save the settings: {}", err);
+        }
+    }
+}
+
+fn parse_piece_values(value: &str) -> Option<PieceValues> {
+    // a preset name, or custom values for the pawn, knight, bishop, rook and queen
+    match value {
+        "classical" => Some(PieceValues::classical()),
+        "kaufman" => Some(PieceValues::kaufman()),
+        _ => {
+            let values = value
+                .split(',')
+                .map(|value| {
+                    value
+                        .trim()
+                        .parse()
+                        .ok()
+                        .filter(|v: &f32| *v > 0. && v.is_finite())
+                })
+                .collect::<Option<Vec<f32>>>()?;
+            match values[..] {
+                [pawn, knight, bishop, rook, queen] => Some(PieceValues {
+                    pawn,
+                    knight,
+                    bishop,
+                    rook,
+                    queen,
+                }),
+                _ => None,
+            }
+        }
+    }
+}
+
+fn serialize_piece_values(values: &PieceValues) -> String {
+    if *values == PieceValues::classical() {
+        "classical".to_string()
+    } else if *values == PieceValues::kaufman() {
+        "kaufman".to_string()
+    } else {
+        format!(
+            "{},{},{},{},{}",
+            values.pawn, values.knight, values.bishop, values.rook, values.queen
+        )
+    }
+}
+
+pub fn on_off(lang: &Lang, on: bool) -> String {
+    lang.get(if on { "notice.on" } else { "notice.off" })
+}
+
+fn toggle_settings(
+    controls: Controls,
+    mut settings: ResMut<Settings>,
+    mut notice: ResMut<Notice>,
+    lang: Res<Lang>,
+) {
+    if controls.just_pressed(Control::ConfirmMoves) {
+        settings.confirm_moves = !settings.confirm_moves;
+        notice.show(lang.format(
+            "notice.confirm_moves",
+            &[&on_off(&lang, settings.confirm_moves)],
+        ));
+    }
+    if controls.just_pressed(Control::Tutorial) {
+        settings.tutorial = !settings.tutorial;
+        notice.show(lang.format("notice.tutorial", &[&on_off(&lang, settings.tutorial)]));
+    }
+    if controls.just_pressed(Control::HighContrast) {
+        settings.high_contrast = !settings.high_contrast;
+        notice.show(lang.format(
+            "notice.high_contrast",
+            &[&on_off(&lang, settings.high_contrast)],
+        ));
+    }
+    if controls.just_pressed(Control::ClaimDraws) {
+        settings.claim_draws = !settings.claim_draws;
+        notice.show(lang.format(
+            "notice.claim_draws",
+            &[&on_off(&lang, settings.claim_draws)],
+        ));
+    }
+    if controls.just_pressed(Control::SkipUndoAnimation) {
+        settings.skip_undo_animation = !settings.skip_undo_animation;
+        notice.show(lang.format(
+            "notice.skip_undo_animation",
+            &[&on_off(&lang, settings.skip_undo_animation)],
+        ));
+    }
+    if controls.just_pressed(Control::Threats) {
+        settings.show_threats = !settings.show_threats;
+        notice.show(lang.format("notice.threats", &[&on_off(&lang, settings.show_threats)]));
+    }
+    if controls.just_pressed(Control::PieceValues) {
+        // custom values can only be set in the settings file
+        settings.piece_values = if settings.piece_values == PieceValues::classical() {
+            PieceValues::kaufman()
+        } else {
+            PieceValues::classical()
+        };
+        notice.show(lang.format(
+            "notice.piece_values",
+            &[&serialize_piece_values(&settings.piece_values)],
+        ));
+    }
+    if controls.just_pressed(Control::Handicap) {
+        // only applies from the next game
+        settings.handicap = (settings.handicap + 1) % (MAX_HANDICAP + 1);
+        notice.show(lang.format("notice.handicap", &[&settings.handicap]));
+    }
+    if controls.just_pressed(Control::Language) {
+        let i = LANGUAGES
+            .iter()
+            .position(|(code, _)| *code == settings.lang)
+            .unwrap_or(0);
+        settings.lang = LANGUAGES[(i + 1) % LANGUAGES.len()].0.to_string();
+        // already in the new language
+        notice.show(Lang::new(&settings.lang).format("notice.language", &[&settings.lang]));
+    }
+    if controls.just_pressed(Control::FasterMoves) {
+        settings.move_delay = (settings.move_delay - 0.25).max(0.);
+        notice.show(lang.format("notice.move_delay", &[&settings.move_delay]));
+    }
+    if controls.just_pressed(Control::SlowerMoves) {
+        settings.move_delay = (settings.move_delay + 0.25).min(3.);
+        notice.show(lang.format("notice.move_delay", &[&settings.move_delay]));
+    }
+}
+
+fn save_settings(settings: Res<Settings>) {
+    if settings.is_changed() && !settings.is_added() {
+        settings.save();
+    }
+}
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Settings::load())
+            .add_system(toggle_settings.label("settings"))
+            .add_system(save_settings.after("settings"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::help::Help;
+
+    #[test]
+    fn toggles_show_a_notice() {
+        let mut world = World::new();
+        let mut keys = Input::<KeyCode>::default();
+        keys.press(KeyCode::G);
+        world.insert_resource(keys);
+        world.insert_resource(KeyBindings::default());
+        world.insert_resource(Help::default());
+        world.insert_resource(Settings::default());
+        world.insert_resource(Notice::default());
+        world.insert_resource(Lang::english());
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(toggle_settings);
+        stage.run(&mut world);
+        assert_eq!(world.get_resource::<Settings>().unwrap().handicap, 1);
+        // the handicap waits for the next game, the player is told so
+        assert_eq!(
+            world.get_resource::<Notice>().unwrap().0,
+            "Pieces the AI starts without, from the next game: 1"
+        );
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let mut settings = Settings {
+            confirm_moves: true,
+            tutorial: false,
+            high_contrast: true,
+            move_delay: 0.25,
+            contempt: -1.5,
+            claim_draws: true,
+            skip_undo_animation: true,
+            seed: Some(42),
+            move_limit: Some(200),
+            show_threats: true,
+            piece_values: PieceValues::kaufman(),
+            quiescence_depth: 2,
+            think_time: Some(1500),
+            handicap: 2,
+            lang: "fr".to_string(),
+            board_margin: 40.5,
+            piece_set: "alt".to_string(),
+            vsync: false,
+            fps_cap: Some(30),
+            key_bindings: KeyBindings::default(),
+        };
+        settings
+            .key_bindings
+            .rebind(Control::Analysis, KeyCode::J)
+            .unwrap();
+        settings
+            .key_bindings
+            .rebind(Control::Takeback, KeyCode::Delete)
+            .unwrap();
+        assert_eq!(Settings::parse(&settings.serialize()), settings);
+        let settings = Settings {
+            piece_values: PieceValues {
+                knight: 3.5,
+                ..PieceValues::classical()
+            },
+            ..Default::default()
+        };
+        assert_eq!(Settings::parse(&settings.serialize()), settings);
+        assert_eq!(
+            Settings::parse(&Settings::default().serialize()),
+            Settings::default()
+        );
+    }
+
+    #[test]
+    fn settings_ignore_garbage() {
+        let settings = Settings::parse(
+            "high_contrast=yes\nunknown=1\nconfirm_moves = true\nmove_delay=-2\ncontempt=NaN\nseed=-3\nmove_limit=0\nhandicap=9\npiece_values=1,3,3,5\nlang=xx\nboard_margin=-4\nquiescence_depth=-1\nthink_time=0\npiece_set=../fonts\nvsync=maybe\nfps_cap=0\nkey.help=F5\nkey.analysis=LShift\nkey.nothing=A",
+        );
+        assert_eq!(
+            settings,
+            Settings {
+                confirm_moves: true,
+                ..Default::default()
+            }
+        );
+    }
+}
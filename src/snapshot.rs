@@ -0,0 +1,156 @@
+use crate::choss::{board_tex, ChossGame, HSIZE, SIZE};
+use crate::keybindings::{Control, Controls};
+use crate::lang::Lang;
+use crate::notice::Notice;
+use crate::piece_set::{piece_tex_path, PieceSet};
+use crate::settings::{Palette, Settings};
+use bevy::prelude::*;
+use image::{imageops, RgbaImage};
+use std::path::{Path, PathBuf};
+
+const SNAPSHOT_NAME: &str = "position";
+// the same size as the piece sprites
+const PIECE_SIZE: u32 = (SIZE as f32 * 0.8) as u32;
+
+fn load_piece(path: &str) -> Option<RgbaImage> {
+    let piece = image::open(format!("assets/{}", path))
+        .map_err(|err| println!("couldn't load the {} texture: {}", path, err))
+        .ok()?;
+    // the textures are pixel art, they're scaled up without smoothing like on screen
+    Some(imageops::resize(
+        &piece.to_rgba8(),
+        PIECE_SIZE,
+        PIECE_SIZE,
+        imageops::FilterType::Nearest,
+    ))
+}
+
+pub fn snapshot(
+    choss: &ChossGame,
+    palette: &Palette,
+    set: &str,
+    mut load_piece: impl FnMut(&str) -> Option<RgbaImage>,
+) -> Option<RgbaImage> {
+    // the board as it's shown, turned around if the player has Black
+    let board = board_tex(&choss.board, SIZE, palette, choss.flipped());
+    let size = board.texture_descriptor.size;
+    let mut image = RgbaImage::from_raw(size.width, size.height, board.data)?;
+    for (pos, color, piece) in choss.board.pieces() {
+        let piece_image = load_piece(&piece_tex_path(set, &piece, &color))?;
+        // from the world coordinates, centered on the board with y going up, to the image's pixels
+        let center = choss.board_to_world(pos).translation;
+        let x = center.x + HSIZE * choss.board.width as f32 - PIECE_SIZE as f32 / 2.;
+        let y = HSIZE * choss.board.height as f32 - center.y - PIECE_SIZE as f32 / 2.;
+        imageops::overlay(&mut image, &piece_image, x.round() as u32, y.round() as u32);
+    }
+    Some(image)
+}
+
+fn snapshot_path(dir: &Path) -> PathBuf {
+    // position.png, then position-2.png and so on, the earlier pictures are kept
+    (1..)
+        .map(|i| {
+            dir.join(if i == 1 {
+                format!("{}.png", SNAPSHOT_NAME)
+            } else {
+                format!("{}-{}.png", SNAPSHOT_NAME, i)
+            })
+        })
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+fn save_snapshot(
+    controls: Controls,
+    choss: Res<ChossGame>,
+    settings: Res<Settings>,
+    piece_set: Res<PieceSet>,
+    mut notice: ResMut<Notice>,
+    lang: Res<Lang>,
+) {
+    if controls.just_pressed(Control::Snapshot) {
+        let text = match snapshot(&choss, &settings.palette(), &piece_set.0, load_piece) {
+            Some(image) => {
+                let path = snapshot_path(Path::new(""));
+                match image.save(&path) {
+                    Ok(()) => lang.format("notice.snapshot_saved", &[&path.display()]),
+                    Err(err) => lang.format("notice.snapshot_failed", &[&err]),
+                }
+            }
+            None => lang.get("notice.snapshot_not_drawn"),
+        };
+        notice.show(text);
+    }
+}
+
+pub struct SnapshotPlugin;
+
+impl Plugin for SnapshotPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(save_snapshot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::Color;
+    use crate::piece_set::DEFAULT_SET;
+    use crate::pos::Pos;
+    use std::fs;
+
+    #[test]
+    fn snapshots_are_kept() {
+        let dir = std::env::temp_dir().join(format!("undoing-snapshots-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        assert_eq!(snapshot_path(&dir), dir.join("position.png"));
+        fs::write(dir.join("position.png"), "").unwrap();
+        assert_eq!(snapshot_path(&dir), dir.join("position-2.png"));
+        fs::write(dir.join("position-2.png"), "").unwrap();
+        assert_eq!(snapshot_path(&dir), dir.join("position-3.png"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn snapshot_matches_the_screen() {
+        let palette = Palette::standard();
+        let square_pixels = |image: &RgbaImage, x: u32, y: u32| {
+            let mut pixels = Vec::new();
+            for dy in 0..SIZE {
+                for dx in 0..SIZE {
+                    pixels.push(*image.get_pixel(x * SIZE + dx, y * SIZE + dy));
+                }
+            }
+            pixels
+        };
+        for player in [Color::White, Color::Black] {
+            let choss = ChossGame::from_fen("4k/5/5/5/5/5/5/K4 w", player).unwrap();
+            let image = snapshot(&choss, &palette, DEFAULT_SET, load_piece).unwrap();
+            assert_eq!((image.width(), image.height()), (5 * SIZE, 8 * SIZE));
+            let empty = ChossGame {
+                board: crate::board::Board::new(5, 8),
+                ..choss.clone()
+            };
+            let empty = snapshot(&empty, &palette, DEFAULT_SET, load_piece).unwrap();
+            // the white king is in the bottom left corner for White and the top right for Black
+            let (king_x, king_y) = if player == Color::White {
+                (0, 7)
+            } else {
+                (4, 0)
+            };
+            for y in 0..8 {
+                for x in 0..5 {
+                    let has_piece =
+                        (x, y) == (king_x, king_y) || (x, y) == (4 - king_x, 7 - king_y);
+                    assert_eq!(
+                        square_pixels(&image, x, y) != square_pixels(&empty, x, y),
+                        has_piece,
+                        "{:?} {:?}",
+                        player,
+                        Pos(x as i32, y as i32)
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use crate::choss::ChossGame;
+use crate::events::{Check, GameOver, IllegalMove, MovePlayed, PieceCaptured, Promotion};
+use bevy::prelude::*;
+use rand::seq::SliceRandom;
+
+pub struct Sounds {
+    // move and take sounds come in a few variants so they don't get repetitive
+    pub move_piece: Vec<Handle<AudioSource>>,
+    pub take: Vec<Handle<AudioSource>>,
+    pub check: Handle<AudioSource>,
+    pub promote: Handle<AudioSource>,
+    pub illegal: Handle<AudioSource>,
+    pub win: Handle<AudioSource>,
+    pub loss: Handle<AudioSource>,
+    pub draw: Handle<AudioSource>,
+}
+
+impl Sounds {
+    pub fn for_move(
+        &self,
+        is_check: bool,
+        is_take: bool,
+        is_promotion: bool,
+    ) -> Handle<AudioSource> {
+        // only one sound is played per move, the most important information wins
+        if is_check {
+            self.check.clone()
+        } else if is_promotion {
+            self.promote.clone()
+        } else if is_take {
+            Sounds::pick(&self.take)
+        } else {
+            Sounds::pick(&self.move_piece)
+        }
+    }
+
+    fn pick(pool: &[Handle<AudioSource>]) -> Handle<AudioSource> {
+        pool.choose(&mut rand::thread_rng()).unwrap().clone()
+    }
+}
+
+fn load_pool(server: &AssetServer, name: &str) -> Vec<Handle<AudioSource>> {
+    // the base sound, plus every variant found in the folder of the same name
+    // (folders can't be listed in the browser, so there it's just the base sound)
+    let mut pool = vec![server.load(format!("sounds/{}.ogg", name).as_str())];
+    if let Ok(variants) = server.load_folder(format!("sounds/{}", name).as_str()) {
+        pool.extend(variants.into_iter().map(|handle| handle.typed()));
+    }
+    pool
+}
+
+fn load_sounds(mut commands: Commands, server: Res<AssetServer>) {
+    commands.insert_resource(Sounds {
+        move_piece: load_pool(&server, "move"),
+        take: load_pool(&server, "take"),
+        check: server.load("sounds/check.ogg"),
+        promote: server.load("sounds/promote.wav"),
+        illegal: server.load("sounds/illegal.wav"),
+        win: server.load("sounds/win.wav"),
+        loss: server.load("sounds/loss.wav"),
+        draw: server.load("sounds/draw.wav"),
+    });
+}
+
+fn move_sounds(
+    mut moves: EventReader<MovePlayed>,
+    mut captures: EventReader<PieceCaptured>,
+    mut checks: EventReader<Check>,
+    mut promotions: EventReader<Promotion>,
+    sounds: Res<Sounds>,
+    audio: Res<Audio>,
+) {
+    // a move's events are all sent at once, so everything read here is about the same move
+    let is_take = captures.iter().count() > 0;
+    let is_check = checks.iter().count() > 0;
+    let is_promotion = promotions.iter().count() > 0;
+    if moves.iter().count() > 0 {
+        audio.play(sounds.for_move(is_check, is_take, is_promotion));
+    }
+}
+
+fn illegal_move_sounds(
+    mut illegal_moves: EventReader<IllegalMove>,
+    sounds: Res<Sounds>,
+    audio: Res<Audio>,
+) {
+    // a single buzz even if several clicks land in the same frame
+    if illegal_moves.iter().count() > 0 {
+        audio.play(sounds.illegal.clone());
+    }
+}
+
+fn game_over_sounds(
+    mut game_overs: EventReader<GameOver>,
+    choss: Res<ChossGame>,
+    sounds: Res<Sounds>,
+    audio: Res<Audio>,
+) {
+    for game_over in game_overs.iter() {
+        audio.play(match game_over.winner {
+            Some(winner) if winner == choss.player => sounds.win.clone(),
+            Some(_) => sounds.loss.clone(),
+            None => sounds.draw.clone(),
+        });
+    }
+}
+
+pub struct SoundsPlugin;
+
+impl Plugin for SoundsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(load_sounds)
+            .add_system(move_sounds)
+            .add_system(illegal_move_sounds)
+            .add_system(game_over_sounds);
+    }
+}
@@ -0,0 +1,76 @@
+// positions with a single clearly best move, to catch evaluation and search regressions
+// (each keeps a pawn or two around, lone minor pieces are a draw to the search)
+use crate::ai::{negamax, PieceValues, QUIESCENCE_DEPTH};
+use crate::board::Board;
+use crate::choss::WinCondition;
+use crate::piece::{Action, Color, Piece};
+use crate::pos::Pos;
+
+struct Tactic {
+    name: &'static str,
+    fen: &'static str,
+    depth: u32,
+    from: Pos,
+    actions: Vec<Action>,
+}
+
+fn tactics() -> Vec<Tactic> {
+    vec![
+        Tactic {
+            // Ne7+ checks the king on g8 and attacks the rook on c8, which falls after the king moves
+            name: "fork",
+            fen: "2r3k1/7p/8/3N4/8/8/7P/K7 w",
+            depth: 4,
+            from: Pos(3, 3),
+            actions: vec![Action::Go(Pos(4, 1))],
+        },
+        Tactic {
+            // Re1 pins the queen to its king, and the king on f2 guards e1 so Qxe1+ only gets a rook
+            name: "pin",
+            fen: "4k3/p7/4q3/8/8/8/5K1P/R7 w",
+            depth: 4,
+            from: Pos(0, 7),
+            actions: vec![Action::Go(Pos(4, 7))],
+        },
+        Tactic {
+            // Rd8# the king is walled in by its own pawns
+            name: "back rank mate",
+            fen: "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w",
+            depth: 2,
+            from: Pos(3, 7),
+            actions: vec![Action::Go(Pos(3, 0))],
+        },
+        Tactic {
+            // e8=N+ forks the king on g7 and the queen on d6, a new queen would just be traded off
+            name: "underpromotion",
+            fen: "8/4P1kp/3q4/8/8/8/7P/K7 w",
+            depth: 4,
+            from: Pos(4, 1),
+            actions: vec![Action::Go(Pos(4, 0)), Action::Promotion(Piece::Knight)],
+        },
+    ]
+}
+
+#[test]
+fn tactics_are_found() {
+    for tactic in tactics() {
+        let board = Board::from_fen(tactic.fen).unwrap();
+        let (_, pos, actions) = negamax(
+            &board,
+            Color::White,
+            tactic.depth,
+            WinCondition::Checkmate,
+            [0; 2],
+            0.,
+            &PieceValues::classical(),
+            QUIESCENCE_DEPTH,
+        )
+        .remove(0);
+        assert_eq!(
+            (pos, actions),
+            (tactic.from, tactic.actions),
+            "{}",
+            tactic.name
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{
+    choss::{CaptureMarker, ChossGame, HSIZE, SIZE},
+    game::{HoveredSquare, SelectedSquare},
+    lang::Lang,
+    settings::Settings,
+};
+use bevy::prelude::*;
+
+#[derive(Component)]
+struct TutorialText;
+
+#[derive(Component)]
+struct HoverDisplay;
+
+fn setup(mut commands: Commands, server: Res<AssetServer>, choss: Res<ChossGame>) {
+    let text_style = TextStyle {
+        font: server.load("fonts/RobotoMono-Regular.ttf"),
+        font_size: 14.0,
+        color: Color::rgb(0.8, 0.8, 0.8),
+    };
+    let text_alignment = TextAlignment {
+        vertical: VerticalAlign::Top,
+        horizontal: HorizontalAlign::Left,
+    };
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::with_section("", text_style, text_alignment),
+            transform: Transform::from_xyz(
+                -HSIZE * choss.board.width as f32,
+                -HSIZE * choss.board.height as f32 - 8.,
+                0.,
+            ),
+            ..Default::default()
+        })
+        .insert(TutorialText);
+}
+
+fn tutorial(
+    mut commands: Commands,
+    mut query_text: Query<&mut Text, With<TutorialText>>,
+    query_display: Query<Entity, With<HoverDisplay>>,
+    hovered: Res<HoveredSquare>,
+    selected: Res<SelectedSquare>,
+    settings: Res<Settings>,
+    lang: Res<Lang>,
+    choss: Res<ChossGame>,
+    capture_marker: Res<CaptureMarker>,
+    server: Res<AssetServer>,
+) {
+    if !(hovered.is_changed()
+        || selected.is_changed()
+        || settings.is_changed()
+        || lang.is_changed()
+        || choss.is_changed())
+    {
+        return;
+    }
+    for hover_display in query_display.iter() {
+        commands.entity(hover_display).despawn();
+    }
+    let mut description = String::new();
+    if let Some(pos) = hovered.0.filter(|_| settings.tutorial) {
+        if let Some(Some((_, piece))) = choss.board.get(pos) {
+            description = lang.get(&format!("piece.{}", piece.name()));
+            // preview where the piece can go, unless it's already selected
+            if selected.0 != Some(pos) {
+                let mut color = settings.palette().move_marker;
+                color.set_a(0.2);
+                for (target, marker) in choss.move_markers(pos).unwrap_or_default() {
+                    let (texture, size) = if marker.capture {
+                        (capture_marker.0.clone(), SIZE as f32)
+                    } else {
+                        (server.load("circle.png"), SIZE as f32 / 2.5)
+                    };
+                    commands
+                        .spawn_bundle(SpriteBundle {
+                            sprite: Sprite {
+                                color,
+                                custom_size: Some(Vec2::new(size, size)),
+                                ..Default::default()
+                            },
+                            texture,
+                            transform: choss.board_to_world(target),
+                            ..Default::default()
+                        })
+                        .insert(HoverDisplay);
+                }
+            }
+        }
+    }
+    if let Ok(mut text) = query_text.get_single_mut() {
+        if text.sections[0].value != description {
+            text.sections[0].value = description;
+        }
+    }
+}
+
+pub struct TutorialPlugin;
+
+impl Plugin for TutorialPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(setup).add_system(tutorial);
+    }
+}
@@ -21,3 +21,27 @@ pub fn screen_to_world(
     // reduce it to a 2D value
     world_pos.truncate()
 }
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn copy_to_clipboard(text: String) -> Result<(), String> {
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set_text(text))
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn copy_to_clipboard(_text: String) -> Result<(), String> {
+    Err("the clipboard is not available in the browser".to_string())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn paste_from_clipboard() -> Result<String, String> {
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.get_text())
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn paste_from_clipboard() -> Result<String, String> {
+    Err("the clipboard is not available in the browser".to_string())
+}